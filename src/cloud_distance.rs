@@ -0,0 +1,136 @@
+// cloud_distance.rs
+//
+// Cloud-to-cloud (C2C) distances for change detection between two surveys: how far each
+// point of the compared cloud is from the nearest point of the reference cloud.
+use wasm_bindgen::prelude::*;
+
+use crate::octree::{get_point, VERTEX_STRIDE};
+use crate::spatial_index::SpatialIndex;
+
+/// Returns the distance from each point of `compared_points` to the nearest of the
+/// reference points indexed by `index`.
+pub fn cloud_to_cloud_distances(
+    index: &dyn SpatialIndex,
+    reference_points: &[f32],
+    compared_points: &[f32],
+) -> Vec<f32> {
+    (0..compared_points.len() / VERTEX_STRIDE)
+        .map(|point_index| {
+            let point = get_point(compared_points, point_index);
+            index
+                .knn(&point, 1, reference_points)
+                .first()
+                .map_or(f32::INFINITY, |&(_, distance)| distance)
+        })
+        .collect()
+}
+
+/// Distances counted into equal-width bins from the smallest to the largest.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct DistanceHistogram {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub bin_width: f32,
+    counts: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl DistanceHistogram {
+    /// Number of distances in each bin. The last bin includes `max`.
+    #[wasm_bindgen(getter)]
+    pub fn counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+}
+
+/// Bins the finite `distances` into `num_bins` bins.
+pub fn distance_histogram(distances: &[f32], num_bins: usize) -> DistanceHistogram {
+    let finite = || {
+        distances
+            .iter()
+            .copied()
+            .filter(|distance| distance.is_finite())
+    };
+    let num_distances = finite().count();
+    let min = finite().fold(f32::INFINITY, f32::min);
+    let max = finite().fold(f32::NEG_INFINITY, f32::max);
+    let mut histogram = DistanceHistogram {
+        min: if num_distances == 0 { 0.0 } else { min },
+        max: if num_distances == 0 { 0.0 } else { max },
+        mean: finite().sum::<f32>() / num_distances.max(1) as f32,
+        bin_width: 0.0,
+        counts: vec![0; num_bins],
+    };
+    if num_bins == 0 || num_distances == 0 {
+        return histogram;
+    }
+
+    histogram.bin_width = (histogram.max - histogram.min) / num_bins as f32;
+    for distance in finite() {
+        let bin = if histogram.bin_width > 0.0 {
+            ((distance - histogram.min) / histogram.bin_width) as usize
+        } else {
+            0
+        };
+        histogram.counts[bin.min(num_bins - 1)] += 1;
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::{Octree, OctreeConfig};
+
+    // A grid of points on the plane y = 0, 0.05 apart
+    fn grid() -> Vec<f32> {
+        let mut vertex_points = Vec::new();
+        for i in 0..41 {
+            for j in 0..41 {
+                vertex_points.extend_from_slice(&[
+                    -1.0 + i as f32 * 0.05,
+                    0.0,
+                    -1.0 + j as f32 * 0.05,
+                ]);
+                vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+            }
+        }
+        vertex_points
+    }
+
+    #[test]
+    fn measures_how_far_a_raised_patch_moved() {
+        let reference_points = grid();
+        let (octree, _) = Octree::build(&reference_points, OctreeConfig::default());
+        // The same surface, with the points of one corner raised by 0.3
+        let mut compared_points = grid();
+        for vertex in compared_points.chunks_exact_mut(VERTEX_STRIDE) {
+            if vertex[0] > 0.5 && vertex[2] > 0.5 {
+                vertex[1] = 0.3;
+            }
+        }
+
+        let distances = cloud_to_cloud_distances(&octree, &reference_points, &compared_points);
+
+        let num_raised = compared_points
+            .chunks_exact(VERTEX_STRIDE)
+            .filter(|vertex| vertex[1] > 0.0)
+            .count();
+        assert!(distances
+            .iter()
+            .all(|&d| d < 1e-6 || (d - 0.3).abs() < 1e-6));
+        assert_eq!(distances.iter().filter(|&&d| d > 0.1).count(), num_raised);
+
+        let histogram = distance_histogram(&distances, 3);
+        assert_eq!(
+            histogram.counts(),
+            vec![(distances.len() - num_raised) as u32, 0, num_raised as u32]
+        );
+        assert!((histogram.bin_width - 0.1).abs() < 1e-6);
+        assert!((histogram.max - 0.3).abs() < 1e-6);
+        assert!((histogram.mean - 0.3 * num_raised as f32 / distances.len() as f32).abs() < 1e-6);
+        assert_eq!(distance_histogram(&[], 4).counts(), vec![0; 4]);
+    }
+}
@@ -0,0 +1,307 @@
+// clustering.rs
+//
+// Splits the cloud into separate objects. Each point gets a cluster id, with the largest
+// cluster numbered 0, or `NOISE` if it belongs to none.
+use nalgebra_glm::Vec3;
+use wasm_bindgen::prelude::*;
+
+use crate::octree::{get_point, VERTEX_STRIDE};
+use crate::spatial_index::SpatialIndex;
+
+/// The cluster id of points that belong to no cluster.
+pub const NOISE: u32 = u32::MAX;
+
+/// One of the clusterings below with its parameters, as chosen in the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClusterMethod {
+    Euclidean {
+        tolerance: f32,
+        min_cluster_size: usize,
+    },
+    Dbscan {
+        eps: f32,
+        min_points: usize,
+    },
+}
+
+impl ClusterMethod {
+    /// Builds the method called `name`, "euclidean" or "dbscan", from its distance and
+    /// minimum point count.
+    pub fn from_parts(name: &str, distance: f32, min_points: usize) -> Option<Self> {
+        match name {
+            "euclidean" => Some(ClusterMethod::Euclidean {
+                tolerance: distance,
+                min_cluster_size: min_points,
+            }),
+            "dbscan" => Some(ClusterMethod::Dbscan {
+                eps: distance,
+                min_points,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_parts`, as `(name, distance, min_points)`.
+    pub fn parts(&self) -> (&'static str, f32, usize) {
+        match *self {
+            ClusterMethod::Euclidean {
+                tolerance,
+                min_cluster_size,
+            } => ("euclidean", tolerance, min_cluster_size),
+            ClusterMethod::Dbscan { eps, min_points } => ("dbscan", eps, min_points),
+        }
+    }
+
+    pub fn cluster(&self, index: &dyn SpatialIndex, vertex_points: &[f32]) -> Vec<u32> {
+        match *self {
+            ClusterMethod::Euclidean {
+                tolerance,
+                min_cluster_size,
+            } => euclidean_clusters(index, vertex_points, tolerance, min_cluster_size),
+            ClusterMethod::Dbscan { eps, min_points } => {
+                dbscan(index, vertex_points, eps, min_points)
+            }
+        }
+    }
+}
+
+/// Groups points that are joined by a chain of points less than `tolerance` apart.
+/// Groups of fewer than `min_cluster_size` points are left as noise.
+pub fn euclidean_clusters(
+    index: &dyn SpatialIndex,
+    vertex_points: &[f32],
+    tolerance: f32,
+    min_cluster_size: usize,
+) -> Vec<u32> {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    let mut cluster_ids = vec![NOISE; num_points];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut visited = vec![false; num_points];
+
+    for seed in 0..num_points {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+        let mut cluster = vec![seed];
+        let mut next = 0;
+        while next < cluster.len() {
+            let point = get_point(vertex_points, cluster[next]);
+            for neighbour in index.query_sphere(&point, tolerance, vertex_points) {
+                if !visited[neighbour] {
+                    visited[neighbour] = true;
+                    cluster.push(neighbour);
+                }
+            }
+            next += 1;
+        }
+        if cluster.len() >= min_cluster_size {
+            clusters.push(cluster);
+        }
+    }
+
+    number_by_size(&mut clusters, &mut cluster_ids);
+    cluster_ids
+}
+
+/// Density-based clustering: points with at least `min_points` points, themselves
+/// included, within `eps` are core points, and clusters are the core points joined
+/// within `eps` of each other along with the points they reach.
+pub fn dbscan(
+    index: &dyn SpatialIndex,
+    vertex_points: &[f32],
+    eps: f32,
+    min_points: usize,
+) -> Vec<u32> {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    let mut cluster_ids = vec![NOISE; num_points];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut visited = vec![false; num_points];
+    let mut clustered = vec![false; num_points];
+
+    for seed in 0..num_points {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+        let seed_point = get_point(vertex_points, seed);
+        let mut frontier = index.query_sphere(&seed_point, eps, vertex_points);
+        if frontier.len() < min_points {
+            // Noise for now, though a later cluster may still reach it
+            continue;
+        }
+
+        let mut cluster = vec![seed];
+        clustered[seed] = true;
+        while let Some(point_index) = frontier.pop() {
+            if !clustered[point_index] {
+                clustered[point_index] = true;
+                cluster.push(point_index);
+            }
+            if visited[point_index] {
+                continue;
+            }
+            visited[point_index] = true;
+            let point = get_point(vertex_points, point_index);
+            let neighbours = index.query_sphere(&point, eps, vertex_points);
+            if neighbours.len() >= min_points {
+                frontier.extend(neighbours);
+            }
+        }
+        clusters.push(cluster);
+    }
+
+    number_by_size(&mut clusters, &mut cluster_ids);
+    cluster_ids
+}
+
+// Numbers the clusters from the largest down
+fn number_by_size(clusters: &mut [Vec<usize>], cluster_ids: &mut [u32]) {
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.len()));
+    for (cluster_id, cluster) in clusters.iter().enumerate() {
+        for &point_index in cluster {
+            cluster_ids[point_index] = cluster_id as u32;
+        }
+    }
+}
+
+/// The size and extent of one cluster.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct ClusterSummary {
+    pub cluster_id: u32,
+    pub num_points: usize,
+    centroid: Vec3,
+    min: Vec3,
+    max: Vec3,
+}
+
+#[wasm_bindgen]
+impl ClusterSummary {
+    #[wasm_bindgen(getter)]
+    pub fn centroid(&self) -> Vec<f32> {
+        self.centroid.as_slice().to_vec()
+    }
+
+    /// Minimum corner of the cluster's axis-aligned bounding box.
+    #[wasm_bindgen(getter)]
+    pub fn min(&self) -> Vec<f32> {
+        self.min.as_slice().to_vec()
+    }
+
+    /// Maximum corner of the cluster's axis-aligned bounding box.
+    #[wasm_bindgen(getter)]
+    pub fn max(&self) -> Vec<f32> {
+        self.max.as_slice().to_vec()
+    }
+}
+
+/// Summarises each cluster in `cluster_ids`, in order of cluster id. Noise is left out.
+pub fn summarize_clusters(vertex_points: &[f32], cluster_ids: &[u32]) -> Vec<ClusterSummary> {
+    let num_clusters = cluster_ids
+        .iter()
+        .filter(|&&cluster_id| cluster_id != NOISE)
+        .max()
+        .map_or(0, |&max_id| max_id as usize + 1);
+    let mut summaries: Vec<ClusterSummary> = (0..num_clusters)
+        .map(|cluster_id| ClusterSummary {
+            cluster_id: cluster_id as u32,
+            num_points: 0,
+            centroid: Vec3::zeros(),
+            min: Vec3::repeat(f32::INFINITY),
+            max: Vec3::repeat(f32::NEG_INFINITY),
+        })
+        .collect();
+
+    for (point_index, &cluster_id) in cluster_ids.iter().enumerate() {
+        if cluster_id == NOISE {
+            continue;
+        }
+        let point = get_point(vertex_points, point_index);
+        let summary = &mut summaries[cluster_id as usize];
+        summary.num_points += 1;
+        summary.centroid += point;
+        summary.min = summary.min.inf(&point);
+        summary.max = summary.max.sup(&point);
+    }
+    for summary in &mut summaries {
+        summary.centroid /= summary.num_points.max(1) as f32;
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{Octree, OctreeConfig};
+
+    const CENTERS: [[f32; 3]; 3] = [[-0.6, 0.0, 0.0], [0.6, 0.0, 0.0], [0.0, 0.7, 0.3]];
+
+    // Blobs of 600, 400 and 200 points around `CENTERS`, then a few isolated points
+    fn blobs_with_strays() -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(21);
+        let mut vertex_points = Vec::new();
+        for (center, num_points) in CENTERS.iter().zip([600, 400, 200]) {
+            for _ in 0..num_points {
+                let offset = Vec3::new(
+                    rng.gen_range(-0.15..0.15),
+                    rng.gen_range(-0.15..0.15),
+                    rng.gen_range(-0.15..0.15),
+                );
+                let point = Vec3::from_column_slice(center) + offset;
+                vertex_points.extend_from_slice(point.as_slice());
+                vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+            }
+        }
+        for stray in [[0.0, -0.9, 0.9], [0.9, 0.9, -0.9]] {
+            vertex_points.extend_from_slice(&stray);
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    fn check_blobs(vertex_points: &[f32], cluster_ids: &[u32]) {
+        assert!(cluster_ids[..600].iter().all(|&id| id == 0));
+        assert!(cluster_ids[600..1000].iter().all(|&id| id == 1));
+        assert!(cluster_ids[1000..1200].iter().all(|&id| id == 2));
+        assert_eq!(&cluster_ids[1200..], &[NOISE, NOISE]);
+
+        let summaries = summarize_clusters(vertex_points, cluster_ids);
+        assert_eq!(summaries.len(), 3);
+        for (summary, (center, num_points)) in
+            summaries.iter().zip(CENTERS.iter().zip([600, 400, 200]))
+        {
+            assert_eq!(summary.num_points, num_points);
+            assert!(
+                nalgebra_glm::distance(&summary.centroid, &Vec3::from_column_slice(center)) < 0.03
+            );
+            assert!(summary.max.x - summary.min.x <= 0.3);
+        }
+    }
+
+    #[test]
+    fn euclidean_clusters_separate_the_blobs() {
+        let vertex_points = blobs_with_strays();
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+
+        let cluster_ids = euclidean_clusters(&octree, &vertex_points, 0.1, 5);
+
+        check_blobs(&vertex_points, &cluster_ids);
+    }
+
+    #[test]
+    fn dbscan_separates_the_blobs() {
+        let vertex_points = blobs_with_strays();
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+
+        let cluster_ids = dbscan(&octree, &vertex_points, 0.1, 3);
+
+        check_blobs(&vertex_points, &cluster_ids);
+        // Nothing is dense enough when every point needs the whole cloud around it
+        assert!(dbscan(&octree, &vertex_points, 0.1, 5000)
+            .iter()
+            .all(|&id| id == NOISE));
+    }
+}
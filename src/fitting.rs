@@ -0,0 +1,475 @@
+// fitting.rs
+//
+// RANSAC fitting of planes, spheres and cylinders to a set of points. Models are
+// proposed from random minimal samples, the one with the most points within the
+// distance threshold wins, and it is refitted to those inliers.
+use nalgebra_glm::{Mat3, Mat4, Vec3, Vec4};
+use rand::seq::index::sample;
+use rand::Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::normals::fit_plane;
+use crate::octree::{get_point, NORMAL_OFFSET, VERTEX_STRIDE};
+
+/// The shapes that can be fitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Plane,
+    Sphere,
+    /// Needs the point normals from `normals::estimate_normals`.
+    Cylinder,
+}
+
+impl Primitive {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plane" => Some(Primitive::Plane),
+            "sphere" => Some(Primitive::Sphere),
+            "cylinder" => Some(Primitive::Cylinder),
+            _ => None,
+        }
+    }
+
+    // Points needed to propose a model
+    fn sample_size(self) -> usize {
+        match self {
+            Primitive::Plane => 3,
+            Primitive::Sphere => 4,
+            Primitive::Cylinder => 2,
+        }
+    }
+}
+
+/// A fitted primitive. The parameters are:
+/// - plane: a, b, c, d of ax + by + cz + d = 0, with (a, b, c) a unit normal
+/// - sphere: the center x, y, z, then the radius
+/// - cylinder: a point on the axis x, y, z, the unit axis direction x, y, z, then the radius
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PrimitiveFit {
+    /// Root mean square distance of the inliers from the surface.
+    pub rms_error: f32,
+    parameters: Vec<f32>,
+    inliers: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl PrimitiveFit {
+    #[wasm_bindgen(getter)]
+    pub fn parameters(&self) -> Vec<f32> {
+        self.parameters.clone()
+    }
+
+    /// Indices of the points within the threshold of the surface, in ascending order.
+    #[wasm_bindgen(getter)]
+    pub fn inliers(&self) -> Vec<u32> {
+        self.inliers.clone()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Model {
+    Plane {
+        normal: Vec3,
+        offset: f32,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Cylinder {
+        point: Vec3,
+        axis: Vec3,
+        radius: f32,
+    },
+}
+
+impl Model {
+    fn distance(&self, point: &Vec3) -> f32 {
+        match *self {
+            Model::Plane { normal, offset } => (normal.dot(point) + offset).abs(),
+            Model::Sphere { center, radius } => {
+                (nalgebra_glm::distance(point, &center) - radius).abs()
+            }
+            Model::Cylinder {
+                point: on_axis,
+                axis,
+                radius,
+            } => (distance_from_axis(point, &on_axis, &axis) - radius).abs(),
+        }
+    }
+
+    fn parameters(&self) -> Vec<f32> {
+        match *self {
+            Model::Plane { normal, offset } => vec![normal.x, normal.y, normal.z, offset],
+            Model::Sphere { center, radius } => vec![center.x, center.y, center.z, radius],
+            Model::Cylinder {
+                point,
+                axis,
+                radius,
+            } => {
+                vec![point.x, point.y, point.z, axis.x, axis.y, axis.z, radius]
+            }
+        }
+    }
+}
+
+fn distance_from_axis(point: &Vec3, on_axis: &Vec3, axis: &Vec3) -> f32 {
+    let offset = point - on_axis;
+    (offset - axis * offset.dot(axis)).norm()
+}
+
+/// Fits `primitive` to the points at `point_indices`, counting points within `threshold`
+/// of the surface as inliers, over `iterations` random samples. Returns `None` if there
+/// are too few points or no sample gave a model.
+pub fn fit_primitive(
+    vertex_points: &[f32],
+    point_indices: &[u32],
+    primitive: Primitive,
+    threshold: f32,
+    iterations: usize,
+    rng: &mut impl Rng,
+) -> Option<PrimitiveFit> {
+    if point_indices.len() < primitive.sample_size() {
+        return None;
+    }
+
+    let mut best: Option<(Model, usize)> = None;
+    for _ in 0..iterations {
+        let samples: Vec<usize> = sample(rng, point_indices.len(), primitive.sample_size())
+            .iter()
+            .map(|i| point_indices[i] as usize)
+            .collect();
+        let Some(model) = propose_model(vertex_points, primitive, &samples) else {
+            continue;
+        };
+        let num_inliers = point_indices
+            .iter()
+            .filter(|&&i| model.distance(&get_point(vertex_points, i as usize)) <= threshold)
+            .count();
+        if best.map_or(true, |(_, most_inliers)| num_inliers > most_inliers) {
+            best = Some((model, num_inliers));
+        }
+    }
+
+    let (mut model, _) = best?;
+    let mut inliers = find_inliers(vertex_points, point_indices, &model, threshold);
+    // Keep the sampled model if the refit loses points
+    if let Some(refitted) = refit_model(vertex_points, &inliers, &model) {
+        let refitted_inliers = find_inliers(vertex_points, point_indices, &refitted, threshold);
+        if refitted_inliers.len() >= inliers.len() {
+            model = refitted;
+            inliers = refitted_inliers;
+        }
+    }
+
+    let squared_error: f32 = inliers
+        .iter()
+        .map(|&i| {
+            model
+                .distance(&get_point(vertex_points, i as usize))
+                .powi(2)
+        })
+        .sum();
+    Some(PrimitiveFit {
+        rms_error: (squared_error / inliers.len().max(1) as f32).sqrt(),
+        parameters: model.parameters(),
+        inliers,
+    })
+}
+
+fn find_inliers(
+    vertex_points: &[f32],
+    point_indices: &[u32],
+    model: &Model,
+    threshold: f32,
+) -> Vec<u32> {
+    let mut inliers: Vec<u32> = point_indices
+        .iter()
+        .copied()
+        .filter(|&i| model.distance(&get_point(vertex_points, i as usize)) <= threshold)
+        .collect();
+    inliers.sort_unstable();
+    inliers
+}
+
+fn get_normal(vertex_points: &[f32], point_index: usize) -> Vec3 {
+    let offset = point_index * VERTEX_STRIDE + NORMAL_OFFSET;
+    Vec3::from_column_slice(&vertex_points[offset..offset + 3])
+}
+
+fn propose_model(vertex_points: &[f32], primitive: Primitive, samples: &[usize]) -> Option<Model> {
+    let points: Vec<Vec3> = samples
+        .iter()
+        .map(|&i| get_point(vertex_points, i))
+        .collect();
+    match primitive {
+        Primitive::Plane => {
+            let normal = (points[1] - points[0])
+                .cross(&(points[2] - points[0]))
+                .try_normalize(f32::EPSILON)?;
+            Some(Model::Plane {
+                normal,
+                offset: -normal.dot(&points[0]),
+            })
+        }
+        Primitive::Sphere => {
+            // The center is equally far from all four points
+            let rows: Vec<Vec3> = points[1..].iter().map(|p| (p - points[0]) * 2.0).collect();
+            let system = Mat3::from_rows(&[
+                rows[0].transpose(),
+                rows[1].transpose(),
+                rows[2].transpose(),
+            ]);
+            let squared_norm0 = points[0].norm_squared();
+            let rhs = Vec3::new(
+                points[1].norm_squared() - squared_norm0,
+                points[2].norm_squared() - squared_norm0,
+                points[3].norm_squared() - squared_norm0,
+            );
+            let center = system.try_inverse()? * rhs;
+            Some(Model::Sphere {
+                center,
+                radius: nalgebra_glm::distance(&points[0], &center),
+            })
+        }
+        Primitive::Cylinder => {
+            // Both normals are perpendicular to the axis and pass through it
+            let normals: Vec<Vec3> = samples
+                .iter()
+                .map(|&i| get_normal(vertex_points, i))
+                .collect();
+            let axis = normals[0].cross(&normals[1]).try_normalize(1e-3)?;
+            let flatten = |v: &Vec3| v - axis * v.dot(&axis);
+            let (p0, p1) = (flatten(&points[0]), flatten(&points[1]));
+            let (n0, n1) = (flatten(&normals[0]), flatten(&normals[1]));
+
+            // Where the two normal lines cross
+            let w = p0 - p1;
+            let (a, b, c) = (n0.dot(&n0), n0.dot(&n1), n1.dot(&n1));
+            let denominator = a * c - b * b;
+            if denominator.abs() < f32::EPSILON {
+                return None;
+            }
+            let t = (b * n1.dot(&w) - c * n0.dot(&w)) / denominator;
+            let point = p0 + n0 * t;
+            Some(Model::Cylinder {
+                point,
+                axis,
+                radius: nalgebra_glm::distance(&p0, &point),
+            })
+        }
+    }
+}
+
+// Least squares fit of the model's shape to the inliers
+fn refit_model(vertex_points: &[f32], inliers: &[u32], model: &Model) -> Option<Model> {
+    let points: Vec<Vec3> = inliers
+        .iter()
+        .map(|&i| get_point(vertex_points, i as usize))
+        .collect();
+    match *model {
+        Model::Plane { .. } => {
+            let (centroid, normal) = fit_plane(&points)?;
+            Some(Model::Plane {
+                normal,
+                offset: -normal.dot(&centroid),
+            })
+        }
+        Model::Sphere { .. } => {
+            if points.len() < 4 {
+                return None;
+            }
+            // |p|² = 2 p·c + k is linear in the center c and k = r² - |c|². Points are
+            // taken relative to their centroid to keep the system well conditioned
+            let centroid = points.iter().sum::<Vec3>() / points.len() as f32;
+            let mut normal_matrix = Mat4::zeros();
+            let mut rhs = Vec4::zeros();
+            for point in &points {
+                let p = point - centroid;
+                let row = Vec4::new(2.0 * p.x, 2.0 * p.y, 2.0 * p.z, 1.0);
+                normal_matrix += row * row.transpose();
+                rhs += row * p.norm_squared();
+            }
+            let solution = normal_matrix.try_inverse()? * rhs;
+            let center = Vec3::new(solution.x, solution.y, solution.z);
+            let squared_radius = solution.w + center.norm_squared();
+            (squared_radius > 0.0).then(|| Model::Sphere {
+                center: center + centroid,
+                radius: squared_radius.sqrt(),
+            })
+        }
+        Model::Cylinder { point, axis, .. } => {
+            let radius = points
+                .iter()
+                .map(|p| distance_from_axis(p, &point, &axis))
+                .sum::<f32>()
+                / points.len().max(1) as f32;
+            Some(Model::Cylinder {
+                point,
+                axis,
+                radius,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const NUM_SURFACE_POINTS: usize = 800;
+
+    fn push_vertex(vertex_points: &mut Vec<f32>, point: Vec3, normal: Vec3) {
+        vertex_points.extend_from_slice(point.as_slice());
+        vertex_points.extend_from_slice(&[0.0; 3]);
+        vertex_points.extend_from_slice(normal.as_slice());
+    }
+
+    // `NUM_SURFACE_POINTS` points from `surface`, jittered by up to `noise`, then 200 points
+    // scattered through the cube around the origin
+    fn with_clutter(
+        surface: impl Fn(&mut rand::rngs::StdRng) -> (Vec3, Vec3),
+        noise: f32,
+    ) -> (Vec<f32>, Vec<u32>) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(22);
+        let mut vertex_points = Vec::new();
+        for _ in 0..NUM_SURFACE_POINTS {
+            let (point, normal) = surface(&mut rng);
+            let jitter = rng.gen_range(-noise..=noise);
+            push_vertex(&mut vertex_points, point + normal * jitter, normal);
+        }
+        for _ in 0..200 {
+            let point = Vec3::new(
+                rng.gen_range(-2.0..2.0),
+                rng.gen_range(-2.0..2.0),
+                rng.gen_range(-2.0..2.0),
+            );
+            push_vertex(&mut vertex_points, point, Vec3::zeros());
+        }
+        let point_indices = (0..(vertex_points.len() / VERTEX_STRIDE) as u32).collect();
+        (vertex_points, point_indices)
+    }
+
+    fn surface_inliers(fit: &PrimitiveFit) -> usize {
+        fit.inliers
+            .iter()
+            .filter(|&&i| (i as usize) < NUM_SURFACE_POINTS)
+            .count()
+    }
+
+    #[test]
+    fn fits_a_noisy_plane_among_clutter() {
+        let normal = Vec3::new(0.2, 1.0, -0.3).normalize();
+        let u = normal.cross(&Vec3::x()).normalize();
+        let v = normal.cross(&u);
+        let origin = Vec3::new(0.0, 0.5, 0.0);
+        let (vertex_points, point_indices) = with_clutter(
+            |rng| {
+                (
+                    origin + u * rng.gen_range(-1.5..1.5) + v * rng.gen_range(-1.5..1.5),
+                    normal,
+                )
+            },
+            0.005,
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        let fit = fit_primitive(
+            &vertex_points,
+            &point_indices,
+            Primitive::Plane,
+            0.02,
+            200,
+            &mut rng,
+        )
+        .unwrap();
+
+        let fitted_normal = Vec3::from_column_slice(&fit.parameters[..3]);
+        let flip = fitted_normal.dot(&normal).signum();
+        assert!(fitted_normal.dot(&normal).abs() > 0.999);
+        assert!((fit.parameters[3] * flip + normal.dot(&origin)).abs() < 0.01);
+        assert_eq!(surface_inliers(&fit), NUM_SURFACE_POINTS);
+        assert!(fit.inliers.len() < NUM_SURFACE_POINTS + 20);
+        assert!(fit.rms_error < 0.005);
+    }
+
+    #[test]
+    fn fits_a_sphere_and_a_cylinder() {
+        let center = Vec3::new(0.3, -0.2, 0.1);
+        let (vertex_points, point_indices) = with_clutter(
+            |rng| {
+                let direction = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .normalize();
+                (center + direction * 0.8, direction)
+            },
+            0.005,
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let fit = fit_primitive(
+            &vertex_points,
+            &point_indices,
+            Primitive::Sphere,
+            0.02,
+            200,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(
+            nalgebra_glm::distance(&Vec3::from_column_slice(&fit.parameters[..3]), &center) < 0.01
+        );
+        assert!((fit.parameters[3] - 0.8).abs() < 0.01);
+        assert_eq!(surface_inliers(&fit), NUM_SURFACE_POINTS);
+
+        let axis = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let u = axis.cross(&Vec3::y()).normalize();
+        let v = axis.cross(&u);
+        let (vertex_points, point_indices) = with_clutter(
+            |rng| {
+                let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+                let radial = u * angle.cos() + v * angle.sin();
+                (
+                    center + axis * rng.gen_range(-1.5..1.5) + radial * 0.5,
+                    radial,
+                )
+            },
+            0.005,
+        );
+        let fit = fit_primitive(
+            &vertex_points,
+            &point_indices,
+            Primitive::Cylinder,
+            0.02,
+            200,
+            &mut rng,
+        )
+        .unwrap();
+        let fitted_axis = Vec3::from_column_slice(&fit.parameters[3..6]);
+        assert!(fitted_axis.dot(&axis).abs() > 0.999);
+        assert!(
+            distance_from_axis(
+                &center,
+                &Vec3::from_column_slice(&fit.parameters[..3]),
+                &fitted_axis
+            ) < 0.01
+        );
+        assert!((fit.parameters[6] - 0.5).abs() < 0.01);
+        assert_eq!(surface_inliers(&fit), NUM_SURFACE_POINTS);
+
+        assert!(fit_primitive(
+            &vertex_points,
+            &point_indices[..1],
+            Primitive::Cylinder,
+            0.02,
+            10,
+            &mut rng
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,156 @@
+// frustum.rs
+use nalgebra_glm::{Mat4, Vec3};
+
+/// A plane `normal · p + distance = 0`. Points with a non-negative signed distance are
+/// on the inner side.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    pub fn new(normal: Vec3, distance: f32) -> Self {
+        // Normalizing keeps `signed_distance` in world units
+        let length = normal.magnitude();
+        Plane {
+            normal: normal / length,
+            distance: distance / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: &Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// How a bounding box relates to a convex region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Containment {
+    Inside,
+    Outside,
+    Intersecting,
+}
+
+/// The six clipping planes of a camera, facing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a combined projection * model-view matrix, so they are
+    /// expressed in the same space as the point positions.
+    pub fn from_matrix(mvp: &Mat4) -> Self {
+        let row = |i: usize| mvp.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let plane = |v: nalgebra_glm::Vec4| Plane::new(v.xyz(), v.w);
+
+        Frustum {
+            planes: [
+                plane(w + x), // Left
+                plane(w - x), // Right
+                plane(w + y), // Bottom
+                plane(w - y), // Top
+                plane(w + z), // Near
+                plane(w - z), // Far
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Classifies the axis-aligned box `[min, max]` against the frustum. The test is
+    /// conservative: a box reported as `Intersecting` may still lie just outside a corner.
+    pub fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        classify_aabb(&self.planes, min, max)
+    }
+}
+
+/// Classifies the axis-aligned box `[min, max]` against the intersection of the inner
+/// sides of `planes`, with the same conservative corner handling as `Frustum`.
+pub fn classify_aabb(planes: &[Plane], min: &Vec3, max: &Vec3) -> Containment {
+    let mut containment = Containment::Inside;
+    for plane in planes {
+        // The corners furthest along and against the plane normal
+        let positive = Vec3::new(
+            if plane.normal.x >= 0.0 { max.x } else { min.x },
+            if plane.normal.y >= 0.0 { max.y } else { min.y },
+            if plane.normal.z >= 0.0 { max.z } else { min.z },
+        );
+        let negative = Vec3::new(
+            if plane.normal.x >= 0.0 { min.x } else { max.x },
+            if plane.normal.y >= 0.0 { min.y } else { max.y },
+            if plane.normal.z >= 0.0 { min.z } else { max.z },
+        );
+
+        if plane.signed_distance(&positive) < 0.0 {
+            return Containment::Outside;
+        }
+        if plane.signed_distance(&negative) < 0.0 {
+            containment = Containment::Intersecting;
+        }
+    }
+    containment
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn camera_frustum() -> Frustum {
+        let mv_matrix = nalgebra_glm::look_at(
+            &Vec3::new(1.0, 2.0, 3.0),
+            &Vec3::zeros(),
+            &Vec3::new(0.0, 1.0, 0.0),
+        );
+        let p_matrix = nalgebra_glm::perspective(1.5, 0.8, 0.5, 6.0);
+        Frustum::from_matrix(&(p_matrix * mv_matrix))
+    }
+
+    #[test]
+    fn classify_aabb_agrees_with_the_points_of_the_box() {
+        let frustum = camera_frustum();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let mut seen = [false; 3];
+        for _ in 0..500 {
+            let min = Vec3::new(
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+            );
+            let max = min + Vec3::repeat(rng.gen_range(0.05..1.0));
+            // The corners and a grid of points through the box
+            let samples: Vec<Vec3> = (0..125)
+                .map(|i| {
+                    let t = Vec3::new(
+                        (i % 5) as f32 / 4.0,
+                        (i / 5 % 5) as f32 / 4.0,
+                        (i / 25) as f32 / 4.0,
+                    );
+                    min + (max - min).component_mul(&t)
+                })
+                .collect();
+            let num_inside = samples
+                .iter()
+                .filter(|point| frustum.contains_point(point))
+                .count();
+
+            let containment = frustum.classify_aabb(&min, &max);
+            match containment {
+                Containment::Inside => assert_eq!(num_inside, samples.len()),
+                Containment::Outside => assert_eq!(num_inside, 0),
+                // Conservative: a box near a corner may be intersecting with no point inside
+                Containment::Intersecting => assert!(num_inside < samples.len()),
+            }
+            seen[containment as usize] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+}
@@ -0,0 +1,313 @@
+// ground.rs
+//
+// Separates ground from the objects standing on it with a progressive morphological
+// filter (Zhang et al., 2003). The lowest point of each cell of a horizontal grid gives a
+// rough surface, which is opened with growing windows; each opening removes objects
+// narrower than the window, and points that stand too far above the opened surface are
+// not ground. Elevation is measured along y, the scene's up axis.
+use crate::octree::{compute_bounds, get_point, VERTEX_STRIDE};
+use crate::selection::Selection;
+
+/// Class of points that are not ground, using the LAS classification codes.
+pub const UNCLASSIFIED: u8 = 1;
+/// Class of ground points, using the LAS classification codes.
+pub const GROUND: u8 = 2;
+
+// Largest elevation grid classified, which a cell size far too small for the cloud's
+// extent would otherwise make
+const MAX_GRID_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GroundFilterConfig {
+    /// Width of the grid cells.
+    pub cell_size: f32,
+    /// Width of the largest window, which should be wider than the largest building.
+    pub max_window_size: f32,
+    /// Steepest ground slope expected, as rise over run.
+    pub slope: f32,
+    /// Height above the surface a point can be and still be ground with the smallest window.
+    pub initial_distance: f32,
+    /// Cap on that height as the windows grow.
+    pub max_distance: f32,
+}
+
+impl Default for GroundFilterConfig {
+    fn default() -> Self {
+        GroundFilterConfig {
+            cell_size: 0.05,
+            max_window_size: 0.5,
+            slope: 0.3,
+            initial_distance: 0.02,
+            max_distance: 0.5,
+        }
+    }
+}
+
+// Lowest elevation in each cell of a grid over x and z
+struct ElevationGrid {
+    min_x: f32,
+    min_z: f32,
+    cell_size: f32,
+    num_columns: usize,
+    num_rows: usize,
+    elevations: Vec<f32>,
+}
+
+impl ElevationGrid {
+    fn cell(&self, x: f32, z: f32) -> usize {
+        let column = (((x - self.min_x) / self.cell_size) as usize).min(self.num_columns - 1);
+        let row = (((z - self.min_z) / self.cell_size) as usize).min(self.num_rows - 1);
+        row * self.num_columns + column
+    }
+
+    // Empty cells take the elevation of the nearest filled cell, spreading out a ring at
+    // a time
+    fn fill_empty_cells(&mut self) {
+        let mut frontier: Vec<usize> = (0..self.elevations.len())
+            .filter(|&cell| self.elevations[cell].is_finite())
+            .collect();
+        while !frontier.is_empty() {
+            let mut next = Vec::new();
+            for cell in frontier {
+                let (row, column) = (cell / self.num_columns, cell % self.num_columns);
+                let neighbours = [
+                    (row > 0).then(|| cell - self.num_columns),
+                    (row + 1 < self.num_rows).then(|| cell + self.num_columns),
+                    (column > 0).then(|| cell - 1),
+                    (column + 1 < self.num_columns).then(|| cell + 1),
+                ];
+                for neighbour in neighbours.into_iter().flatten() {
+                    if !self.elevations[neighbour].is_finite() {
+                        self.elevations[neighbour] = self.elevations[cell];
+                        next.push(neighbour);
+                    }
+                }
+            }
+            frontier = next;
+        }
+    }
+
+    // Erosion then dilation over a square window `2 * half_width + 1` cells across
+    fn open(&self, half_width: usize) -> Vec<f32> {
+        let eroded = self.filter(&self.elevations, half_width, f32::min);
+        self.filter(&eroded, half_width, f32::max)
+    }
+
+    // A square window filter, applied along the rows and then the columns
+    fn filter(&self, values: &[f32], half_width: usize, op: fn(f32, f32) -> f32) -> Vec<f32> {
+        let (num_columns, num_rows) = (self.num_columns, self.num_rows);
+        let mut along_rows = vec![0.0; values.len()];
+        for row in 0..num_rows {
+            for column in 0..num_columns {
+                let first = column.saturating_sub(half_width);
+                let last = (column + half_width).min(num_columns - 1);
+                let row_values = &values[row * num_columns..(row + 1) * num_columns];
+                along_rows[row * num_columns + column] =
+                    row_values[first..=last].iter().copied().reduce(op).unwrap();
+            }
+        }
+        let mut filtered = vec![0.0; values.len()];
+        for row in 0..num_rows {
+            let first = row.saturating_sub(half_width);
+            let last = (row + half_width).min(num_rows - 1);
+            for column in 0..num_columns {
+                filtered[row * num_columns + column] = (first..=last)
+                    .map(|r| along_rows[r * num_columns + column])
+                    .reduce(op)
+                    .unwrap();
+            }
+        }
+        filtered
+    }
+}
+
+/// Classifies every point as `GROUND` or `UNCLASSIFIED`. Fails if the cell size, window
+/// size or slope can't be used, or if the grid would have more than `MAX_GRID_CELLS`
+/// cells.
+pub fn classify_ground(
+    vertex_points: &[f32],
+    config: &GroundFilterConfig,
+) -> Result<Vec<u8>, String> {
+    if !(config.cell_size.is_finite() && config.cell_size > 0.0) {
+        return Err(format!("Invalid cell size: {}", config.cell_size));
+    }
+    if !(config.max_window_size.is_finite() && config.max_window_size >= 0.0) {
+        return Err(format!("Invalid window size: {}", config.max_window_size));
+    }
+    if !(config.slope.is_finite() && config.slope >= 0.0) {
+        return Err(format!("Invalid slope: {}", config.slope));
+    }
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    let Some((min, max)) = compute_bounds(vertex_points) else {
+        return Ok(Vec::new());
+    };
+
+    let num_columns = ((max.x - min.x) / config.cell_size) as usize + 1;
+    let num_rows = ((max.z - min.z) / config.cell_size) as usize + 1;
+    if num_columns.saturating_mul(num_rows) > MAX_GRID_CELLS {
+        return Err(format!(
+            "A cell size of {} needs a {} by {} grid, more than {} cells",
+            config.cell_size, num_columns, num_rows, MAX_GRID_CELLS
+        ));
+    }
+    let mut grid = ElevationGrid {
+        min_x: min.x,
+        min_z: min.z,
+        cell_size: config.cell_size,
+        num_columns,
+        num_rows,
+        elevations: vec![f32::INFINITY; num_columns * num_rows],
+    };
+    let point_cells: Vec<usize> = (0..num_points)
+        .map(|point_index| {
+            let point = get_point(vertex_points, point_index);
+            let cell = grid.cell(point.x, point.z);
+            grid.elevations[cell] = grid.elevations[cell].min(point.y);
+            cell
+        })
+        .collect();
+    grid.fill_empty_cells();
+
+    let mut classes = vec![GROUND; num_points];
+    let mut half_width = 1;
+    let mut previous_window_size = 0.0;
+    loop {
+        let window_size = (2 * half_width + 1) as f32 * config.cell_size;
+        if window_size > config.max_window_size && half_width > 1 {
+            break;
+        }
+        let height_threshold = if previous_window_size == 0.0 {
+            config.initial_distance
+        } else {
+            (config.slope * (window_size - previous_window_size) + config.initial_distance)
+                .min(config.max_distance)
+        };
+
+        let surface = grid.open(half_width);
+        for (point_index, &cell) in point_cells.iter().enumerate() {
+            let height = get_point(vertex_points, point_index).y - surface[cell];
+            if height > height_threshold {
+                classes[point_index] = UNCLASSIFIED;
+            }
+        }
+        grid.elevations = surface;
+
+        previous_window_size = window_size;
+        half_width *= 2;
+    }
+    Ok(classes)
+}
+
+/// The points of `class`.
+pub fn class_selection(classes: &[u8], class: u8) -> Selection {
+    let point_indices = classes
+        .iter()
+        .enumerate()
+        .filter(|&(_, &point_class)| point_class == class)
+        .map(|(point_index, _)| point_index as u32);
+    Selection::from_indices(classes.len(), point_indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn push_point(vertex_points: &mut Vec<f32>, point: Vec3) {
+        vertex_points.extend_from_slice(point.as_slice());
+        vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+    }
+
+    fn ground_height(x: f32, z: f32) -> f32 {
+        0.15 * x + 0.05 * (3.0 * z).sin() - 0.5
+    }
+
+    #[test]
+    fn separates_buildings_and_trees_from_sloping_ground() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(23);
+        let mut vertex_points = Vec::new();
+        for _ in 0..6000 {
+            let (x, z) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let noise = rng.gen_range(-0.005..0.005);
+            push_point(
+                &mut vertex_points,
+                Vec3::new(x, ground_height(x, z) + noise, z),
+            );
+        }
+        let num_ground_points = vertex_points.len() / VERTEX_STRIDE;
+
+        // A flat-roofed building 0.3 across and 0.4 tall, with walls
+        for _ in 0..1500 {
+            let (x, z) = (rng.gen_range(0.2..0.5), rng.gen_range(-0.4..-0.1));
+            let roof = ground_height(0.5, -0.1) + 0.4;
+            let y = if rng.gen_bool(0.6) {
+                roof
+            } else {
+                rng.gen_range(roof - 0.3..roof)
+            };
+            push_point(&mut vertex_points, Vec3::new(x, y, z));
+        }
+        // Trees: narrow canopies of scattered points
+        for center in [Vec3::new(-0.6, 0.0, 0.5), Vec3::new(0.6, 0.0, 0.6)] {
+            for _ in 0..300 {
+                let offset = Vec3::new(
+                    rng.gen_range(-0.08..0.08),
+                    rng.gen_range(0.15..0.35),
+                    rng.gen_range(-0.08..0.08),
+                );
+                let base = Vec3::new(center.x, ground_height(center.x, center.z), center.z);
+                push_point(&mut vertex_points, base + offset);
+            }
+        }
+
+        let classes = classify_ground(&vertex_points, &GroundFilterConfig::default()).unwrap();
+
+        let ground = class_selection(&classes, GROUND);
+        let misclassified_ground = num_ground_points
+            - ground
+                .indices()
+                .filter(|&i| (i as usize) < num_ground_points)
+                .count();
+        assert!(misclassified_ground < num_ground_points / 100);
+        assert!((num_ground_points..classes.len()).all(|i| classes[i] == UNCLASSIFIED));
+    }
+
+    #[test]
+    fn rejects_unusable_parameters() {
+        let mut vertex_points = Vec::new();
+        push_point(&mut vertex_points, Vec3::new(-1.0, 0.0, -1.0));
+        push_point(&mut vertex_points, Vec3::new(1.0, 0.0, 1.0));
+        let default = GroundFilterConfig::default();
+
+        for cell_size in [0.0, -0.05, f32::NAN, f32::INFINITY] {
+            let config = GroundFilterConfig {
+                cell_size,
+                ..default
+            };
+            assert!(classify_ground(&vertex_points, &config).is_err());
+        }
+        for slope in [-0.3, f32::NAN] {
+            let config = GroundFilterConfig { slope, ..default };
+            assert!(classify_ground(&vertex_points, &config).is_err());
+        }
+        let config = GroundFilterConfig {
+            max_window_size: f32::INFINITY,
+            ..default
+        };
+        assert!(classify_ground(&vertex_points, &config).is_err());
+
+        // A 2 by 2 cloud in cells 0.0001 across would need 4e8 cells
+        let config = GroundFilterConfig {
+            cell_size: 0.0001,
+            ..default
+        };
+        assert!(classify_ground(&vertex_points, &config).is_err());
+        assert_eq!(
+            classify_ground(&vertex_points, &default),
+            Ok(vec![GROUND, GROUND])
+        );
+        assert_eq!(classify_ground(&[], &default), Ok(Vec::new()));
+    }
+}
@@ -5,10 +5,29 @@ use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 use web_sys::WebGl2RenderingContext;
 
 use crate::{
-    matrix::MVMatrixValues,
+    cloud_distance::distance_histogram,
+    clustering::{summarize_clusters, ClusterMethod},
+    fitting::{fit_primitive, Primitive},
+    ground::{class_selection, classify_ground, GroundFilterConfig, GROUND, UNCLASSIFIED},
+    matrix::{
+        create_model_view_matrix, create_projection_matrix, unproject_to_ray, MVMatrixValues,
+    },
+    mouse::{MouseState, SelectionTool},
+    normals::set_normals,
+    octree::{get_point, VERTEX_STRIDE},
+    outliers::OutlierFilter,
+    region::FilterShape,
+    registration::{transform_points, IcpConfig, IcpMethod},
+    screen_selection::ScreenPolygon,
+    selection::{Selection, SelectionOp},
+    spatial_index::SpatialIndexKind,
     vertex_buffer::{
-        create_draggable_point_vbo, create_point_ebo, create_sphere_vbo, create_vertex_buffers, generate_sphere_vertices, VertexData
+        align_second_cloud, compute_second_cloud_distances, create_draggable_point_vbo,
+        create_sphere_vbo, create_vertex_buffers, delete_points, generate_filter_shape_vertices,
+        set_second_cloud, start_scene_build, update_point_ebo, update_point_vbo, update_scalar_vbo,
+        ColorMode, VertexData,
     },
+    worker_pool::SpatialWorkers,
 };
 
 pub fn get_num_points_from_html() -> Result<i32, JsValue> {
@@ -27,6 +46,21 @@ pub fn get_num_points_from_html() -> Result<i32, JsValue> {
         .map_err(|_| JsValue::from_str("Invalid number of points"))
 }
 
+pub fn get_spatial_index_kind_from_html() -> Result<SpatialIndexKind, JsValue> {
+    let window = web_sys::window().ok_or("No global `window` exists")?;
+    let document = window
+        .document()
+        .ok_or("Should have a document on window")?;
+    let spatial_index_select = document
+        .get_element_by_id("spatial-index")
+        .ok_or("Can't find spatial-index select element")?;
+    let spatial_index_name = spatial_index_select
+        .dyn_into::<web_sys::HtmlSelectElement>()?
+        .value();
+    SpatialIndexKind::from_name(&spatial_index_name)
+        .ok_or_else(|| JsValue::from_str("Invalid spatial index"))
+}
+
 pub fn create_slider_handler(
     mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
 ) -> Closure<dyn FnMut(web_sys::Event)> {
@@ -90,14 +124,15 @@ pub fn add_wheel_event_listener(wheel_handler: Closure<dyn FnMut(web_sys::WheelE
 pub fn create_num_points_handler(
     gl: Rc<WebGl2RenderingContext>,
     vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
 ) -> Closure<dyn FnMut(web_sys::Event)> {
     Closure::wrap(Box::new(move |event: web_sys::Event| {
         let target = event.target().unwrap();
         let input = target.dyn_ref::<web_sys::HtmlInputElement>().unwrap();
         let num_points = input.value().parse::<u32>().unwrap();
 
-
-        let new_vertex_data = match create_vertex_buffers(&gl, num_points) {
+        let spatial_index_kind = vertex_data_ref.borrow().spatial_index_kind;
+        let new_vertex_data = match create_vertex_buffers(&gl, num_points, spatial_index_kind) {
             Ok(data) => data,
             Err(err) => {
                 eprintln!("Error creating vertex buffers: {:?}", err);
@@ -105,12 +140,23 @@ pub fn create_num_points_handler(
             }
         };
 
-        let mut vertex_data = vertex_data_ref.borrow_mut();
-        vertex_data.point_vbo = new_vertex_data.point_vbo;
-        vertex_data.point_ebo = new_vertex_data.point_ebo;
-        vertex_data.cube_vbo = new_vertex_data.cube_vbo;
-        vertex_data.octree = new_vertex_data.octree;
-        vertex_data.num_points = num_points;
+        {
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            vertex_data.point_vbo = new_vertex_data.point_vbo;
+            vertex_data.point_ebo = new_vertex_data.point_ebo;
+            vertex_data.cube_vbo = new_vertex_data.cube_vbo;
+            vertex_data.octree = new_vertex_data.octree;
+            vertex_data.point_vertices = new_vertex_data.point_vertices;
+            vertex_data.num_points = num_points;
+            vertex_data.selected_points = None;
+            vertex_data.filter_base = None;
+            vertex_data.num_highlighted_points = 0;
+            vertex_data.culled_mvp = None;
+            vertex_data.cluster_ids = None;
+            vertex_data.classes = None;
+            update_scalar_vbo(&gl, &vertex_data);
+        }
+        start_scene_build(gl.clone(), vertex_data_ref.clone(), spatial_workers.clone());
     }) as Box<dyn FnMut(_)>)
 }
 
@@ -133,6 +179,8 @@ pub fn add_num_points_event_listener(num_points_handler: Closure<dyn FnMut(web_s
 pub fn create_xyz_handler(
     gl: Rc<WebGl2RenderingContext>,
     vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+    mouse_state: Rc<RefCell<MouseState>>,
 ) -> Closure<dyn FnMut()> {
     Closure::wrap(Box::new(move || {
         let window = web_sys::window().expect("No global window exists");
@@ -152,6 +200,20 @@ pub fn create_xyz_handler(
         let y = get_input_value("draggable-point-y");
         let z = get_input_value("draggable-point-z");
         let radius = get_input_value("draggable-point-radius");
+        let end = Vec3::new(
+            get_input_value("filter-end-x"),
+            get_input_value("filter-end-y"),
+            get_input_value("filter-end-z"),
+        );
+        let shape_name = document
+            .get_element_by_id("filter-shape")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+            .map_or_else(|| "sphere".to_string(), |select| select.value());
+        let Some(shape) = FilterShape::from_parts(&shape_name, Vec3::new(x, y, z), end, radius)
+        else {
+            web_sys::console::error_1(&JsValue::from_str("Invalid filter shape"));
+            return;
+        };
 
         let draggable_point_vertex = [x, y, z];
 
@@ -164,7 +226,7 @@ pub fn create_xyz_handler(
             }
         };
 
-        let (sphere_vertices, num_sphere_vertices) = generate_sphere_vertices(&[x, y, z], radius);
+        let (sphere_vertices, num_sphere_vertices) = generate_filter_shape_vertices(&shape);
 
         let sphere_buffer = match create_sphere_vbo(&gl, &sphere_vertices) {
             Ok(buffer) => buffer,
@@ -174,26 +236,28 @@ pub fn create_xyz_handler(
             }
         };
 
-        let mut vertex_data = vertex_data_ref.borrow_mut();
-        vertex_data.draggable_point_vbo = draggable_point_buffer;
-        vertex_data.sphere_radius = radius;
-        vertex_data.sphere_vbo = sphere_buffer;
-        vertex_data.num_sphere_vertices = num_sphere_vertices;
-
-        // Update the point EBO based on the sphere radius
-        let octree = &vertex_data.octree;
-        let points_within_sphere = octree.query_sphere(&Vec3::new(x, y, z), radius, &vertex_data.point_vertices);
-        let point_indices: Vec<u32> = points_within_sphere.iter().map(|&i| i as u32).collect();
-        vertex_data.num_points = point_indices.len() as u32;
-
-        let point_ebo = match create_point_ebo(&gl, &point_indices) {
-            Ok(buffer) => buffer,
-            Err(err) => {
-                eprintln!("Error creating point EBO: {:?}", err);
-                return;
-            }
-        };
-        vertex_data.point_ebo = point_ebo;
+        {
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            vertex_data.draggable_point_vbo = draggable_point_buffer;
+            vertex_data.sphere_radius = radius;
+            vertex_data.sphere_vbo = sphere_buffer;
+            vertex_data.num_sphere_vertices = num_sphere_vertices;
+        }
+
+        // Combine the points within the shape with the selection once the workers reply;
+        // the renderer rebuilds the point EBO from the selection on the next frame
+        let op = mouse_state.borrow().selection_op();
+        let vertex_data_ref = vertex_data_ref.clone();
+        spatial_workers.query_shape(&shape, move |result| {
+            let point_indices = match result {
+                Ok(point_indices) => point_indices,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            apply_filter_selection(&mut vertex_data_ref.borrow_mut(), op, point_indices);
+        });
     }) as Box<dyn FnMut()>)
 }
 
@@ -206,6 +270,9 @@ pub fn add_xyz_event_listener(xyz_handler: Closure<dyn FnMut()>) {
         "draggable-point-y",
         "draggable-point-z",
         "draggable-point-radius",
+        "filter-end-x",
+        "filter-end-y",
+        "filter-end-z",
     ];
 
     for input_id in input_ids.iter() {
@@ -219,5 +286,989 @@ pub fn add_xyz_event_listener(xyz_handler: Closure<dyn FnMut()>) {
             .unwrap();
     }
 
+    let filter_shape_select = document
+        .get_element_by_id("filter-shape")
+        .expect("Can't find filter-shape select element");
+    filter_shape_select
+        .add_event_listener_with_callback("change", xyz_handler.as_ref().unchecked_ref())
+        .unwrap();
+
     xyz_handler.forget();
-}
\ No newline at end of file
+}
+pub fn create_pick_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+    scale_factor_ref: Rc<RefCell<f32>>,
+    mouse_state: Rc<RefCell<MouseState>>,
+    mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let mouse_state = mouse_state.borrow();
+        // Releasing the mouse after rotating the view also fires a click
+        if mouse_state.drag_distance > 3.0 {
+            return;
+        }
+
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+        let canvas = document
+            .get_element_by_id("canvas")
+            .expect("Can't find canvas element");
+        let pick_radius = document
+            .query_selector("input[type=number][id=pick-radius]")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .unwrap()
+            .value()
+            .parse::<f32>()
+            .unwrap_or(0.02);
+
+        let mv_matrix = create_model_view_matrix(
+            *scale_factor_ref.borrow(),
+            &mouse_state,
+            &mv_matrix_values.borrow(),
+        );
+        let p_matrix = create_projection_matrix();
+        let (ray_origin, ray_dir) = unproject_to_ray(
+            event.offset_x() as f32,
+            event.offset_y() as f32,
+            canvas.client_width() as f32,
+            canvas.client_height() as f32,
+            &mv_matrix,
+            &p_matrix,
+        );
+
+        let vertex_data_ref = vertex_data_ref.clone();
+        spatial_workers.pick(&ray_origin, &ray_dir, pick_radius, move |result| {
+            let hit = match result {
+                Ok(hit) => hit,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            let vertex_data = vertex_data_ref.borrow();
+            let message = match hit {
+                Some((point_index, _)) => {
+                    let point = get_point(&vertex_data.point_vertices, point_index);
+                    format!(
+                        "Picked point {} at ({:.3}, {:.3}, {:.3})",
+                        point_index, point.x, point.y, point.z
+                    )
+                }
+                None => "No point picked".to_string(),
+            };
+
+            if let Some(pick_result) = document.get_element_by_id("pick-result") {
+                pick_result.set_text_content(Some(&message));
+            }
+        });
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_pick_event_listener(pick_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let canvas = document
+        .get_element_by_id("canvas")
+        .expect("Can't find canvas element");
+
+    canvas
+        .add_event_listener_with_callback("click", pick_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    pick_handler.forget();
+}
+
+pub fn create_selection_tool_handler(
+    mouse_state: Rc<RefCell<MouseState>>,
+) -> Closure<dyn FnMut(web_sys::Event)> {
+    Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let target = event.target().unwrap();
+        let select = target.dyn_ref::<web_sys::HtmlSelectElement>().unwrap();
+        let Some(selection_tool) = SelectionTool::from_name(&select.value()) else {
+            web_sys::console::error_1(&JsValue::from_str("Invalid selection tool"));
+            return;
+        };
+
+        let mut mouse_state = mouse_state.borrow_mut();
+        mouse_state.selection_tool = selection_tool;
+        mouse_state.selection_path.clear();
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_selection_tool_event_listener(
+    selection_tool_handler: Closure<dyn FnMut(web_sys::Event)>,
+) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let selection_tool_select = document
+        .get_element_by_id("selection-tool")
+        .expect("Can't find selection-tool select element");
+    selection_tool_select
+        .add_event_listener_with_callback("change", selection_tool_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    selection_tool_handler.forget();
+}
+
+pub fn create_screen_selection_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+    scale_factor_ref: Rc<RefCell<f32>>,
+    mouse_state: Rc<RefCell<MouseState>>,
+    mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let (outline, mvp) = {
+            let mut mouse_state = mouse_state.borrow_mut();
+            if mouse_state.selection_tool == SelectionTool::Rotate {
+                return;
+            }
+            let outline = mouse_state.selection_outline();
+            mouse_state.selection_path.clear();
+
+            // Clicking without dragging or modifiers clears the selection and shows every
+            // point again
+            if mouse_state.drag_distance <= 3.0 {
+                if !event.shift_key() && !event.alt_key() {
+                    set_selection(&mut vertex_data_ref.borrow_mut(), None);
+                }
+                return;
+            }
+
+            let mv_matrix = create_model_view_matrix(
+                *scale_factor_ref.borrow(),
+                &mouse_state,
+                &mv_matrix_values.borrow(),
+            );
+            (outline, create_projection_matrix() * mv_matrix)
+        };
+        if outline.is_empty() {
+            return;
+        }
+
+        // Like the filter shapes, the selection drives the point EBO from the next frame on
+        let op = SelectionOp::from_modifiers(event.shift_key(), event.alt_key());
+        let vertex_data_ref = vertex_data_ref.clone();
+        let polygon = ScreenPolygon::new(mvp, outline);
+        spatial_workers.query_screen_polygon(&polygon, move |result| {
+            let point_indices = match result {
+                Ok(point_indices) => point_indices,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            let found = found_selection(&vertex_data, point_indices);
+            let selection = current_selection(&vertex_data).apply(op, &found);
+            set_selection(&mut vertex_data, Some(selection));
+        });
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_screen_selection_event_listener(
+    screen_selection_handler: Closure<dyn FnMut(web_sys::MouseEvent)>,
+) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let canvas = document
+        .get_element_by_id("canvas")
+        .expect("Can't find canvas element");
+
+    canvas
+        .add_event_listener_with_callback(
+            "mouseup",
+            screen_selection_handler.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+
+    screen_selection_handler.forget();
+}
+
+pub fn create_selection_button_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::Event)> {
+    Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let target = event.target().unwrap();
+        let button = target.dyn_ref::<web_sys::Element>().unwrap();
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        match button.id().as_str() {
+            // Later filter shape moves combine with the selection as it is now
+            "keep-selection" => vertex_data.filter_base = None,
+            "invert-selection" => {
+                let inverted = current_selection(&vertex_data).invert();
+                set_selection(&mut vertex_data, Some(inverted));
+            }
+            "clear-selection" => set_selection(&mut vertex_data, None),
+            _ => {}
+        }
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_selection_button_event_listener(
+    selection_button_handler: Closure<dyn FnMut(web_sys::Event)>,
+) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+    let button_ids = ["keep-selection", "invert-selection", "clear-selection"];
+    for button_id in button_ids.iter() {
+        let button = document
+            .get_element_by_id(button_id)
+            .unwrap_or_else(|| panic!("Can't find {} button", button_id));
+        button
+            .add_event_listener_with_callback(
+                "click",
+                selection_button_handler.as_ref().unchecked_ref(),
+            )
+            .unwrap();
+    }
+    selection_button_handler.forget();
+}
+
+// The selection as a set, with nothing selected meaning every point is
+fn current_selection(vertex_data: &VertexData) -> Selection {
+    let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+    vertex_data
+        .selected_points
+        .clone()
+        .unwrap_or_else(|| Selection::all(num_points))
+}
+
+fn found_selection(vertex_data: &VertexData, point_indices: Vec<u32>) -> Selection {
+    let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+    Selection::from_indices(num_points, point_indices)
+}
+
+// Replaces the selection outright, ending any filter shape move in progress
+fn set_selection(vertex_data: &mut VertexData, selection: Option<Selection>) {
+    vertex_data.selected_points = selection;
+    vertex_data.filter_base = None;
+    vertex_data.culled_mvp = None;
+}
+
+// The filter shape is combined with the selection from before it started moving, so that
+// moving it replaces its own earlier result rather than accumulating every position
+fn apply_filter_selection(vertex_data: &mut VertexData, op: SelectionOp, point_indices: Vec<u32>) {
+    let found = found_selection(vertex_data, point_indices);
+    let base = match vertex_data.filter_base.take() {
+        Some(base) => base,
+        None => current_selection(vertex_data),
+    };
+    vertex_data.selected_points = Some(base.apply(op, &found));
+    vertex_data.filter_base = Some(base);
+    vertex_data.culled_mvp = None;
+}
+
+pub fn create_downsample_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+        let voxel_size = document
+            .query_selector("input[type=number][id=voxel-size]")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .unwrap()
+            .value()
+            .parse::<f32>()
+            .unwrap_or(0.0);
+        if voxel_size <= 0.0 {
+            web_sys::console::error_1(&JsValue::from_str("Voxel size must be positive"));
+            return;
+        }
+
+        // The preview draws the point nearest each voxel's centroid, so it is an ordinary
+        // selection and combines with the others
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let representatives = vertex_data
+            .octree
+            .voxel_representatives(voxel_size, &vertex_data.point_vertices);
+        let found = found_selection(
+            &vertex_data,
+            representatives.into_iter().map(|i| i as u32).collect(),
+        );
+        let op = SelectionOp::from_modifiers(event.shift_key(), event.alt_key());
+        let selection = current_selection(&vertex_data).apply(op, &found);
+        set_selection(&mut vertex_data, Some(selection));
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_downsample_event_listener(downsample_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let downsample_button = document
+        .get_element_by_id("downsample")
+        .expect("Can't find downsample button");
+    downsample_button
+        .add_event_listener_with_callback("click", downsample_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    downsample_handler.forget();
+}
+
+pub fn create_outlier_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+
+        let get_input_value = |id: &str| {
+            document
+                .query_selector(&format!("input[type=number][id={}]", id))
+                .unwrap()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .unwrap()
+                .value()
+                .parse::<f32>()
+                .unwrap_or(0.0)
+        };
+        let filter_name = document
+            .get_element_by_id("outlier-filter")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+            .map_or_else(|| "statistical".to_string(), |select| select.value());
+        let num_neighbours = get_input_value("outlier-neighbours").max(0.0) as usize;
+        let value = match filter_name.as_str() {
+            "radius" => get_input_value("outlier-radius"),
+            _ => get_input_value("outlier-std-ratio"),
+        };
+        let Some(filter) = OutlierFilter::from_parts(&filter_name, num_neighbours, value) else {
+            web_sys::console::error_1(&JsValue::from_str("Invalid outlier filter"));
+            return;
+        };
+
+        let point_vertices = {
+            let vertex_data = vertex_data_ref.borrow();
+            let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+            if vertex_data.octree.num_points() != num_points {
+                web_sys::console::warn_1(&JsValue::from_str("The octree is still being built"));
+                return;
+            }
+            vertex_data.point_vertices.clone()
+        };
+        let outlier_result = document.get_element_by_id("outlier-result");
+        let target = event.target().unwrap();
+        let action = target.dyn_ref::<web_sys::Element>().unwrap().id();
+
+        let gl = gl.clone();
+        let vertex_data_ref = vertex_data_ref.clone();
+        let workers = spatial_workers.clone();
+        spatial_workers.find_outliers(point_vertices, &filter, move |result| {
+            let outliers = match result {
+                Ok(outliers) => outliers,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            if let Some(outlier_result) = outlier_result {
+                let message = format!("Found {} outliers", outliers.len());
+                outlier_result.set_text_content(Some(&message));
+            }
+
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            match action.as_str() {
+                "hide-outliers" => {
+                    let selection = current_selection(&vertex_data).difference(&outliers);
+                    set_selection(&mut vertex_data, Some(selection));
+                }
+                "highlight-outliers" => {
+                    let point_indices: Vec<u32> = outliers.indices().collect();
+                    update_point_ebo(&gl, &vertex_data.highlight_ebo, &point_indices);
+                    vertex_data.num_highlighted_points = point_indices.len() as u32;
+                }
+                "delete-outliers" => {
+                    delete_points(&mut vertex_data, &outliers);
+                    drop(vertex_data);
+                    start_scene_build(gl, vertex_data_ref.clone(), workers);
+                }
+                _ => {}
+            }
+        });
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_outlier_event_listener(outlier_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+    let button_ids = ["hide-outliers", "highlight-outliers", "delete-outliers"];
+    for button_id in button_ids.iter() {
+        let button = document
+            .get_element_by_id(button_id)
+            .unwrap_or_else(|| panic!("Can't find {} button", button_id));
+        button
+            .add_event_listener_with_callback("click", outlier_handler.as_ref().unchecked_ref())
+            .unwrap();
+    }
+    outlier_handler.forget();
+}
+
+pub fn create_normals_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+    scale_factor_ref: Rc<RefCell<f32>>,
+    mouse_state: Rc<RefCell<MouseState>>,
+    mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+        let num_neighbours = document
+            .query_selector("input[type=number][id=normal-neighbours]")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .unwrap()
+            .value()
+            .parse::<usize>()
+            .unwrap_or(10);
+
+        let point_vertices = {
+            let vertex_data = vertex_data_ref.borrow();
+            let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+            if vertex_data.octree.num_points() != num_points {
+                web_sys::console::warn_1(&JsValue::from_str("The octree is still being built"));
+                return;
+            }
+            vertex_data.point_vertices.clone()
+        };
+
+        // The normals are turned towards the camera as it is now
+        let mv_matrix = create_model_view_matrix(
+            *scale_factor_ref.borrow(),
+            &mouse_state.borrow(),
+            &mv_matrix_values.borrow(),
+        );
+        let eye = mv_matrix.try_inverse().map_or_else(Vec3::zeros, |inverse| {
+            Vec3::new(inverse[(0, 3)], inverse[(1, 3)], inverse[(2, 3)])
+        });
+
+        let gl = gl.clone();
+        let vertex_data_ref = vertex_data_ref.clone();
+        spatial_workers.estimate_normals(point_vertices, num_neighbours, &eye, move |result| {
+            let normals = match result {
+                Ok(normals) => normals,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            set_normals(&mut vertex_data.point_vertices, &normals);
+            update_point_vbo(&gl, &vertex_data.point_vbo, &vertex_data.point_vertices);
+        });
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_normals_event_listener(normals_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let normals_button = document
+        .get_element_by_id("estimate-normals")
+        .expect("Can't find estimate-normals button");
+    normals_button
+        .add_event_listener_with_callback("click", normals_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    normals_handler.forget();
+}
+
+pub fn create_color_mode_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::Event)> {
+    Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let target = event.target().unwrap();
+        let select = target.dyn_ref::<web_sys::HtmlSelectElement>().unwrap();
+        let Some(color_mode) = ColorMode::from_name(&select.value()) else {
+            web_sys::console::error_1(&JsValue::from_str("Invalid color mode"));
+            return;
+        };
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        vertex_data.color_mode = color_mode;
+        update_scalar_vbo(&gl, &vertex_data);
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_color_mode_event_listener(color_mode_handler: Closure<dyn FnMut(web_sys::Event)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let color_mode_select = document
+        .get_element_by_id("color-mode")
+        .expect("Can't find color-mode select element");
+    color_mode_select
+        .add_event_listener_with_callback("change", color_mode_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    color_mode_handler.forget();
+}
+
+pub fn create_cluster_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+
+        let get_input_value = |id: &str| {
+            document
+                .query_selector(&format!("input[type=number][id={}]", id))
+                .unwrap()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .unwrap()
+                .value()
+                .parse::<f32>()
+                .unwrap_or(0.0)
+        };
+        let method_name = document
+            .get_element_by_id("cluster-method")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+            .map_or_else(|| "euclidean".to_string(), |select| select.value());
+        let distance = get_input_value("cluster-distance");
+        let min_points = get_input_value("cluster-min-points").max(0.0) as usize;
+        let Some(method) = ClusterMethod::from_parts(&method_name, distance, min_points) else {
+            web_sys::console::error_1(&JsValue::from_str("Invalid clustering method"));
+            return;
+        };
+
+        let point_vertices = {
+            let vertex_data = vertex_data_ref.borrow();
+            let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+            if vertex_data.octree.num_points() != num_points {
+                web_sys::console::warn_1(&JsValue::from_str("The octree is still being built"));
+                return;
+            }
+            vertex_data.point_vertices.clone()
+        };
+        let cluster_result = document.get_element_by_id("cluster-result");
+        let color_mode_select = document
+            .get_element_by_id("color-mode")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok());
+
+        let gl = gl.clone();
+        let vertex_data_ref = vertex_data_ref.clone();
+        spatial_workers.find_clusters(point_vertices, &method, move |result| {
+            let cluster_ids = match result {
+                Ok(cluster_ids) => cluster_ids,
+                Err(err) => {
+                    err.report();
+                    return;
+                }
+            };
+            let mut vertex_data = vertex_data_ref.borrow_mut();
+            let num_clusters = summarize_clusters(&vertex_data.point_vertices, &cluster_ids).len();
+            if let Some(cluster_result) = cluster_result {
+                let message = format!("Found {} clusters", num_clusters);
+                cluster_result.set_text_content(Some(&message));
+            }
+
+            // Show the clusters straight away
+            if let Some(color_mode_select) = color_mode_select {
+                color_mode_select.set_value("cluster");
+            }
+            vertex_data.cluster_ids = Some(cluster_ids);
+            vertex_data.color_mode = ColorMode::Cluster;
+            update_scalar_vbo(&gl, &vertex_data);
+        });
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_cluster_event_listener(cluster_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let cluster_button = document
+        .get_element_by_id("cluster-points")
+        .expect("Can't find cluster-points button");
+    cluster_button
+        .add_event_listener_with_callback("click", cluster_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    cluster_handler.forget();
+}
+
+pub fn create_fit_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+
+        let get_input_value = |id: &str| {
+            document
+                .query_selector(&format!("input[type=number][id={}]", id))
+                .unwrap()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .unwrap()
+                .value()
+                .parse::<f32>()
+                .unwrap_or(0.0)
+        };
+        let primitive = document
+            .get_element_by_id("fit-primitive")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+            .and_then(|select| Primitive::from_name(&select.value()))
+            .unwrap_or(Primitive::Plane);
+        let threshold = get_input_value("fit-threshold");
+        let iterations = get_input_value("fit-iterations").max(0.0) as usize;
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let point_indices: Vec<u32> = current_selection(&vertex_data).indices().collect();
+        let fit = fit_primitive(
+            &vertex_data.point_vertices,
+            &point_indices,
+            primitive,
+            threshold,
+            iterations,
+            &mut rand::thread_rng(),
+        );
+
+        let message = match fit.as_ref() {
+            Some(fit) => format!(
+                "Parameters {:?}, {} inliers, RMS error {}",
+                fit.parameters(),
+                fit.inliers().len(),
+                fit.rms_error
+            ),
+            None => "No fit found".to_string(),
+        };
+        if let Some(fit_result) = document.get_element_by_id("fit-result") {
+            fit_result.set_text_content(Some(&message));
+        }
+
+        let inliers = fit.map(|fit| fit.inliers()).unwrap_or_default();
+        update_point_ebo(&gl, &vertex_data.highlight_ebo, &inliers);
+        vertex_data.num_highlighted_points = inliers.len() as u32;
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_fit_event_listener(fit_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let fit_button = document
+        .get_element_by_id("fit-selection")
+        .expect("Can't find fit-selection button");
+    fit_button
+        .add_event_listener_with_callback("click", fit_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    fit_handler.forget();
+}
+
+pub fn create_ground_handler(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+
+        let ground_result = document.get_element_by_id("ground-result");
+        let report_error = |message: &str| {
+            web_sys::console::error_1(&JsValue::from_str(message));
+            if let Some(ground_result) = ground_result.as_ref() {
+                ground_result.set_text_content(Some(message));
+            }
+        };
+
+        // An empty or malformed field is reported rather than read as zero, which would
+        // make every point ground
+        let get_input_value = |id: &str| {
+            document
+                .query_selector(&format!("input[type=number][id={}]", id))
+                .unwrap()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .unwrap()
+                .value()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid {}", id))
+        };
+        let config = match (
+            get_input_value("ground-cell-size"),
+            get_input_value("ground-max-window"),
+            get_input_value("ground-slope"),
+        ) {
+            (Ok(cell_size), Ok(max_window_size), Ok(slope)) => GroundFilterConfig {
+                cell_size,
+                max_window_size,
+                slope,
+                ..GroundFilterConfig::default()
+            },
+            (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+                report_error(&err);
+                return;
+            }
+        };
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let classes = match classify_ground(&vertex_data.point_vertices, &config) {
+            Ok(classes) => classes,
+            Err(err) => {
+                report_error(&err);
+                return;
+            }
+        };
+        let num_ground_points = classes.iter().filter(|&&class| class == GROUND).count();
+        let message = format!(
+            "{} ground points, {} others",
+            num_ground_points,
+            classes.len() - num_ground_points
+        );
+        if let Some(ground_result) = ground_result.as_ref() {
+            ground_result.set_text_content(Some(&message));
+        }
+
+        // Show the classes straight away
+        if let Some(color_mode_select) = document
+            .get_element_by_id("color-mode")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+        {
+            color_mode_select.set_value("class");
+        }
+        vertex_data.classes = Some(classes);
+        vertex_data.color_mode = ColorMode::Classification;
+        update_scalar_vbo(&gl, &vertex_data);
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_ground_event_listener(ground_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let ground_button = document
+        .get_element_by_id("classify-ground")
+        .expect("Can't find classify-ground button");
+    ground_button
+        .add_event_listener_with_callback("click", ground_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    ground_handler.forget();
+}
+
+pub fn create_class_filter_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::Event)> {
+    Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let target = event.target().unwrap();
+        let select = target.dyn_ref::<web_sys::HtmlSelectElement>().unwrap();
+        let class = match select.value().as_str() {
+            "ground" => Some(GROUND),
+            "non-ground" => Some(UNCLASSIFIED),
+            _ => None,
+        };
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let selection = match (class, vertex_data.classes.as_ref()) {
+            (None, _) => None,
+            (Some(class), Some(classes)) => Some(class_selection(classes, class)),
+            (Some(_), None) => {
+                web_sys::console::warn_1(&JsValue::from_str("The ground hasn't been classified"));
+                return;
+            }
+        };
+        set_selection(&mut vertex_data, selection);
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_class_filter_event_listener(class_filter_handler: Closure<dyn FnMut(web_sys::Event)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let class_filter_select = document
+        .get_element_by_id("class-filter")
+        .expect("Can't find class-filter select element");
+    class_filter_select
+        .add_event_listener_with_callback("change", class_filter_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    class_filter_handler.forget();
+}
+
+pub fn create_second_cloud_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let target = event.target().unwrap();
+        let button = target.dyn_ref::<web_sys::Element>().unwrap();
+        match button.id().as_str() {
+            "add-moved-copy" => {
+                // A copy of the scene as a second scan taken from slightly elsewhere would
+                // see it, for trying out the alignment
+                let motion = nalgebra_glm::translation(&Vec3::new(0.05, 0.02, -0.04))
+                    * nalgebra_glm::rotation(0.1, &Vec3::new(0.2, 1.0, 0.1).normalize());
+                let mut vertices = vertex_data.point_vertices.clone();
+                transform_points(&mut vertices, &motion);
+                set_second_cloud(&mut vertex_data, vertices);
+            }
+            "clear-second-cloud" => set_second_cloud(&mut vertex_data, Vec::new()),
+            _ => {}
+        }
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_second_cloud_event_listener(
+    second_cloud_handler: Closure<dyn FnMut(web_sys::MouseEvent)>,
+) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+    let button_ids = ["add-moved-copy", "clear-second-cloud"];
+    for button_id in button_ids.iter() {
+        let button = document
+            .get_element_by_id(button_id)
+            .unwrap_or_else(|| panic!("Can't find {} button", button_id));
+        button
+            .add_event_listener_with_callback(
+                "click",
+                second_cloud_handler.as_ref().unchecked_ref(),
+            )
+            .unwrap();
+    }
+    second_cloud_handler.forget();
+}
+
+pub fn create_icp_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+
+        let get_input_value = |id: &str| {
+            document
+                .query_selector(&format!("input[type=number][id={}]", id))
+                .unwrap()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .unwrap()
+                .value()
+                .parse::<f32>()
+                .unwrap_or(0.0)
+        };
+        let method = document
+            .get_element_by_id("icp-method")
+            .and_then(|select| select.dyn_into::<web_sys::HtmlSelectElement>().ok())
+            .and_then(|select| IcpMethod::from_name(&select.value()))
+            .unwrap_or(IcpMethod::PointToPoint);
+        let config = IcpConfig {
+            max_iterations: get_input_value("icp-iterations").max(0.0) as usize,
+            max_correspondence_distance: get_input_value("icp-max-distance"),
+            ..IcpConfig::default()
+        };
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let message = match align_second_cloud(&mut vertex_data, method, &config) {
+            Ok(result) => {
+                let rms_errors = result.rms_errors();
+                format!(
+                    "{} after {} iterations, RMS error {} to {}",
+                    if result.converged {
+                        "Converged"
+                    } else {
+                        "Stopped"
+                    },
+                    result.iterations,
+                    rms_errors.first().copied().unwrap_or_default(),
+                    rms_errors.last().copied().unwrap_or_default()
+                )
+            }
+            Err(err) => err,
+        };
+        if let Some(icp_result) = document.get_element_by_id("icp-result") {
+            icp_result.set_text_content(Some(&message));
+        }
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_icp_event_listener(icp_handler: Closure<dyn FnMut(web_sys::MouseEvent)>) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let icp_button = document
+        .get_element_by_id("run-icp")
+        .expect("Can't find run-icp button");
+    icp_button
+        .add_event_listener_with_callback("click", icp_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    icp_handler.forget();
+}
+
+pub fn create_cloud_distance_handler(
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+) -> Closure<dyn FnMut(web_sys::MouseEvent)> {
+    Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
+        let window = web_sys::window().expect("No global window exists");
+        let document = window.document().expect("Should have a document on window");
+        let num_bins = document
+            .query_selector("input[type=number][id=c2c-bins]")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .unwrap()
+            .value()
+            .parse::<usize>()
+            .unwrap_or(10);
+
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        let message = match compute_second_cloud_distances(&mut vertex_data) {
+            Ok(distances) => {
+                let histogram = distance_histogram(distances, num_bins);
+                let mut message = format!("Mean distance {}\n", histogram.mean);
+                for (bin, count) in histogram.counts().iter().enumerate() {
+                    let bin_start = histogram.min + bin as f32 * histogram.bin_width;
+                    message += &format!(
+                        "{:.4} to {:.4}: {}\n",
+                        bin_start,
+                        bin_start + histogram.bin_width,
+                        count
+                    );
+                }
+                message
+            }
+            Err(err) => err,
+        };
+        if let Some(c2c_result) = document.get_element_by_id("c2c-result") {
+            c2c_result.set_text_content(Some(&message));
+        }
+    }) as Box<dyn FnMut(_)>)
+}
+
+pub fn add_cloud_distance_event_listener(
+    cloud_distance_handler: Closure<dyn FnMut(web_sys::MouseEvent)>,
+) {
+    let window = web_sys::window().expect("No global window exists");
+    let document = window.document().expect("Should have a document on window");
+
+    let c2c_button = document
+        .get_element_by_id("compute-c2c")
+        .expect("Can't find compute-c2c button");
+    c2c_button
+        .add_event_listener_with_callback("click", cloud_distance_handler.as_ref().unchecked_ref())
+        .unwrap();
+
+    cloud_distance_handler.forget();
+}
@@ -0,0 +1,337 @@
+// kdtree.rs
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use nalgebra_glm::Vec3;
+
+use crate::frustum::Containment;
+use crate::octree::{get_point, VERTEX_STRIDE};
+use crate::region::Region;
+use crate::spatial_index::{
+    aabb_contains, aabb_distance, aabb_overlaps, ray_aabb_entry, ray_point_hit, HeapEntry,
+    SpatialIndex,
+};
+
+// Nodes with this many points or fewer are not split
+const MAX_POINTS_PER_LEAF: usize = 16;
+
+#[derive(Clone)]
+struct KdNode {
+    // Tight bounds of the points under this node
+    min: Vec3,
+    max: Vec3,
+    // The node's points are `point_indices[start..end]`
+    start: usize,
+    end: usize,
+    children: Option<(usize, usize)>,
+}
+
+/// A static k-d tree. Each node splits its points at the median along the axis on which
+/// they are most spread out, which adapts to long, thin clouds better than the octree's
+/// fixed cubes. The tree cannot be edited once built.
+#[derive(Clone)]
+pub struct KdTree {
+    nodes: Vec<KdNode>,
+    point_indices: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn build(vertex_points: &[f32]) -> Self {
+        let num_points = vertex_points.len() / VERTEX_STRIDE;
+        let mut kd_tree = KdTree {
+            nodes: Vec::new(),
+            point_indices: (0..num_points).collect(),
+        };
+        if num_points > 0 {
+            kd_tree.build_node(0, num_points, vertex_points);
+        }
+        kd_tree
+    }
+
+    // Builds the node for `point_indices[start..end]` and returns its position in `nodes`
+    fn build_node(&mut self, start: usize, end: usize, vertex_points: &[f32]) -> usize {
+        let node_points = &mut self.point_indices[start..end];
+        let (min, max) = node_points
+            .iter()
+            .map(|&i| get_point(vertex_points, i))
+            .fold(
+                (Vec3::repeat(f32::INFINITY), Vec3::repeat(f32::NEG_INFINITY)),
+                |(min, max), point| (min.inf(&point), max.sup(&point)),
+            );
+
+        let node_id = self.nodes.len();
+        self.nodes.push(KdNode {
+            min,
+            max,
+            start,
+            end,
+            children: None,
+        });
+        if end - start <= MAX_POINTS_PER_LEAF {
+            return node_id;
+        }
+
+        let axis = (max - min).imax();
+        let middle = node_points.len() / 2;
+        node_points.select_nth_unstable_by(middle, |&a, &b| {
+            get_point(vertex_points, a)[axis].total_cmp(&get_point(vertex_points, b)[axis])
+        });
+
+        let left = self.build_node(start, start + middle, vertex_points);
+        let right = self.build_node(start + middle, end, vertex_points);
+        self.nodes[node_id].children = Some((left, right));
+        node_id
+    }
+
+    fn query_sphere_into(
+        &self,
+        node_id: usize,
+        center: &Vec3,
+        radius: f32,
+        vertex_points: &[f32],
+        point_indices: &mut Vec<usize>,
+    ) {
+        let node = &self.nodes[node_id];
+        if aabb_distance(&node.min, &node.max, center) > radius {
+            return;
+        }
+
+        match node.children {
+            None => {
+                for &point_index in &self.point_indices[node.start..node.end] {
+                    let point = get_point(vertex_points, point_index);
+                    if nalgebra_glm::distance(&point, center) <= radius {
+                        point_indices.push(point_index);
+                    }
+                }
+            }
+            Some((left, right)) => {
+                self.query_sphere_into(left, center, radius, vertex_points, point_indices);
+                self.query_sphere_into(right, center, radius, vertex_points, point_indices);
+            }
+        }
+    }
+
+    fn query_aabb_into(
+        &self,
+        node_id: usize,
+        min: &Vec3,
+        max: &Vec3,
+        vertex_points: &[f32],
+        point_indices: &mut Vec<usize>,
+    ) {
+        let node = &self.nodes[node_id];
+        if !aabb_overlaps(&node.min, &node.max, min, max) {
+            return;
+        }
+        if aabb_contains(min, max, &node.min) && aabb_contains(min, max, &node.max) {
+            point_indices.extend_from_slice(&self.point_indices[node.start..node.end]);
+            return;
+        }
+
+        match node.children {
+            None => {
+                for &point_index in &self.point_indices[node.start..node.end] {
+                    let point = get_point(vertex_points, point_index);
+                    if aabb_contains(min, max, &point) {
+                        point_indices.push(point_index);
+                    }
+                }
+            }
+            Some((left, right)) => {
+                self.query_aabb_into(left, min, max, vertex_points, point_indices);
+                self.query_aabb_into(right, min, max, vertex_points, point_indices);
+            }
+        }
+    }
+
+    fn query_region_into(
+        &self,
+        node_id: usize,
+        region: &dyn Region,
+        vertex_points: &[f32],
+        point_indices: &mut Vec<usize>,
+    ) {
+        let node = &self.nodes[node_id];
+        match region.classify_aabb(&node.min, &node.max) {
+            Containment::Outside => {}
+            Containment::Inside => {
+                point_indices.extend_from_slice(&self.point_indices[node.start..node.end])
+            }
+            Containment::Intersecting => match node.children {
+                None => {
+                    for &point_index in &self.point_indices[node.start..node.end] {
+                        if region.contains_point(&get_point(vertex_points, point_index)) {
+                            point_indices.push(point_index);
+                        }
+                    }
+                }
+                Some((left, right)) => {
+                    self.query_region_into(left, region, vertex_points, point_indices);
+                    self.query_region_into(right, region, vertex_points, point_indices);
+                }
+            },
+        }
+    }
+
+    fn pick_into(
+        &self,
+        node_id: usize,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+        best: &mut Option<(usize, f32)>,
+    ) {
+        let node = &self.nodes[node_id];
+        match node.children {
+            None => {
+                for &point_index in &self.point_indices[node.start..node.end] {
+                    let point = get_point(vertex_points, point_index);
+                    let Some(along_ray) = ray_point_hit(&point, ray_origin, ray_dir, pick_radius)
+                    else {
+                        continue;
+                    };
+                    if best.map_or(true, |(_, best_along_ray)| along_ray < best_along_ray) {
+                        *best = Some((point_index, along_ray));
+                    }
+                }
+            }
+            Some((left, right)) => {
+                let mut hits: Vec<(f32, usize)> = [left, right]
+                    .into_iter()
+                    .filter_map(|child_id| {
+                        let child = &self.nodes[child_id];
+                        ray_aabb_entry(&child.min, &child.max, ray_origin, ray_dir, pick_radius)
+                            .map(|entry| (entry, child_id))
+                    })
+                    .collect();
+                hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                for (entry, child_id) in hits {
+                    if best.is_some_and(|(_, best_along_ray)| entry > best_along_ray) {
+                        break;
+                    }
+                    self.pick_into(
+                        child_id,
+                        ray_origin,
+                        ray_dir,
+                        pick_radius,
+                        vertex_points,
+                        best,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl SpatialIndex for KdTree {
+    fn query_sphere(&self, center: &Vec3, radius: f32, vertex_points: &[f32]) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        if !self.nodes.is_empty() {
+            self.query_sphere_into(0, center, radius, vertex_points, &mut point_indices);
+        }
+        point_indices
+    }
+
+    fn query_aabb(&self, min: &Vec3, max: &Vec3, vertex_points: &[f32]) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        if !self.nodes.is_empty() {
+            self.query_aabb_into(0, min, max, vertex_points, &mut point_indices);
+        }
+        point_indices
+    }
+
+    fn query_region(&self, region: &dyn Region, vertex_points: &[f32]) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        if !self.nodes.is_empty() {
+            self.query_region_into(0, region, vertex_points, &mut point_indices);
+        }
+        point_indices
+    }
+
+    fn knn(&self, query: &Vec3, k: usize, vertex_points: &[f32]) -> Vec<(usize, f32)> {
+        if k == 0 || self.nodes.is_empty() {
+            return Vec::new();
+        }
+
+        // Same best-first search as `Octree::knn`, over node ids
+        let mut nearest: BinaryHeap<HeapEntry<usize>> = BinaryHeap::with_capacity(k + 1);
+        let mut pending: BinaryHeap<Reverse<HeapEntry<usize>>> = BinaryHeap::new();
+        pending.push(Reverse(HeapEntry {
+            distance: aabb_distance(&self.nodes[0].min, &self.nodes[0].max, query),
+            item: 0,
+        }));
+
+        while let Some(Reverse(HeapEntry {
+            distance,
+            item: node_id,
+        })) = pending.pop()
+        {
+            if nearest.len() == k && distance > nearest.peek().unwrap().distance {
+                break;
+            }
+
+            let node = &self.nodes[node_id];
+            match node.children {
+                None => {
+                    for &point_index in &self.point_indices[node.start..node.end] {
+                        let point = get_point(vertex_points, point_index);
+                        let distance = nalgebra_glm::distance(&point, query);
+                        if nearest.len() < k {
+                            nearest.push(HeapEntry {
+                                distance,
+                                item: point_index,
+                            });
+                        } else if distance < nearest.peek().unwrap().distance {
+                            nearest.pop();
+                            nearest.push(HeapEntry {
+                                distance,
+                                item: point_index,
+                            });
+                        }
+                    }
+                }
+                Some((left, right)) => {
+                    for child_id in [left, right] {
+                        let child = &self.nodes[child_id];
+                        pending.push(Reverse(HeapEntry {
+                            distance: aabb_distance(&child.min, &child.max, query),
+                            item: child_id,
+                        }));
+                    }
+                }
+            }
+        }
+
+        nearest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| (entry.item, entry.distance))
+            .collect()
+    }
+
+    fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+    ) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let ray_dir = ray_dir.normalize();
+        let mut best = None;
+        self.pick_into(
+            0,
+            ray_origin,
+            &ray_dir,
+            pick_radius,
+            vertex_points,
+            &mut best,
+        );
+        best
+    }
+}
@@ -1,34 +1,146 @@
 use std::{cell::RefCell, rc::Rc};
 
-use input::get_num_points_from_html;
-use nalgebra_glm::vec3;
+use input::{get_num_points_from_html, get_spatial_index_kind_from_html};
+use js_sys::{Function, Promise, Uint32Array};
+use nalgebra_glm::{vec3, Vec3};
 use wasm_bindgen::prelude::*;
 use web_sys::WebGl2RenderingContext;
 
+mod cloud_distance;
+mod clustering;
+mod fitting;
+mod frustum;
+mod ground;
 mod input;
+mod kdtree;
 mod matrix;
 mod mouse;
+mod normals;
 mod octree;
+mod outliers;
+mod polytope;
+mod region;
+mod registration;
 mod render;
+mod screen_selection;
+mod selection;
 mod shaders;
+mod spatial_index;
 mod vertex_buffer;
 mod webgl_utils;
+mod worker;
+mod worker_pool;
 
+use cloud_distance::DistanceHistogram;
+use clustering::ClusterSummary;
+use fitting::{Primitive, PrimitiveFit};
 use matrix::MVMatrixValues;
+use octree::{OctreeStats, VoxelRepresentative};
+use polytope::ConvexPolytope;
+use registration::{IcpConfig, IcpMethod, IcpResult};
+use vertex_buffer::VertexData;
+use worker_pool::{JobResult, SpatialWorkers};
+
+thread_local! {
+    // The scene's vertex data, kept so exported functions can reach it once `start` returns
+    static VERTEX_DATA: RefCell<Option<Rc<RefCell<VertexData>>>> = const { RefCell::new(None) };
+    // The workers point queries from exported functions are sent to
+    static SPATIAL_WORKERS: RefCell<Option<Rc<SpatialWorkers>>> = const { RefCell::new(None) };
+}
+
+fn with_vertex_data<T>(f: impl FnOnce(&VertexData) -> T) -> Result<T, JsValue> {
+    VERTEX_DATA.with(|vertex_data| {
+        let vertex_data = vertex_data.borrow();
+        let vertex_data = vertex_data
+            .as_ref()
+            .ok_or("The scene has not been started")?;
+        let vertex_data = vertex_data.borrow();
+        Ok(f(&vertex_data))
+    })
+}
+
+fn with_vertex_data_mut<T>(f: impl FnOnce(&mut VertexData) -> T) -> Result<T, JsValue> {
+    VERTEX_DATA.with(|vertex_data| {
+        let vertex_data = vertex_data.borrow();
+        let vertex_data = vertex_data
+            .as_ref()
+            .ok_or("The scene has not been started")?;
+        let mut vertex_data = vertex_data.borrow_mut();
+        Ok(f(&mut vertex_data))
+    })
+}
+
+fn spatial_workers() -> Option<Rc<SpatialWorkers>> {
+    SPATIAL_WORKERS.with(|spatial_workers| spatial_workers.borrow().clone())
+}
+
+fn to_vec3(values: &[f32], name: &str) -> Result<Vec3, JsValue> {
+    match *values {
+        [x, y, z] => Ok(vec3(x, y, z)),
+        _ => Err(JsValue::from_str(&format!(
+            "{} needs three coordinates",
+            name
+        ))),
+    }
+}
+
+type QueryCallback<T> = Box<dyn FnOnce(JobResult<T>)>;
+
+// Starts a query on the spatial workers and returns a promise of its result, converted by
+// `to_js`. The promise is rejected if a worker fails, or if the points are replaced before
+// the workers reply.
+fn query_workers<T: 'static>(
+    query: impl FnOnce(&SpatialWorkers, QueryCallback<T>),
+    to_js: impl FnOnce(T) -> JsValue + 'static,
+) -> Result<Promise, JsValue> {
+    let spatial_workers = spatial_workers().ok_or("The scene has not been started")?;
+    let mut start = Some((query, to_js));
+    Ok(Promise::new(&mut |resolve: Function, reject: Function| {
+        if let Some((query, to_js)) = start.take() {
+            let on_done = move |result: JobResult<T>| {
+                let _ = match result {
+                    Ok(value) => resolve.call1(&JsValue::NULL, &to_js(value)),
+                    Err(err) => reject.call1(&JsValue::NULL, &err.into()),
+                };
+            };
+            query(&spatial_workers, Box::new(on_done));
+        }
+    }))
+}
+
+fn indices_to_js(point_indices: Vec<u32>) -> JsValue {
+    Uint32Array::from(point_indices.as_slice()).into()
+}
 
 #[wasm_bindgen(start)]
 pub fn start() -> Result<(), JsValue> {
+    // Workers load this module too, for the `worker_*` exports, and have no page to draw on
+    if web_sys::window().is_none() {
+        return Ok(());
+    }
+
     let (gl, program, mouse_state, mv_matrix_values) = setup()?;
     let num_points = get_num_points_from_html()?;
-    let vertex_data = vertex_buffer::create_vertex_buffers(&gl, num_points as u32)?;
+    let spatial_index_kind = get_spatial_index_kind_from_html()?;
+    let vertex_data =
+        vertex_buffer::create_vertex_buffers(&gl, num_points as u32, spatial_index_kind)?;
     let vertex_data_ref = Rc::new(RefCell::new(vertex_data));
+    VERTEX_DATA.with(|vertex_data| *vertex_data.borrow_mut() = Some(vertex_data_ref.clone()));
     let scale_factor = 1.0;
     let gl_ref = Rc::new(gl);
+    let spatial_workers = Rc::new(SpatialWorkers::start());
+    SPATIAL_WORKERS.with(|workers| *workers.borrow_mut() = Some(spatial_workers.clone()));
+    vertex_buffer::start_scene_build(
+        gl_ref.clone(),
+        vertex_data_ref.clone(),
+        spatial_workers.clone(),
+    );
 
     render::start_render_loop(
         gl_ref,
         program,
         vertex_data_ref,
+        spatial_workers,
         scale_factor,
         mouse_state,
         mv_matrix_values,
@@ -36,15 +148,259 @@ pub fn start() -> Result<(), JsValue> {
     Ok(())
 }
 
-fn setup() -> Result<
-    (
-        WebGl2RenderingContext,
-        web_sys::WebGlProgram,
-        Rc<RefCell<mouse::MouseState>>,
-        Rc<RefCell<MVMatrixValues>>,
-    ),
-    JsValue,
-> {
+/// Resolves to a `Uint32Array` of the indices of the points inside the axis-aligned box
+/// from `min` to `max`, each given as `[x, y, z]`.
+#[wasm_bindgen]
+pub fn query_box(min: &[f32], max: &[f32]) -> Result<Promise, JsValue> {
+    let (min, max) = (to_vec3(min, "min")?, to_vec3(max, "max")?);
+    query_workers(
+        |spatial_workers, on_done| spatial_workers.query_aabb(&min, &max, on_done),
+        indices_to_js,
+    )
+}
+
+/// Resolves to a `Uint32Array` of the indices of the points whose offset along `normal`
+/// lies between `min_offset` and `max_offset`, for section cuts.
+#[wasm_bindgen]
+pub fn query_slab(normal: &[f32], min_offset: f32, max_offset: f32) -> Result<Promise, JsValue> {
+    let slab = ConvexPolytope::slab(&to_vec3(normal, "normal")?, min_offset, max_offset);
+    query_polytope(slab)
+}
+
+/// Resolves to a `Uint32Array` of the indices of the points inside the box centred on
+/// `center` that reaches `half_extents[i]` either side along axis `i`. `axes` holds the
+/// three perpendicular axes one after another.
+#[wasm_bindgen]
+pub fn query_oriented_box(
+    center: &[f32],
+    axes: &[f32],
+    half_extents: &[f32],
+) -> Result<Promise, JsValue> {
+    if axes.len() != 9 {
+        return Err(JsValue::from_str(
+            "axes needs three coordinates for each axis",
+        ));
+    }
+    let axes = [
+        to_vec3(&axes[0..3], "axes")?,
+        to_vec3(&axes[3..6], "axes")?,
+        to_vec3(&axes[6..9], "axes")?,
+    ];
+    let oriented_box = ConvexPolytope::oriented_box(
+        &to_vec3(center, "center")?,
+        &axes,
+        &to_vec3(half_extents, "half_extents")?,
+    );
+    query_polytope(oriented_box)
+}
+
+fn query_polytope(polytope: ConvexPolytope) -> Result<Promise, JsValue> {
+    query_workers(
+        |spatial_workers, on_done| spatial_workers.query_polytope(&polytope, on_done),
+        indices_to_js,
+    )
+}
+
+/// Resolves to a `Uint32Array` of the indices of the `k` points closest to `(x, y, z)`,
+/// nearest first.
+#[wasm_bindgen]
+pub fn nearest_points(x: f32, y: f32, z: f32, k: usize) -> Result<Promise, JsValue> {
+    query_workers(
+        |spatial_workers, on_done| spatial_workers.knn(&vec3(x, y, z), k, on_done),
+        |neighbours| {
+            let point_indices: Vec<u32> = neighbours
+                .into_iter()
+                .map(|(point_index, _)| point_index as u32)
+                .collect();
+            indices_to_js(point_indices)
+        },
+    )
+}
+
+/// Moves point `point_index` to `(x, y, z)`. The octree is updated in place, and the
+/// spatial workers are reloaded on the next frame.
+#[wasm_bindgen]
+pub fn move_point(point_index: usize, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+    with_vertex_data_mut(|vertex_data| {
+        vertex_buffer::move_point(vertex_data, point_index, &vec3(x, y, z))
+    })?
+    .map_err(|err| JsValue::from_str(&err))
+}
+
+/// Reports the shape of the current octree.
+#[wasm_bindgen]
+pub fn get_octree_stats() -> Result<OctreeStats, JsValue> {
+    with_vertex_data(|vertex_data| vertex_data.octree.stats())
+}
+
+/// Serializes the current octree, so it can be shipped alongside the points and loaded
+/// with `load_octree` instead of being rebuilt.
+#[wasm_bindgen]
+pub fn get_octree_bytes() -> Result<Vec<u8>, JsValue> {
+    with_vertex_data(|vertex_data| vertex_data.octree.to_bytes())
+}
+
+/// Replaces the octree with one serialized by `get_octree_bytes` over the same points.
+#[wasm_bindgen]
+pub fn load_octree(bytes: &[u8]) -> Result<(), JsValue> {
+    with_vertex_data_mut(|vertex_data| vertex_buffer::load_octree(vertex_data, bytes))?
+        .map_err(|err| JsValue::from_str(&err))
+}
+
+/// Returns the indices of the selected points in ascending order, or of every point when
+/// nothing is selected, for exporting the selection.
+#[wasm_bindgen]
+pub fn get_selected_points() -> Result<Vec<u32>, JsValue> {
+    with_vertex_data(|vertex_data| match vertex_data.selected_points.as_ref() {
+        Some(selection) => selection.indices().collect(),
+        None => (0..vertex_data.point_vertices.len() / octree::VERTEX_STRIDE)
+            .map(|i| i as u32)
+            .collect(),
+    })
+}
+
+/// Returns the scene downsampled to one point per voxel of `voxel_size`, as interleaved
+/// vertices. `representative` is "centroid" or "nearest", as for `VoxelRepresentative`.
+#[wasm_bindgen]
+pub fn downsample_points(voxel_size: f32, representative: &str) -> Result<Vec<f32>, JsValue> {
+    let representative = VoxelRepresentative::from_name(representative)
+        .ok_or_else(|| JsValue::from_str("Invalid voxel representative"))?;
+    with_vertex_data(|vertex_data| {
+        vertex_data
+            .octree
+            .voxel_downsample(voxel_size, representative, &vertex_data.point_vertices)
+    })
+}
+
+/// Returns the normal of every point as flat x, y, z triples, zero until they have been
+/// estimated.
+#[wasm_bindgen]
+pub fn get_point_normals() -> Result<Vec<f32>, JsValue> {
+    with_vertex_data(|vertex_data| {
+        vertex_data
+            .point_vertices
+            .chunks_exact(octree::VERTEX_STRIDE)
+            .flat_map(|vertex| &vertex[octree::NORMAL_OFFSET..octree::NORMAL_OFFSET + 3])
+            .copied()
+            .collect()
+    })
+}
+
+/// Returns the cluster id of every point from the last clustering, with `u32::MAX` for
+/// points in no cluster.
+#[wasm_bindgen]
+pub fn get_cluster_ids() -> Result<Vec<u32>, JsValue> {
+    with_vertex_data(|vertex_data| vertex_data.cluster_ids.clone())?
+        .ok_or_else(|| JsValue::from_str("The points have not been clustered"))
+}
+
+/// Returns the point count, centroid and bounding box of each cluster from the last
+/// clustering, largest first.
+#[wasm_bindgen]
+pub fn get_cluster_summaries() -> Result<Vec<ClusterSummary>, JsValue> {
+    with_vertex_data(|vertex_data| {
+        vertex_data.cluster_ids.as_ref().map(|cluster_ids| {
+            clustering::summarize_clusters(&vertex_data.point_vertices, cluster_ids)
+        })
+    })?
+    .ok_or_else(|| JsValue::from_str("The points have not been clustered"))
+}
+
+/// Fits a "plane", "sphere" or "cylinder" to the selected points, or to every point when
+/// nothing is selected, by RANSAC over `iterations` samples. Points within `threshold` of
+/// the surface count as inliers.
+#[wasm_bindgen]
+pub fn fit_selection(
+    primitive: &str,
+    threshold: f32,
+    iterations: usize,
+) -> Result<PrimitiveFit, JsValue> {
+    let primitive =
+        Primitive::from_name(primitive).ok_or_else(|| JsValue::from_str("Invalid primitive"))?;
+    let point_indices = get_selected_points()?;
+    with_vertex_data(|vertex_data| {
+        fitting::fit_primitive(
+            &vertex_data.point_vertices,
+            &point_indices,
+            primitive,
+            threshold,
+            iterations,
+            &mut rand::thread_rng(),
+        )
+    })?
+    .ok_or_else(|| JsValue::from_str("No fit found"))
+}
+
+/// Returns the LAS class of every point from the last ground classification: 2 for
+/// ground and 1 for everything else.
+#[wasm_bindgen]
+pub fn get_point_classes() -> Result<Vec<u8>, JsValue> {
+    with_vertex_data(|vertex_data| vertex_data.classes.clone())?
+        .ok_or_else(|| JsValue::from_str("The ground hasn't been classified"))
+}
+
+/// Loads a second cloud, such as another scan of the same site, as interleaved vertices in
+/// the layout `downsample_points` returns. It's drawn over the scene in its own color.
+#[wasm_bindgen]
+pub fn load_second_cloud(vertices: Vec<f32>) -> Result<(), JsValue> {
+    if vertices.len() % octree::VERTEX_STRIDE != 0 {
+        return Err(JsValue::from_str(&format!(
+            "Vertices should have {} floats each",
+            octree::VERTEX_STRIDE
+        )));
+    }
+    with_vertex_data_mut(|vertex_data| vertex_buffer::set_second_cloud(vertex_data, vertices))
+}
+
+/// Aligns the second cloud to the scene by "point-to-point" or "point-to-plane" ICP and
+/// moves it into place. Point-to-plane needs the scene's normals. Pairs of points further
+/// apart than `max_distance` are left out.
+#[wasm_bindgen]
+pub fn align_second_cloud(
+    method: &str,
+    max_iterations: usize,
+    max_distance: f32,
+) -> Result<IcpResult, JsValue> {
+    let method =
+        IcpMethod::from_name(method).ok_or_else(|| JsValue::from_str("Invalid ICP method"))?;
+    let config = IcpConfig {
+        max_iterations,
+        max_correspondence_distance: max_distance,
+        ..IcpConfig::default()
+    };
+    with_vertex_data_mut(|vertex_data| {
+        vertex_buffer::align_second_cloud(vertex_data, method, &config)
+    })?
+    .map_err(|err| JsValue::from_str(&err))
+}
+
+/// Measures the distance from each point of the second cloud to the nearest scene point,
+/// colors the second cloud by it, and returns the distances binned into `num_bins` bins.
+#[wasm_bindgen]
+pub fn compute_cloud_distances(num_bins: usize) -> Result<DistanceHistogram, JsValue> {
+    with_vertex_data_mut(|vertex_data| {
+        vertex_buffer::compute_second_cloud_distances(vertex_data)
+            .map(|distances| cloud_distance::distance_histogram(distances, num_bins))
+    })?
+    .map_err(|err| JsValue::from_str(&err))
+}
+
+/// Returns the distance from each point of the second cloud to the nearest scene point,
+/// as last computed by `compute_cloud_distances`.
+#[wasm_bindgen]
+pub fn get_cloud_distances() -> Result<Vec<f32>, JsValue> {
+    with_vertex_data(|vertex_data| vertex_data.second_cloud_distances.clone())?
+        .ok_or_else(|| JsValue::from_str("The cloud distances have not been computed"))
+}
+
+type SetupResult = (
+    WebGl2RenderingContext,
+    web_sys::WebGlProgram,
+    Rc<RefCell<mouse::MouseState>>,
+    Rc<RefCell<MVMatrixValues>>,
+);
+
+fn setup() -> Result<SetupResult, JsValue> {
     let window = web_sys::window().ok_or("No global `window` exists")?;
     let document = window
         .document()
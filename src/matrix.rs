@@ -1,5 +1,5 @@
 use nalgebra_glm::Vec3;
-use web_sys::{WebGlProgram, WebGl2RenderingContext};
+use web_sys::{WebGl2RenderingContext, WebGlProgram};
 
 use crate::mouse::MouseState;
 
@@ -17,7 +17,7 @@ pub fn create_model_view_matrix(
     let mv_matrix = nalgebra_glm::Mat4::look_at_rh(
         &mv_matrix_values.eye.into(),
         &mv_matrix_values.target.into(),
-        &mv_matrix_values.up.into(),
+        &mv_matrix_values.up,
     );
     let translation_vector = nalgebra_glm::Vec3::new(0.0, 0.0, -distance);
     let translated_mv_matrix = mv_matrix.append_translation(&translation_vector);
@@ -32,14 +32,46 @@ pub fn create_projection_matrix() -> nalgebra_glm::Mat4 {
     nalgebra_glm::perspective(800.0 / 600.0, 45.0_f32.to_radians(), 0.1, 100.0)
 }
 
+/// Turns a position on the canvas (in CSS pixels, origin top-left) into a ray in the
+/// model space the points live in. Returns the ray origin on the near plane and its
+/// normalized direction.
+pub fn unproject_to_ray(
+    canvas_x: f32,
+    canvas_y: f32,
+    canvas_width: f32,
+    canvas_height: f32,
+    mv_matrix: &nalgebra_glm::Mat4,
+    p_matrix: &nalgebra_glm::Mat4,
+) -> (Vec3, Vec3) {
+    let ndc_x = 2.0 * canvas_x / canvas_width - 1.0;
+    let ndc_y = 1.0 - 2.0 * canvas_y / canvas_height;
+    let near = unproject(&Vec3::new(ndc_x, ndc_y, -1.0), mv_matrix, p_matrix);
+    let far = unproject(&Vec3::new(ndc_x, ndc_y, 1.0), mv_matrix, p_matrix);
+
+    (near, (far - near).normalize())
+}
+
+/// Turns a position in normalized device coordinates back into model space.
+pub fn unproject(
+    ndc: &Vec3,
+    mv_matrix: &nalgebra_glm::Mat4,
+    p_matrix: &nalgebra_glm::Mat4,
+) -> Vec3 {
+    let inverse = (p_matrix * mv_matrix)
+        .try_inverse()
+        .unwrap_or_else(nalgebra_glm::Mat4::identity);
+    let clip = inverse * nalgebra_glm::vec4(ndc.x, ndc.y, ndc.z, 1.0);
+    clip.xyz() / clip.w
+}
+
 pub fn set_uniform_matrices(
     gl: &WebGl2RenderingContext,
     program: &WebGlProgram,
     mv_matrix: &nalgebra_glm::Mat4,
     p_matrix: &nalgebra_glm::Mat4,
 ) {
-    let mv_matrix_location = gl.get_uniform_location(&program, "uMVMatrix").unwrap();
-    let p_matrix_location = gl.get_uniform_location(&program, "uPMatrix").unwrap();
+    let mv_matrix_location = gl.get_uniform_location(program, "uMVMatrix").unwrap();
+    let p_matrix_location = gl.get_uniform_location(program, "uPMatrix").unwrap();
     gl.uniform_matrix4fv_with_f32_array(Some(&mv_matrix_location), false, mv_matrix.as_slice());
     gl.uniform_matrix4fv_with_f32_array(Some(&p_matrix_location), false, p_matrix.as_slice());
-}
\ No newline at end of file
+}
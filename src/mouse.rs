@@ -1,6 +1,28 @@
+use nalgebra_glm::Vec2;
 use std::{cell::RefCell, rc::Rc};
 use wasm_bindgen::prelude::*;
-use web_sys::{HtmlCanvasElement, MouseEvent};
+use web_sys::{HtmlCanvasElement, KeyboardEvent, MouseEvent};
+
+use crate::selection::SelectionOp;
+
+/// What dragging on the canvas does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionTool {
+    Rotate,
+    Rectangle,
+    Lasso,
+}
+
+impl SelectionTool {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rotate" => Some(SelectionTool::Rotate),
+            "rectangle" => Some(SelectionTool::Rectangle),
+            "lasso" => Some(SelectionTool::Lasso),
+            _ => None,
+        }
+    }
+}
 
 pub struct MouseState {
     pub is_dragging: bool,
@@ -8,12 +30,66 @@ pub struct MouseState {
     pub last_y: f32,
     pub rotation_x: f32,
     pub rotation_y: f32,
+    // Pixels moved since the last mousedown, used to tell a click from a rotation drag
+    pub drag_distance: f32,
+    pub selection_tool: SelectionTool,
+    // Positions dragged through with a selection tool, in normalized device coordinates.
+    // A rectangle keeps only the corner it was started from and the current one
+    pub selection_path: Vec<Vec2>,
+    // Modifier keys currently held, which decide how new selections are combined
+    pub shift_key: bool,
+    pub alt_key: bool,
+}
+
+impl MouseState {
+    pub fn selection_op(&self) -> SelectionOp {
+        SelectionOp::from_modifiers(self.shift_key, self.alt_key)
+    }
+
+    /// The corners of the selection being dragged, or an empty list if there are too few
+    /// to enclose anything.
+    pub fn selection_outline(&self) -> Vec<Vec2> {
+        match (self.selection_tool, self.selection_path.as_slice()) {
+            (SelectionTool::Rectangle, [corner, opposite_corner]) => vec![
+                *corner,
+                Vec2::new(opposite_corner.x, corner.y),
+                *opposite_corner,
+                Vec2::new(corner.x, opposite_corner.y),
+            ],
+            (SelectionTool::Lasso, path) if path.len() >= 3 => path.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// The event's position on the canvas in normalized device coordinates
+fn event_ndc(event: &MouseEvent) -> Vec2 {
+    let canvas = event
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlCanvasElement>().ok());
+    let (width, height) = canvas.map_or((1.0, 1.0), |canvas| {
+        (
+            canvas.client_width().max(1) as f32,
+            canvas.client_height().max(1) as f32,
+        )
+    });
+    Vec2::new(
+        2.0 * event.offset_x() as f32 / width - 1.0,
+        1.0 - 2.0 * event.offset_y() as f32 / height,
+    )
 }
 
 fn mouse_down_handler(event: MouseEvent, mouse_state: &mut MouseState) {
     mouse_state.is_dragging = true;
+    mouse_state.shift_key = event.shift_key();
+    mouse_state.alt_key = event.alt_key();
     mouse_state.last_x = event.client_x() as f32;
     mouse_state.last_y = event.client_y() as f32;
+    mouse_state.drag_distance = 0.0;
+    mouse_state.selection_path.clear();
+    if mouse_state.selection_tool != SelectionTool::Rotate {
+        mouse_state.selection_path.push(event_ndc(&event));
+    }
 }
 
 fn mouse_move_handler(event: MouseEvent, mouse_state: &mut MouseState) {
@@ -22,15 +98,46 @@ fn mouse_move_handler(event: MouseEvent, mouse_state: &mut MouseState) {
         let delta_y = event.client_y() as f32 - mouse_state.last_y;
         mouse_state.last_x = event.client_x() as f32;
         mouse_state.last_y = event.client_y() as f32;
-        mouse_state.rotation_x += delta_y * 0.01;
-        mouse_state.rotation_y += delta_x * 0.01;
+        mouse_state.drag_distance += delta_x.abs() + delta_y.abs();
+        match mouse_state.selection_tool {
+            SelectionTool::Rotate => {
+                mouse_state.rotation_x += delta_y * 0.01;
+                mouse_state.rotation_y += delta_x * 0.01;
+            }
+            SelectionTool::Rectangle => {
+                mouse_state.selection_path.truncate(1);
+                mouse_state.selection_path.push(event_ndc(&event));
+            }
+            SelectionTool::Lasso => mouse_state.selection_path.push(event_ndc(&event)),
+        }
     }
 }
 
-fn mouse_up_handler(event: MouseEvent, mouse_state: &mut MouseState) {
+fn mouse_up_handler(_event: MouseEvent, mouse_state: &mut MouseState) {
     mouse_state.is_dragging = false;
 }
 
+fn modifier_key_handler(event: KeyboardEvent, mouse_state: &mut MouseState) {
+    mouse_state.shift_key = event.shift_key();
+    mouse_state.alt_key = event.alt_key();
+}
+
+fn add_modifier_key_listeners(mouse_state: Rc<RefCell<MouseState>>) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or("No global `window` exists")?;
+    let modifier_key_handler = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+        let mut mouse_state = mouse_state.borrow_mut();
+        modifier_key_handler(event, &mut mouse_state);
+    }) as Box<dyn FnMut(_)>);
+    for event_name in ["keydown", "keyup"] {
+        window.add_event_listener_with_callback(
+            event_name,
+            modifier_key_handler.as_ref().unchecked_ref(),
+        )?;
+    }
+    modifier_key_handler.forget();
+    Ok(())
+}
+
 fn add_mouse_down_listener(
     canvas: &HtmlCanvasElement,
     mouse_state: Rc<RefCell<MouseState>>,
@@ -87,11 +194,17 @@ pub fn create_mouse_state(canvas: &HtmlCanvasElement) -> Result<Rc<RefCell<Mouse
         last_y: 0.0,
         rotation_x: 0.0,
         rotation_y: 0.0,
+        drag_distance: 0.0,
+        selection_tool: SelectionTool::Rotate,
+        selection_path: Vec::new(),
+        shift_key: false,
+        alt_key: false,
     }));
 
     add_mouse_down_listener(canvas, mouse_state.clone())?;
     add_mouse_move_listener(canvas, mouse_state.clone())?;
     add_mouse_up_listener(canvas, mouse_state.clone())?;
+    add_modifier_key_listeners(mouse_state.clone())?;
 
     Ok(mouse_state)
 }
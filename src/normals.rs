@@ -0,0 +1,119 @@
+// normals.rs
+use nalgebra_glm::{Mat3, Vec3};
+
+use crate::octree::{get_point, NORMAL_OFFSET, VERTEX_STRIDE};
+use crate::spatial_index::SpatialIndex;
+
+/// Fits a plane to `points` by principal component analysis. Returns the centroid and the
+/// unit normal, the direction in which the points vary least, or `None` if there are fewer
+/// than three points.
+pub fn fit_plane(points: &[Vec3]) -> Option<(Vec3, Vec3)> {
+    if points.len() < 3 {
+        return None;
+    }
+    let centroid = points.iter().sum::<Vec3>() / points.len() as f32;
+    let mut covariance = Mat3::zeros();
+    for point in points {
+        let offset = point - centroid;
+        covariance += offset * offset.transpose();
+    }
+
+    let eigen = covariance.symmetric_eigen();
+    let smallest = eigen.eigenvalues.imin();
+    let normal = eigen.eigenvectors.column(smallest).into_owned();
+    normal
+        .try_normalize(f32::EPSILON)
+        .map(|normal| (centroid, normal))
+}
+
+/// Estimates each point's surface normal by fitting a plane to it and its `k` nearest
+/// neighbours, and flips every normal to face `viewpoint`, the scanner or camera position.
+/// Points with too few neighbours for a plane get a zero normal.
+pub fn estimate_normals(
+    index: &dyn SpatialIndex,
+    vertex_points: &[f32],
+    k: usize,
+    viewpoint: &Vec3,
+) -> Vec<Vec3> {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    (0..num_points)
+        .map(|point_index| {
+            let point = get_point(vertex_points, point_index);
+            // The point itself comes back as its own nearest neighbour
+            let neighbours: Vec<Vec3> = index
+                .knn(&point, k + 1, vertex_points)
+                .into_iter()
+                .map(|(i, _)| get_point(vertex_points, i))
+                .collect();
+            match fit_plane(&neighbours) {
+                Some((_, normal)) if normal.dot(&(viewpoint - point)) < 0.0 => -normal,
+                Some((_, normal)) => normal,
+                None => Vec3::zeros(),
+            }
+        })
+        .collect()
+}
+
+/// Writes `normals` into the normal slots of the point buffer, one per point.
+pub fn set_normals(vertex_points: &mut [f32], normals: &[Vec3]) {
+    for (vertex, normal) in vertex_points.chunks_exact_mut(VERTEX_STRIDE).zip(normals) {
+        vertex[NORMAL_OFFSET..NORMAL_OFFSET + 3].copy_from_slice(normal.as_slice());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{Octree, OctreeConfig};
+
+    // Points on the sphere of radius 1 around the origin
+    fn sphere_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize();
+            vertex_points.extend_from_slice(direction.as_slice());
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    #[test]
+    fn fits_a_tilted_plane() {
+        let normal = Vec3::new(1.0, 2.0, 3.0).normalize();
+        let u = normal.cross(&Vec3::x()).normalize();
+        let v = normal.cross(&u);
+        let points: Vec<Vec3> = (0..25)
+            .map(|i| Vec3::new(4.0, 0.0, -1.0) + u * (i % 5) as f32 + v * (i / 5) as f32)
+            .collect();
+
+        let (_, fitted) = fit_plane(&points).unwrap();
+
+        assert!(fitted.dot(&normal).abs() > 0.9999);
+        assert!(fit_plane(&points[..2]).is_none());
+    }
+
+    #[test]
+    fn sphere_normals_point_outwards_towards_an_outside_viewpoint() {
+        let mut vertex_points = sphere_points(4000);
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+
+        // Seen from far away, every normal should face out of the sphere, along its radius
+        let normals = estimate_normals(&octree, &vertex_points, 12, &Vec3::new(0.0, 0.0, 100.0));
+        let facing_camera = (0..4000)
+            .filter(|&i| get_point(&vertex_points, i).z > 0.2)
+            .all(|i| normals[i].dot(&get_point(&vertex_points, i)) > 0.95);
+        assert!(facing_camera);
+
+        set_normals(&mut vertex_points, &normals);
+        let offset = 17 * VERTEX_STRIDE + NORMAL_OFFSET;
+        assert_eq!(&vertex_points[offset..offset + 3], normals[17].as_slice());
+    }
+}
@@ -1,103 +1,339 @@
 // octree.rs
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 use nalgebra_glm::Vec3;
 
-#[derive(Clone)] // Add this line
+use crate::frustum::{Containment, Frustum};
+use crate::region::Region;
+use crate::spatial_index::{
+    aabb_contains, aabb_distance, aabb_overlaps, ray_aabb_entry, ray_point_hit, HeapEntry,
+    SpatialIndex,
+};
+
+mod downsample;
+mod lod;
+mod morton;
+mod serialize;
+mod stats;
+
+pub use downsample::VoxelRepresentative;
+pub use lod::LodCamera;
+pub use morton::reorder_points;
+pub use stats::OctreeStats;
+
+/// Number of floats per vertex in the point buffer: x, y, z, r, g, b, nx, ny, nz.
+pub const VERTEX_STRIDE: usize = 9;
+/// Offset of a vertex's color within the point buffer.
+pub const COLOR_OFFSET: usize = 3;
+/// Offset of a vertex's normal within the point buffer. Normals are zero until
+/// `normals::estimate_normals` has been run.
+pub const NORMAL_OFFSET: usize = 6;
+
+/// Reads the position of a point out of the interleaved vertex buffer.
+pub fn get_point(vertex_points: &[f32], point_index: usize) -> Vec3 {
+    let offset = point_index * VERTEX_STRIDE;
+    Vec3::new(
+        vertex_points[offset],
+        vertex_points[offset + 1],
+        vertex_points[offset + 2],
+    )
+}
+
+/// Returns the minimum and maximum corners of the box enclosing every point in the
+/// vertex buffer, or `None` if it is empty.
+pub fn compute_bounds(vertex_points: &[f32]) -> Option<(Vec3, Vec3)> {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    (0..num_points)
+        .map(|i| get_point(vertex_points, i))
+        .fold(None, |bounds, point| {
+            Some(match bounds {
+                None => (point, point),
+                Some((min, max)) => (min.inf(&point), max.sup(&point)),
+            })
+        })
+}
+
+/// Controls when a leaf is split into eight children.
+#[derive(Clone, Copy, Debug)]
+pub struct OctreeConfig {
+    /// Nodes at this depth are never subdivided. The root is at depth 0. Growing the root
+    /// raises this by one, so the smallest nodes stay the same size.
+    pub max_depth: usize,
+    /// A leaf holding more points than this is subdivided.
+    pub max_points_per_leaf: usize,
+    /// Nodes are not subdivided if their children would be smaller than this.
+    pub min_node_size: f32,
+    /// Whether inserting a point outside the root cube doubles the root until it fits.
+    /// Otherwise such points are turned away by `insert`.
+    pub grow_root: bool,
+}
+
+impl Default for OctreeConfig {
+    fn default() -> Self {
+        OctreeConfig {
+            max_depth: 10,
+            max_points_per_leaf: 256,
+            min_node_size: 0.001,
+            grow_root: true,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Octree {
     center: Vec3,
     size: f32,
+    depth: usize,
+    config: OctreeConfig,
     point_indices: Vec<usize>,
+    // Representative subset of an interior node's points, filled in by `build_lod`
+    lod_samples: Vec<usize>,
     children: Option<Box<[Octree; 8]>>,
 }
 
 impl Octree {
-    pub fn new(center: Vec3, size: f32) -> Self {
+    pub fn with_config(center: Vec3, size: f32, config: OctreeConfig) -> Self {
+        Octree::new_node(center, size, 0, config)
+    }
+
+    /// Creates an empty root cube enclosing the box `[min, max]`, enlarged by `padding` (a
+    /// fraction of its largest extent) on every side.
+    pub fn from_bounds(min: &Vec3, max: &Vec3, padding: f32, config: OctreeConfig) -> Self {
+        let extent = (max - min).max();
+        // A single point, or points on one spot, still needs a cube with some volume
+        let extent = if extent > 0.0 { extent } else { 1.0 };
+        Octree::with_config((min + max) / 2.0, extent * (1.0 + 2.0 * padding), config)
+    }
+
+    fn new_node(center: Vec3, size: f32, depth: usize, config: OctreeConfig) -> Self {
         Octree {
             center,
             size,
+            depth,
+            config,
             point_indices: Vec::new(),
+            lod_samples: Vec::new(),
             children: None,
         }
     }
 
-    // Adjust the insert method to accept a point index and the point itself
-    // The point is needed to determine the correct child, but only the index is stored
-    pub fn insert(&mut self, point_index: usize, point: Vec3) {
-        if self.size <= 1.0 {
-            self.point_indices.push(point_index);
+    /// Adds `point_index` to the tree. Only the index is stored; the position is read from
+    /// `vertex_points` to pick the child and to redistribute the leaf's points when it is
+    /// split.
+    ///
+    /// Returns false, without storing the point, if it lies outside the root cube and the
+    /// root can't grow around it: always when `grow_root` is off, and for points with a
+    /// non-finite coordinate. Queries skip nodes by their cubes, so a point filed into a
+    /// node that doesn't contain it could be missed or wrongly matched.
+    pub fn insert(&mut self, point_index: usize, vertex_points: &[f32]) -> bool {
+        let point = get_point(vertex_points, point_index);
+        if self.config.grow_root && point.iter().all(|c| c.is_finite()) {
+            while !self.contains(&point) {
+                self.grow_towards(&point);
+            }
+        }
+        if !self.contains(&point) {
+            return false;
+        }
+        self.insert_node(point_index, vertex_points);
+        true
+    }
+
+    fn insert_node(&mut self, point_index: usize, vertex_points: &[f32]) {
+        let point = get_point(vertex_points, point_index);
+        let child_index = self.get_child_index(point);
+
+        if let Some(children) = self.children.as_mut() {
+            children[child_index].insert_node(point_index, vertex_points);
             return;
         }
 
-        if self.children.is_none() {
-            self.children = Some(Box::new([
-                Octree::new(
-                    self.center + Vec3::new(-self.size / 4.0, -self.size / 4.0, -self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(self.size / 4.0, -self.size / 4.0, -self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(-self.size / 4.0, self.size / 4.0, -self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(self.size / 4.0, self.size / 4.0, -self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(-self.size / 4.0, -self.size / 4.0, self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(self.size / 4.0, -self.size / 4.0, self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(-self.size / 4.0, self.size / 4.0, self.size / 4.0),
-                    self.size / 2.0,
-                ),
-                Octree::new(
-                    self.center + Vec3::new(self.size / 4.0, self.size / 4.0, self.size / 4.0),
-                    self.size / 2.0,
-                ),
-            ]));
+        self.point_indices.push(point_index);
+        if self.should_subdivide() {
+            self.subdivide(vertex_points);
         }
+    }
 
-        let child_index = self.get_child_index(point);
-        self.children.as_mut().unwrap()[child_index].insert(point_index, point);
+    fn should_subdivide(&self) -> bool {
+        self.point_indices.len() > self.config.max_points_per_leaf
+            && self.depth < self.config.max_depth
+            && self.size / 2.0 >= self.config.min_node_size
     }
 
-    fn get_child_index(&self, point: Vec3) -> usize {
-        let mut index = 0;
-        if point.x > self.center.x {
-            index += 1;
+    // Turns this leaf into an interior node and moves its points down into the new children
+    fn subdivide(&mut self, vertex_points: &[f32]) {
+        let children: [Octree; 8] = std::array::from_fn(|i| self.new_child(i));
+        self.children = Some(Box::new(children));
+
+        for point_index in std::mem::take(&mut self.point_indices) {
+            self.insert_node(point_index, vertex_points);
+        }
+    }
+
+    /// Returns whether `point` lies inside this node's cube.
+    pub fn contains(&self, point: &Vec3) -> bool {
+        let (min_pos, max_pos) = self.bounds();
+        aabb_contains(&min_pos, &max_pos, point)
+    }
+
+    // Doubles the root cube in the direction of `point`, keeping the old root as one of
+    // the new root's children
+    fn grow_towards(&mut self, point: &Vec3) {
+        let half = self.size / 2.0;
+        let direction = Vec3::new(
+            if point.x >= self.center.x {
+                half
+            } else {
+                -half
+            },
+            if point.y >= self.center.y {
+                half
+            } else {
+                -half
+            },
+            if point.z >= self.center.z {
+                half
+            } else {
+                -half
+            },
+        );
+        // The old root's nodes all move one level down, so every node allows one more level
+        // to keep the same finest resolution
+        let config = OctreeConfig {
+            max_depth: self.config.max_depth + 1,
+            ..self.config
+        };
+        let mut new_root =
+            Octree::new_node(self.center + direction, self.size * 2.0, self.depth, config);
+        let old_root_index = new_root.get_child_index(self.center);
+
+        let mut children: [Octree; 8] = std::array::from_fn(|i| new_root.new_child(i));
+        std::mem::swap(&mut children[old_root_index], self);
+        children[old_root_index].increment_depth();
+        new_root.children = Some(Box::new(children));
+        *self = new_root;
+    }
+
+    // Creates the empty child occupying octant `child_index`, numbered as in `get_child_index`
+    fn new_child(&self, child_index: usize) -> Octree {
+        Octree::new_node(
+            child_center(&self.center, self.size, child_index),
+            self.size / 2.0,
+            self.depth + 1,
+            self.config,
+        )
+    }
+
+    fn increment_depth(&mut self) {
+        self.depth += 1;
+        self.config.max_depth += 1;
+        if let Some(children) = self.children.as_mut() {
+            for child in children.iter_mut() {
+                child.increment_depth();
+            }
+        }
+    }
+
+    /// Removes `point_index`, which must have been inserted at position `point`. Children
+    /// left holding few enough points to fit in one leaf are merged back into their parent,
+    /// and the point is dropped from its ancestors' LOD samples. Returns false if the index
+    /// was not found.
+    pub fn remove(&mut self, point_index: usize, point: &Vec3) -> bool {
+        let child_index = self.get_child_index(*point);
+        let Some(children) = self.children.as_mut() else {
+            return match self.point_indices.iter().position(|&i| i == point_index) {
+                Some(position) => {
+                    self.point_indices.swap_remove(position);
+                    true
+                }
+                None => false,
+            };
+        };
+
+        if !children[child_index].remove(point_index, point) {
+            return false;
+        }
+        self.lod_samples.retain(|&i| i != point_index);
+        self.collapse_if_sparse();
+        true
+    }
+
+    /// Moves `point_index` from `old` to `new`. `vertex_points` must already hold the new
+    /// position, as it is read back if the point moves into a leaf that has to split.
+    /// Returns false if the index was not found at `old`, or if `insert` turns the new
+    /// position away, in which case the point is left out of the tree.
+    pub fn update(
+        &mut self,
+        point_index: usize,
+        old: &Vec3,
+        new: &Vec3,
+        vertex_points: &[f32],
+    ) -> bool {
+        // A point leaving the root goes through `insert`, which grows the root around it or
+        // turns it away
+        let leaves_root = !self.contains(new);
+        let leaf = self.leaf_for(old);
+        if !leaves_root && std::ptr::eq(leaf, self.leaf_for(new)) {
+            // The point stays in the same leaf, and leaves only store indices
+            return leaf.point_indices.contains(&point_index);
+        }
+        if !self.remove(point_index, old) {
+            return false;
         }
-        if point.y > self.center.y {
-            index += 2;
+        self.insert(point_index, vertex_points)
+    }
+
+    // Returns the leaf whose cube `point` falls into
+    fn leaf_for(&self, point: &Vec3) -> &Octree {
+        match self.children.as_ref() {
+            None => self,
+            Some(children) => children[self.get_child_index(*point)].leaf_for(point),
         }
-        if point.z > self.center.z {
-            index += 4;
+    }
+
+    fn collapse_if_sparse(&mut self) {
+        let Some(children) = self.children.as_ref() else {
+            return;
+        };
+        if children.iter().any(|child| child.children.is_some()) {
+            return;
+        }
+        if children
+            .iter()
+            .map(|child| child.point_indices.len())
+            .sum::<usize>()
+            > self.config.max_points_per_leaf
+        {
+            return;
         }
-        index
+
+        let children = self.children.take().unwrap();
+        self.lod_samples.clear();
+        for child in children.iter() {
+            self.point_indices.extend_from_slice(&child.point_indices);
+        }
+    }
+
+    fn get_child_index(&self, point: Vec3) -> usize {
+        child_index_of(&self.center, &point)
     }
 
     pub fn get_vertices(&self, vertices: &mut Vec<f32>) {
-        if self.children.is_none() {
+        match self.children.as_ref() {
             // If the current node is a leaf node, generate its cube vertices
-            self.generate_cube_vertices(vertices);
-        } else {
+            None => self.generate_cube_vertices(vertices),
             // If the current node has children, recursively get the vertices of the child nodes
-            for child in self.children.as_ref().unwrap().iter() {
-                child.get_vertices(vertices);
+            Some(children) => {
+                for child in children.iter() {
+                    child.get_vertices(vertices);
+                }
             }
         }
     }
 
     fn generate_cube_vertices(&self, vertices: &mut Vec<f32>) {
-        let half_size = self.size / 2.0;
-        let min_pos = self.center - Vec3::new(half_size, half_size, half_size);
-        let max_pos = self.center + Vec3::new(half_size, half_size, half_size);
+        let (min_pos, max_pos) = self.bounds();
 
         // Generate the vertices of the cube as triangles in counterclockwise order (when viewed from outside)
 
@@ -161,21 +397,19 @@ impl Octree {
             min_pos.x, min_pos.y, max_pos.z, 0.5, 0.5, 0.5, // Back-left
         ]);
     }
-    /// Returns the number of cubes in the octree.
-    /// This includes both leaf and non-leaf nodes.
-    pub fn get_num_cubes(&self) -> usize {
-        if self.children.is_none() {
-            // If there are no children, this is a leaf node, so return 1
-            1
-        } else {
-            // If there are children, count this node plus all children recursively
-            1 + self
-                .children
-                .as_ref()
-                .unwrap()
-                .iter()
-                .map(|child| child.get_num_cubes())
-                .sum::<usize>()
+    /// Returns the number of points stored in this node and its descendants.
+    pub fn num_points(&self) -> usize {
+        match self.children.as_ref() {
+            None => self.point_indices.len(),
+            Some(children) => children.iter().map(|child| child.num_points()).sum(),
+        }
+    }
+
+    /// Returns the number of leaf nodes, i.e. the number of cubes emitted by `get_vertices`.
+    pub fn get_num_leaves(&self) -> usize {
+        match self.children.as_ref() {
+            None => 1,
+            Some(children) => children.iter().map(|child| child.get_num_leaves()).sum(),
         }
     }
 
@@ -186,42 +420,885 @@ impl Octree {
             return point_indices;
         }
 
-        if self.children.is_none() {
-            for &point_index in &self.point_indices {
-                // Each vertex point consists of 6 values: x, y, z, r, g, b
-                // Calculate the offset to access the x, y, z values of the point
-                let offset = point_index * 6;
-                let point = Vec3::new(
-                    vertex_points[offset],
-                    vertex_points[offset + 1],
-                    vertex_points[offset + 2],
-                );
+        match self.children.as_ref() {
+            None => {
+                for &point_index in &self.point_indices {
+                    let point = get_point(vertex_points, point_index);
+                    if nalgebra_glm::distance(&point, center) <= radius {
+                        point_indices.push(point_index);
+                    }
+                }
+            }
+            Some(children) => {
+                for child in children.iter() {
+                    point_indices.extend(child.query_sphere(center, radius, vertex_points));
+                }
+            }
+        }
+
+        point_indices
+    }
+
+    /// Returns the indices of all points inside the axis-aligned box `[min, max]`.
+    /// Nodes entirely outside the box are skipped, and nodes entirely inside it are
+    /// accepted wholesale without testing their points.
+    pub fn query_aabb(&self, min: &Vec3, max: &Vec3, vertex_points: &[f32]) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        self.query_aabb_into(min, max, vertex_points, &mut point_indices);
+        point_indices
+    }
+
+    fn query_aabb_into(
+        &self,
+        min: &Vec3,
+        max: &Vec3,
+        vertex_points: &[f32],
+        point_indices: &mut Vec<usize>,
+    ) {
+        let (node_min, node_max) = self.bounds();
+        if !aabb_overlaps(&node_min, &node_max, min, max) {
+            return;
+        }
+        if aabb_contains(min, max, &node_min) && aabb_contains(min, max, &node_max) {
+            self.collect_point_indices(point_indices);
+            return;
+        }
 
-                if nalgebra_glm::distance(&point, center) <= radius {
-                    point_indices.push(point_index);
+        match self.children.as_ref() {
+            None => {
+                for &point_index in &self.point_indices {
+                    let point = get_point(vertex_points, point_index);
+                    if aabb_contains(min, max, &point) {
+                        point_indices.push(point_index);
+                    }
                 }
             }
-        } else {
-            for child in self.children.as_ref().unwrap().iter() {
-                point_indices.extend(child.query_sphere(center, radius, vertex_points));
+            Some(children) => {
+                for child in children.iter() {
+                    child.query_aabb_into(min, max, vertex_points, point_indices);
+                }
             }
         }
+    }
 
+    /// Returns the indices of all points inside `region`. Nodes outside it are skipped,
+    /// nodes inside it are accepted wholesale, and points in the remaining leaves are
+    /// tested one by one.
+    pub fn query_region(&self, region: &dyn Region, vertex_points: &[f32]) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        self.query_region_into(region, vertex_points, &mut point_indices);
         point_indices
     }
 
+    fn query_region_into(
+        &self,
+        region: &dyn Region,
+        vertex_points: &[f32],
+        point_indices: &mut Vec<usize>,
+    ) {
+        let (min_pos, max_pos) = self.bounds();
+        match region.classify_aabb(&min_pos, &max_pos) {
+            Containment::Outside => {}
+            Containment::Inside => self.collect_point_indices(point_indices),
+            Containment::Intersecting => match self.children.as_ref() {
+                None => {
+                    for &point_index in &self.point_indices {
+                        if region.contains_point(&get_point(vertex_points, point_index)) {
+                            point_indices.push(point_index);
+                        }
+                    }
+                }
+                Some(children) => {
+                    for child in children.iter() {
+                        child.query_region_into(region, vertex_points, point_indices);
+                    }
+                }
+            },
+        }
+    }
 
-    fn intersects_sphere(&self, center: &Vec3, radius: f32) -> bool {
+    /// Returns the indices of all points in nodes that are at least partly inside the
+    /// frustum. Culling is per node: leaves straddling a plane are returned whole, so a
+    /// few points just outside the view may be included.
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        self.query_frustum_into(frustum, &mut point_indices);
+        point_indices
+    }
+
+    fn query_frustum_into(&self, frustum: &Frustum, point_indices: &mut Vec<usize>) {
+        let (min_pos, max_pos) = self.bounds();
+        match frustum.classify_aabb(&min_pos, &max_pos) {
+            Containment::Outside => {}
+            Containment::Inside => self.collect_point_indices(point_indices),
+            Containment::Intersecting => match self.children.as_ref() {
+                None => point_indices.extend_from_slice(&self.point_indices),
+                Some(children) => {
+                    for child in children.iter() {
+                        child.query_frustum_into(frustum, point_indices);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Appends the indices of every point stored in this node and its descendants.
+    pub fn collect_point_indices(&self, point_indices: &mut Vec<usize>) {
+        match self.children.as_ref() {
+            None => point_indices.extend_from_slice(&self.point_indices),
+            Some(children) => {
+                for child in children.iter() {
+                    child.collect_point_indices(point_indices);
+                }
+            }
+        }
+    }
+
+    /// Returns the minimum and maximum corners of this node's cube.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
         let half_size = self.size / 2.0;
-        let min_pos = self.center - Vec3::new(half_size, half_size, half_size);
-        let max_pos = self.center + Vec3::new(half_size, half_size, half_size);
+        let half_extent = Vec3::new(half_size, half_size, half_size);
+        (self.center - half_extent, self.center + half_extent)
+    }
+
+    /// Returns the `k` points closest to `query` as `(point_index, distance)` pairs,
+    /// nearest first. Nodes are visited in order of their distance to `query`, and the
+    /// search stops once no unvisited node can hold a closer point than the current `k`th.
+    pub fn knn(&self, query: &Vec3, k: usize, vertex_points: &[f32]) -> Vec<(usize, f32)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        // Max-heap of the best candidates so far, so the worst one is cheap to evict
+        let mut nearest: BinaryHeap<HeapEntry<usize>> = BinaryHeap::with_capacity(k + 1);
+        // Min-heap of nodes still to visit, keyed by their distance to the query
+        let mut pending: BinaryHeap<Reverse<HeapEntry<&Octree>>> = BinaryHeap::new();
+        pending.push(Reverse(HeapEntry {
+            distance: self.distance_to(query),
+            item: self,
+        }));
+
+        while let Some(Reverse(HeapEntry {
+            distance,
+            item: node,
+        })) = pending.pop()
+        {
+            if nearest.len() == k && distance > nearest.peek().unwrap().distance {
+                break;
+            }
+
+            match node.children.as_ref() {
+                None => {
+                    for &point_index in &node.point_indices {
+                        let point = get_point(vertex_points, point_index);
+                        let distance = nalgebra_glm::distance(&point, query);
+                        if nearest.len() < k {
+                            nearest.push(HeapEntry {
+                                distance,
+                                item: point_index,
+                            });
+                        } else if distance < nearest.peek().unwrap().distance {
+                            nearest.pop();
+                            nearest.push(HeapEntry {
+                                distance,
+                                item: point_index,
+                            });
+                        }
+                    }
+                }
+                Some(children) => {
+                    for child in children.iter() {
+                        pending.push(Reverse(HeapEntry {
+                            distance: child.distance_to(query),
+                            item: child,
+                        }));
+                    }
+                }
+            }
+        }
+
+        nearest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| (entry.item, entry.distance))
+            .collect()
+    }
+
+    /// Casts a ray through the cloud and returns the point closest to `ray_origin` whose
+    /// perpendicular distance to the ray is at most `pick_radius`, as
+    /// `(point_index, distance_along_ray)`. Children are visited front to back, so nodes
+    /// behind the current best hit are never opened.
+    pub fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+    ) -> Option<(usize, f32)> {
+        let ray_dir = ray_dir.normalize();
+        let mut best = None;
+        self.pick_into(ray_origin, &ray_dir, pick_radius, vertex_points, &mut best);
+        best
+    }
+
+    fn pick_into(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+        best: &mut Option<(usize, f32)>,
+    ) {
+        match self.children.as_ref() {
+            None => {
+                for &point_index in &self.point_indices {
+                    let point = get_point(vertex_points, point_index);
+                    let Some(along_ray) = ray_point_hit(&point, ray_origin, ray_dir, pick_radius)
+                    else {
+                        continue;
+                    };
+                    if best.map_or(true, |(_, best_along_ray)| along_ray < best_along_ray) {
+                        *best = Some((point_index, along_ray));
+                    }
+                }
+            }
+            Some(children) => {
+                let mut hits: Vec<(f32, &Octree)> = children
+                    .iter()
+                    .filter_map(|child| {
+                        child
+                            .ray_entry(ray_origin, ray_dir, pick_radius)
+                            .map(|entry| (entry, child))
+                    })
+                    .collect();
+                hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                for (entry, child) in hits {
+                    // The node is inflated by `pick_radius`, so the ray is inside it wherever it
+                    // passes close enough to one of its points: no hit can be nearer than `entry`
+                    if let Some((_, best_along_ray)) = *best {
+                        if entry > best_along_ray {
+                            break;
+                        }
+                    }
+                    child.pick_into(ray_origin, ray_dir, pick_radius, vertex_points, best);
+                }
+            }
+        }
+    }
+
+    fn ray_entry(&self, ray_origin: &Vec3, ray_dir: &Vec3, padding: f32) -> Option<f32> {
+        let (min_pos, max_pos) = self.bounds();
+        ray_aabb_entry(&min_pos, &max_pos, ray_origin, ray_dir, padding)
+    }
 
-        let closest_point = Vec3::new(
-            center.x.clamp(min_pos.x, max_pos.x),
-            center.y.clamp(min_pos.y, max_pos.y),
-            center.z.clamp(min_pos.z, max_pos.z),
+    /// Returns the distance from `point` to the closest point of this node's cube,
+    /// or 0 if `point` lies inside it.
+    pub fn distance_to(&self, point: &Vec3) -> f32 {
+        let (min_pos, max_pos) = self.bounds();
+        aabb_distance(&min_pos, &max_pos, point)
+    }
+
+    fn intersects_sphere(&self, center: &Vec3, radius: f32) -> bool {
+        self.distance_to(center) <= radius
+    }
+}
+
+// Octants are numbered with x in bit 0, y in bit 1 and z in bit 2
+fn child_index_of(center: &Vec3, point: &Vec3) -> usize {
+    let mut index = 0;
+    if point.x > center.x {
+        index += 1;
+    }
+    if point.y > center.y {
+        index += 2;
+    }
+    if point.z > center.z {
+        index += 4;
+    }
+    index
+}
+
+fn child_center(center: &Vec3, size: f32, child_index: usize) -> Vec3 {
+    let quarter = size / 4.0;
+    let offset = Vec3::new(
+        if child_index & 1 != 0 {
+            quarter
+        } else {
+            -quarter
+        },
+        if child_index & 2 != 0 {
+            quarter
+        } else {
+            -quarter
+        },
+        if child_index & 4 != 0 {
+            quarter
+        } else {
+            -quarter
+        },
+    );
+    center + offset
+}
+
+impl SpatialIndex for Octree {
+    fn query_sphere(&self, center: &Vec3, radius: f32, vertex_points: &[f32]) -> Vec<usize> {
+        Octree::query_sphere(self, center, radius, vertex_points)
+    }
+
+    fn query_aabb(&self, min: &Vec3, max: &Vec3, vertex_points: &[f32]) -> Vec<usize> {
+        Octree::query_aabb(self, min, max, vertex_points)
+    }
+
+    fn query_region(&self, region: &dyn Region, vertex_points: &[f32]) -> Vec<usize> {
+        Octree::query_region(self, region, vertex_points)
+    }
+
+    fn knn(&self, query: &Vec3, k: usize, vertex_points: &[f32]) -> Vec<(usize, f32)> {
+        Octree::knn(self, query, k, vertex_points)
+    }
+
+    fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+    ) -> Option<(usize, f32)> {
+        Octree::pick(self, ray_origin, ray_dir, pick_radius, vertex_points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::polytope::ConvexPolytope;
+
+    fn random_points(num_points: usize, seed: u64) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            for _ in 0..3 {
+                vertex_points.push(rng.gen_range(-1.0..1.0));
+            }
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    fn insert_all(vertex_points: &[f32], config: OctreeConfig) -> Octree {
+        let mut octree = Octree::with_config(Vec3::zeros(), 2.0, config);
+        for point_index in 0..vertex_points.len() / VERTEX_STRIDE {
+            octree.insert(point_index, vertex_points);
+        }
+        octree
+    }
+
+    fn leaves(octree: &Octree) -> Vec<&Octree> {
+        match octree.children.as_ref() {
+            None => vec![octree],
+            Some(children) => children.iter().flat_map(leaves).collect(),
+        }
+    }
+
+    fn cube_contains(node: &Octree, point: &Vec3) -> bool {
+        let half_size = node.size / 2.0;
+        (0..3).all(|axis| (point[axis] - node.center[axis]).abs() <= half_size)
+    }
+
+    fn sorted(mut point_indices: Vec<usize>) -> Vec<usize> {
+        point_indices.sort_unstable();
+        point_indices
+    }
+
+    #[test]
+    fn subdivides_leaves_until_a_threshold_is_reached() {
+        let vertex_points = random_points(2000, 1);
+        let configs = [
+            OctreeConfig::default(),
+            OctreeConfig {
+                max_points_per_leaf: 8,
+                ..OctreeConfig::default()
+            },
+            OctreeConfig {
+                max_depth: 2,
+                max_points_per_leaf: 8,
+                ..OctreeConfig::default()
+            },
+            OctreeConfig {
+                max_points_per_leaf: 8,
+                min_node_size: 0.3,
+                ..OctreeConfig::default()
+            },
+        ];
+
+        for config in configs {
+            let octree = insert_all(&vertex_points, config);
+            let leaves = leaves(&octree);
+            assert_eq!(octree.get_num_leaves(), leaves.len());
+
+            let mut stored: Vec<usize> = Vec::new();
+            for leaf in &leaves {
+                // A leaf over the limit must have been stopped by the depth or size limit
+                if leaf.point_indices.len() > config.max_points_per_leaf {
+                    assert!(
+                        leaf.depth == config.max_depth || leaf.size / 2.0 < config.min_node_size
+                    );
+                }
+                assert!(leaf.depth <= config.max_depth);
+                for &point_index in &leaf.point_indices {
+                    assert!(cube_contains(leaf, &get_point(&vertex_points, point_index)));
+                }
+                stored.extend(&leaf.point_indices);
+            }
+            assert_eq!(sorted(stored), (0..2000).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn query_sphere_matches_brute_force() {
+        let vertex_points = random_points(2000, 2);
+        let octree = insert_all(
+            &vertex_points,
+            OctreeConfig {
+                max_points_per_leaf: 16,
+                ..OctreeConfig::default()
+            },
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        for _ in 0..50 {
+            let center = Vec3::new(
+                rng.gen_range(-1.2..1.2),
+                rng.gen_range(-1.2..1.2),
+                rng.gen_range(-1.2..1.2),
+            );
+            let radius = rng.gen_range(0.05..0.8);
+            let expected: Vec<usize> = (0..2000)
+                .filter(|&i| {
+                    nalgebra_glm::distance(&get_point(&vertex_points, i), &center) <= radius
+                })
+                .collect();
+            assert_eq!(
+                sorted(octree.query_sphere(&center, radius, &vertex_points)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn query_aabb_matches_brute_force() {
+        let vertex_points = random_points(2000, 4);
+        let octree = insert_all(
+            &vertex_points,
+            OctreeConfig {
+                max_points_per_leaf: 16,
+                ..OctreeConfig::default()
+            },
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        for _ in 0..50 {
+            let corner = Vec3::new(
+                rng.gen_range(-1.2..1.2),
+                rng.gen_range(-1.2..1.2),
+                rng.gen_range(-1.2..1.2),
+            );
+            let extent = Vec3::new(
+                rng.gen_range(0.0..1.5),
+                rng.gen_range(0.0..1.5),
+                rng.gen_range(0.0..1.5),
+            );
+            let (min, max) = (corner, corner + extent);
+            let expected: Vec<usize> = (0..2000)
+                .filter(|&i| {
+                    let point = get_point(&vertex_points, i);
+                    (0..3).all(|axis| min[axis] <= point[axis] && point[axis] <= max[axis])
+                })
+                .collect();
+            assert_eq!(
+                sorted(octree.query_aabb(&min, &max, &vertex_points)),
+                expected
+            );
+        }
+
+        // A box around everything accepts every node wholesale
+        let everything = octree.query_aabb(&Vec3::repeat(-2.0), &Vec3::repeat(2.0), &vertex_points);
+        assert_eq!(sorted(everything), (0..2000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn knn_matches_brute_force() {
+        let vertex_points = random_points(2000, 6);
+        let octree = insert_all(
+            &vertex_points,
+            OctreeConfig {
+                max_points_per_leaf: 16,
+                ..OctreeConfig::default()
+            },
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let query = Vec3::new(
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(-1.5..1.5),
+            );
+            let k = rng.gen_range(1..40);
+            let mut expected: Vec<(usize, f32)> = (0..2000)
+                .map(|i| {
+                    (
+                        i,
+                        nalgebra_glm::distance(&get_point(&vertex_points, i), &query),
+                    )
+                })
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            expected.truncate(k);
+
+            let neighbours = octree.knn(&query, k, &vertex_points);
+            assert_eq!(neighbours.len(), k);
+            for ((_, distance), (_, expected_distance)) in neighbours.iter().zip(&expected) {
+                assert!((distance - expected_distance).abs() < 1e-6);
+            }
+        }
+
+        assert!(octree.knn(&Vec3::zeros(), 0, &vertex_points).is_empty());
+        assert_eq!(octree.knn(&Vec3::zeros(), 5000, &vertex_points).len(), 2000);
+    }
+
+    #[test]
+    fn pick_matches_brute_force() {
+        let vertex_points = random_points(2000, 8);
+        let octree = insert_all(
+            &vertex_points,
+            OctreeConfig {
+                max_points_per_leaf: 16,
+                ..OctreeConfig::default()
+            },
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+        let mut num_hits = 0;
+        for _ in 0..100 {
+            let ray_origin = Vec3::new(
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-3.0..3.0),
+            );
+            let target = Vec3::new(
+                rng.gen_range(-0.5..0.5),
+                rng.gen_range(-0.5..0.5),
+                rng.gen_range(-0.5..0.5),
+            );
+            let ray_dir = target - ray_origin;
+            let pick_radius = rng.gen_range(0.005..0.05);
+
+            let unit_dir = ray_dir.normalize();
+            let expected = (0..2000)
+                .filter_map(|i| {
+                    let point = get_point(&vertex_points, i);
+                    let along_ray = (point - ray_origin).dot(&unit_dir);
+                    let closest_on_ray = ray_origin + unit_dir * along_ray;
+                    (along_ray >= 0.0
+                        && nalgebra_glm::distance(&point, &closest_on_ray) <= pick_radius)
+                        .then_some((i, along_ray))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            let hit = octree.pick(&ray_origin, &ray_dir, pick_radius, &vertex_points);
+            match (hit, expected) {
+                (Some((_, along_ray)), Some((_, expected_along_ray))) => {
+                    assert!((along_ray - expected_along_ray).abs() < 1e-5);
+                    num_hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("pick returned {:?}, expected {:?}", hit, expected),
+            }
+        }
+        assert!(num_hits > 0);
+
+        // A ray pointing away from the cloud hits nothing behind its origin
+        let away = octree.pick(
+            &Vec3::new(0.0, 0.0, 3.0),
+            &Vec3::new(0.0, 0.0, 1.0),
+            0.1,
+            &vertex_points,
+        );
+        assert!(away.is_none());
+    }
+
+    #[test]
+    fn query_frustum_keeps_every_visible_point() {
+        let vertex_points = random_points(2000, 10);
+        let octree = insert_all(
+            &vertex_points,
+            OctreeConfig {
+                max_points_per_leaf: 16,
+                ..OctreeConfig::default()
+            },
+        );
+        let mv_matrix = nalgebra_glm::look_at(
+            &Vec3::new(0.5, 0.5, 2.5),
+            &Vec3::new(0.3, 0.0, 0.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+        );
+        let p_matrix = nalgebra_glm::perspective(1.0, 0.6, 0.1, 10.0);
+        let frustum = Frustum::from_matrix(&(p_matrix * mv_matrix));
+
+        let culled = sorted(octree.query_frustum(&frustum));
+        let visible: Vec<usize> = (0..2000)
+            .filter(|&i| frustum.contains_point(&get_point(&vertex_points, i)))
+            .collect();
+        assert!(visible.iter().all(|i| culled.binary_search(i).is_ok()));
+        assert!(culled.len() < 2000);
+    }
+
+    fn set_point(vertex_points: &mut [f32], point_index: usize, point: &Vec3) {
+        let offset = point_index * VERTEX_STRIDE;
+        vertex_points[offset..offset + 3].copy_from_slice(point.as_slice());
+    }
+
+    fn stored_points(octree: &Octree) -> Vec<usize> {
+        sorted(
+            leaves(octree)
+                .iter()
+                .flat_map(|leaf| leaf.point_indices.iter().copied())
+                .collect(),
+        )
+    }
+
+    fn small_leaves() -> OctreeConfig {
+        OctreeConfig {
+            max_points_per_leaf: 8,
+            ..OctreeConfig::default()
+        }
+    }
+
+    #[test]
+    fn removes_points() {
+        let vertex_points = random_points(500, 11);
+        let mut octree = insert_all(&vertex_points, small_leaves());
+
+        let removed: Vec<usize> = (0..500).step_by(3).collect();
+        for &point_index in &removed {
+            assert!(octree.remove(point_index, &get_point(&vertex_points, point_index)));
+        }
+        // Already gone
+        assert!(!octree.remove(0, &get_point(&vertex_points, 0)));
+
+        let kept: Vec<usize> = (0..500).filter(|i| i % 3 != 0).collect();
+        assert_eq!(stored_points(&octree), kept);
+        let everything = octree.query_sphere(&Vec3::zeros(), 10.0, &vertex_points);
+        assert_eq!(sorted(everything), kept);
+    }
+
+    #[test]
+    fn moves_points_across_leaves() {
+        let mut vertex_points = random_points(500, 12);
+        let mut octree = insert_all(&vertex_points, small_leaves());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(13);
+
+        for point_index in (0..500).step_by(7) {
+            let old = get_point(&vertex_points, point_index);
+            let new = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            set_point(&mut vertex_points, point_index, &new);
+            assert!(octree.update(point_index, &old, &new, &vertex_points));
+        }
+
+        // Every point is in the leaf that contains its new position
+        for leaf in leaves(&octree) {
+            for &point_index in &leaf.point_indices {
+                assert!(cube_contains(leaf, &get_point(&vertex_points, point_index)));
+            }
+        }
+        assert_eq!(stored_points(&octree), (0..500).collect::<Vec<_>>());
+        for _ in 0..20 {
+            let center = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let expected: Vec<usize> = (0..500)
+                .filter(|&i| nalgebra_glm::distance(&get_point(&vertex_points, i), &center) <= 0.4)
+                .collect();
+            assert_eq!(
+                sorted(octree.query_sphere(&center, 0.4, &vertex_points)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn moves_points_outside_the_root() {
+        let mut vertex_points = random_points(500, 14);
+        let mut octree = insert_all(&vertex_points, small_leaves());
+
+        // Out of a corner leaf, into the space beyond it that `leaf_for` maps to the same leaf
+        let corner = Vec3::new(-0.99, 0.99, -0.99);
+        let old = get_point(&vertex_points, 7);
+        set_point(&mut vertex_points, 7, &corner);
+        assert!(octree.update(7, &old, &corner, &vertex_points));
+        let beyond = Vec3::new(-1.5, 1.5, -1.5);
+        set_point(&mut vertex_points, 7, &beyond);
+        assert!(octree.update(7, &corner, &beyond, &vertex_points));
+        assert!(octree.contains(&beyond));
+
+        let old = get_point(&vertex_points, 42);
+        let new = Vec3::new(3.0, -2.5, 1.5);
+        set_point(&mut vertex_points, 42, &new);
+        assert!(octree.update(42, &old, &new, &vertex_points));
+
+        assert!(octree.contains(&new));
+        assert_eq!(stored_points(&octree), (0..500).collect::<Vec<_>>());
+        assert_eq!(octree.knn(&new, 1, &vertex_points)[0].0, 42);
+        assert!(!octree
+            .query_sphere(&old, 1e-4, &vertex_points)
+            .contains(&42));
+    }
+
+    #[test]
+    fn merges_sparse_children_back_into_their_parent() {
+        let vertex_points = random_points(500, 15);
+        let mut octree = insert_all(&vertex_points, small_leaves());
+        assert!(octree.get_num_leaves() > 8);
+
+        // Nodes collapse bottom-up as they empty, until the root holds few enough points
+        // to be a leaf again
+        for point_index in 8..500 {
+            assert!(octree.remove(point_index, &get_point(&vertex_points, point_index)));
+        }
+        assert!(octree.children.is_none());
+        assert_eq!(
+            sorted(octree.point_indices.clone()),
+            (0..8).collect::<Vec<_>>()
+        );
+
+        // A node whose children are still over the limit stays split
+        let mut octree = insert_all(&vertex_points, small_leaves());
+        for point_index in 0..400 {
+            octree.remove(point_index, &get_point(&vertex_points, point_index));
+        }
+        assert!(octree.children.is_some());
+        for leaf in leaves(&octree) {
+            assert!(leaf.point_indices.len() <= 8);
+        }
+        assert_eq!(stored_points(&octree), (400..500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fits_the_root_to_the_data_bounds() {
+        let mut vertex_points = random_points(1000, 16);
+        // Squash the cloud into a flat slab away from the origin
+        for vertex in vertex_points.chunks_exact_mut(VERTEX_STRIDE) {
+            vertex[0] = vertex[0] * 5.0 + 20.0;
+            vertex[1] = vertex[1] * 0.1 - 3.0;
+        }
+        let (min, max) = compute_bounds(&vertex_points).unwrap();
+        for point_index in 0..1000 {
+            let point = get_point(&vertex_points, point_index);
+            assert!((0..3).all(|axis| min[axis] <= point[axis] && point[axis] <= max[axis]));
+        }
+
+        let octree = Octree::from_bounds(&min, &max, 0.01, small_leaves());
+        assert!((octree.center - (min + max) / 2.0).norm() < 1e-5);
+        assert!((octree.size - (max - min).max() * 1.02).abs() < 1e-4);
+        assert!((0..1000).all(|i| octree.contains(&get_point(&vertex_points, i))));
+        assert!(compute_bounds(&[]).is_none());
+
+        // Coincident points still get a cube with some volume
+        let mut one_spot = vec![0.0; 2 * VERTEX_STRIDE];
+        for vertex in one_spot.chunks_exact_mut(VERTEX_STRIDE) {
+            vertex[..3].copy_from_slice(&[1.0, 2.0, 3.0]);
+        }
+        let (min, max) = compute_bounds(&one_spot).unwrap();
+        let octree = Octree::from_bounds(&min, &max, 0.01, small_leaves());
+        assert!(octree.size > 0.0 && octree.contains(&Vec3::new(1.0, 2.0, 3.0)));
+    }
+
+    #[test]
+    fn grows_the_root_around_points_outside_it() {
+        let mut vertex_points = random_points(1000, 17);
+        for vertex in vertex_points.chunks_exact_mut(VERTEX_STRIDE).skip(900) {
+            vertex[0] *= 7.0;
+            vertex[2] = vertex[2] * 3.0 - 9.0;
+        }
+        let octree = insert_all(&vertex_points, small_leaves());
+
+        assert!((0..1000).all(|i| octree.contains(&get_point(&vertex_points, i))));
+        for leaf in leaves(&octree) {
+            for &point_index in &leaf.point_indices {
+                assert!(cube_contains(leaf, &get_point(&vertex_points, point_index)));
+            }
+            assert!(leaf.point_indices.len() <= 8 || leaf.depth == leaf.config.max_depth);
+        }
+        assert_eq!(stored_points(&octree), (0..1000).collect::<Vec<_>>());
+        let (min, max) = (Vec3::new(-8.0, -2.0, -13.0), Vec3::new(8.0, 2.0, 0.0));
+        let expected: Vec<usize> = (0..1000)
+            .filter(|&i| {
+                let point = get_point(&vertex_points, i);
+                (0..3).all(|axis| min[axis] <= point[axis] && point[axis] <= max[axis])
+            })
+            .collect();
+        assert_eq!(
+            sorted(octree.query_aabb(&min, &max, &vertex_points)),
+            expected
+        );
+    }
+
+    #[test]
+    fn turns_away_points_outside_a_fixed_root() {
+        let mut vertex_points = random_points(1000, 18);
+        for vertex in vertex_points.chunks_exact_mut(VERTEX_STRIDE).skip(900) {
+            vertex[0] *= 7.0;
+            vertex[2] = vertex[2] * 3.0 - 9.0;
+        }
+        let config = OctreeConfig {
+            grow_root: false,
+            ..small_leaves()
+        };
+        let mut octree = Octree::with_config(Vec3::zeros(), 2.0, config);
+        let inserted: Vec<usize> = (0..1000)
+            .filter(|&i| octree.insert(i, &vertex_points))
+            .collect();
+
+        // Without growth the root keeps its size, and only the points inside it are stored
+        let inside: Vec<usize> = (0..900).collect();
+        assert_eq!(octree.size, 2.0);
+        assert_eq!(inserted, inside);
+        assert_eq!(stored_points(&octree), inside);
+
+        // A box around the outside points alone finds none of them
+        let (min, max) = (Vec3::new(-8.0, -2.0, -13.0), Vec3::new(8.0, 2.0, -5.0));
+        assert!(octree.query_aabb(&min, &max, &vertex_points).is_empty());
+        // A box just around the root accepts it whole, and holds exactly the stored points
+        let (min, max) = (Vec3::repeat(-1.01), Vec3::repeat(1.01));
+        assert_eq!(
+            sorted(octree.query_aabb(&min, &max, &vertex_points)),
+            inside
+        );
+        let axes = [Vec3::x(), Vec3::y(), Vec3::z()];
+        let around_root = ConvexPolytope::oriented_box(&Vec3::zeros(), &axes, &max);
+        assert_eq!(
+            sorted(octree.query_polytope(&around_root, &vertex_points)),
+            inside
         );
+        // Nor do the nearest points to one outside include it
+        let outside = get_point(&vertex_points, 950);
+        assert!(octree
+            .knn(&outside, 5, &vertex_points)
+            .iter()
+            .all(|&(i, _)| i < 900));
 
-        nalgebra_glm::distance(&closest_point, center) <= radius
+        // Moving a point out of the root takes it out of the tree
+        let old = get_point(&vertex_points, 3);
+        let new = Vec3::new(0.0, 5.0, 0.0);
+        set_point(&mut vertex_points, 3, &new);
+        assert!(!octree.update(3, &old, &new, &vertex_points));
+        assert!(!stored_points(&octree).contains(&3));
+        assert_eq!(octree.num_points(), 899);
     }
 }
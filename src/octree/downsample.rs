@@ -0,0 +1,276 @@
+// downsample.rs
+use std::collections::HashMap;
+
+use nalgebra_glm::Vec3;
+
+use super::{get_point, Octree, COLOR_OFFSET, NORMAL_OFFSET, VERTEX_STRIDE};
+
+/// Which point stands in for the points of a voxel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelRepresentative {
+    /// A new point at the voxel's centroid, with the mean color and normal of its points.
+    Centroid,
+    /// The voxel's point closest to the centroid, unchanged.
+    NearestToCentroid,
+}
+
+impl VoxelRepresentative {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "centroid" => Some(VoxelRepresentative::Centroid),
+            "nearest" => Some(VoxelRepresentative::NearestToCentroid),
+            _ => None,
+        }
+    }
+}
+
+// Running totals for one voxel
+#[derive(Clone, Copy, Default)]
+struct Voxel {
+    num_points: usize,
+    position_sum: Vec3,
+    color_sum: Vec3,
+    normal_sum: Vec3,
+    // The point closest to the centroid so far, with its distance
+    nearest: Option<(usize, f32)>,
+}
+
+impl Voxel {
+    fn centroid(&self) -> Vec3 {
+        self.position_sum / self.num_points as f32
+    }
+}
+
+// The occupied voxels, in the order the traversal first reached them
+struct VoxelGrid {
+    voxel_size: f32,
+    voxel_indices: HashMap<[i64; 3], usize>,
+    voxels: Vec<Voxel>,
+}
+
+impl VoxelGrid {
+    fn new(voxel_size: f32) -> Self {
+        VoxelGrid {
+            voxel_size,
+            voxel_indices: HashMap::new(),
+            voxels: Vec::new(),
+        }
+    }
+
+    fn cell(&self, point: &Vec3) -> [i64; 3] {
+        let cell = (point / self.voxel_size).map(f32::floor);
+        [cell.x as i64, cell.y as i64, cell.z as i64]
+    }
+
+    fn voxel_index(&mut self, cell: [i64; 3]) -> usize {
+        let voxels = &mut self.voxels;
+        *self.voxel_indices.entry(cell).or_insert_with(|| {
+            voxels.push(Voxel::default());
+            voxels.len() - 1
+        })
+    }
+}
+
+impl Octree {
+    /// Buckets the points into a grid of cubic voxels `voxel_size` across, aligned with the
+    /// origin so that grids of different scans line up, and returns one vertex per
+    /// occupied voxel in the point buffer's layout. Returns nothing if `voxel_size` is not
+    /// positive.
+    pub fn voxel_downsample(
+        &self,
+        voxel_size: f32,
+        representative: VoxelRepresentative,
+        vertex_points: &[f32],
+    ) -> Vec<f32> {
+        match representative {
+            VoxelRepresentative::Centroid => {
+                let grid = self.sum_voxels(voxel_size, vertex_points);
+                let mut downsampled = Vec::with_capacity(grid.voxels.len() * VERTEX_STRIDE);
+                for voxel in &grid.voxels {
+                    let color = voxel.color_sum / voxel.num_points as f32;
+                    // Points without normals leave the sum at zero, which stays zero
+                    let normal = voxel
+                        .normal_sum
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_default();
+                    downsampled.extend_from_slice(voxel.centroid().as_slice());
+                    downsampled.extend_from_slice(color.as_slice());
+                    downsampled.extend_from_slice(normal.as_slice());
+                }
+                downsampled
+            }
+            VoxelRepresentative::NearestToCentroid => {
+                let mut downsampled = Vec::new();
+                for point_index in self.voxel_representatives(voxel_size, vertex_points) {
+                    let offset = point_index * VERTEX_STRIDE;
+                    downsampled.extend_from_slice(&vertex_points[offset..offset + VERTEX_STRIDE]);
+                }
+                downsampled
+            }
+        }
+    }
+
+    /// Returns the index of the point closest to the centroid of each occupied voxel, for
+    /// the same grid as `voxel_downsample`.
+    pub fn voxel_representatives(&self, voxel_size: f32, vertex_points: &[f32]) -> Vec<usize> {
+        let mut grid = self.sum_voxels(voxel_size, vertex_points);
+        if grid.voxels.is_empty() {
+            return Vec::new();
+        }
+        self.for_each_point_voxel(
+            &mut grid,
+            vertex_points,
+            &mut |voxel, point_index, point| {
+                let distance = nalgebra_glm::distance(point, &voxel.centroid());
+                if voxel
+                    .nearest
+                    .map_or(true, |(_, nearest)| distance < nearest)
+                {
+                    voxel.nearest = Some((point_index, distance));
+                }
+            },
+        );
+        grid.voxels
+            .iter()
+            .filter_map(|voxel| voxel.nearest.map(|(point_index, _)| point_index))
+            .collect()
+    }
+
+    fn sum_voxels(&self, voxel_size: f32, vertex_points: &[f32]) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(voxel_size);
+        if voxel_size > 0.0 {
+            self.for_each_point_voxel(
+                &mut grid,
+                vertex_points,
+                &mut |voxel, point_index, point| {
+                    let color = point_index * VERTEX_STRIDE + COLOR_OFFSET;
+                    let normal = point_index * VERTEX_STRIDE + NORMAL_OFFSET;
+                    voxel.num_points += 1;
+                    voxel.position_sum += point;
+                    voxel.color_sum += Vec3::from_column_slice(&vertex_points[color..color + 3]);
+                    voxel.normal_sum += Vec3::from_column_slice(&vertex_points[normal..normal + 3]);
+                },
+            );
+        }
+        grid
+    }
+
+    // Visits the points leaf by leaf. Points in a leaf lie close together and mostly share
+    // a voxel, so the voxel of the previous point is reused before looking one up
+    fn for_each_point_voxel(
+        &self,
+        grid: &mut VoxelGrid,
+        vertex_points: &[f32],
+        visit: &mut impl FnMut(&mut Voxel, usize, &Vec3),
+    ) {
+        if let Some(children) = self.children.as_ref() {
+            for child in children.iter() {
+                child.for_each_point_voxel(grid, vertex_points, visit);
+            }
+            return;
+        }
+
+        let mut previous: Option<([i64; 3], usize)> = None;
+        for &point_index in &self.point_indices {
+            let point = get_point(vertex_points, point_index);
+            let cell = grid.cell(&point);
+            let voxel_index = match previous {
+                Some((previous_cell, voxel_index)) if previous_cell == cell => voxel_index,
+                _ => grid.voxel_index(cell),
+            };
+            previous = Some((cell, voxel_index));
+            visit(&mut grid.voxels[voxel_index], point_index, &point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::OctreeConfig;
+
+    const VOXEL_SIZE: f32 = 0.25;
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+                rng.gen_range(0.0..1.0),
+                rng.gen_range(0.0..1.0),
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        vertex_points
+    }
+
+    // The points of each voxel, grouped by a linear scan
+    fn brute_force_voxels(vertex_points: &[f32]) -> HashMap<[i64; 3], Vec<usize>> {
+        let grid = VoxelGrid::new(VOXEL_SIZE);
+        let mut voxels: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        for point_index in 0..vertex_points.len() / VERTEX_STRIDE {
+            let cell = grid.cell(&get_point(vertex_points, point_index));
+            voxels.entry(cell).or_default().push(point_index);
+        }
+        voxels
+    }
+
+    fn centroid(vertex_points: &[f32], point_indices: &[usize]) -> Vec3 {
+        point_indices
+            .iter()
+            .map(|&i| get_point(vertex_points, i))
+            .sum::<Vec3>()
+            / point_indices.len() as f32
+    }
+
+    #[test]
+    fn centroids_match_brute_force() {
+        let vertex_points = random_points(5000);
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+        let expected = brute_force_voxels(&vertex_points);
+
+        let downsampled =
+            octree.voxel_downsample(VOXEL_SIZE, VoxelRepresentative::Centroid, &vertex_points);
+
+        assert_eq!(downsampled.len() / VERTEX_STRIDE, expected.len());
+        let grid = VoxelGrid::new(VOXEL_SIZE);
+        for vertex in downsampled.chunks_exact(VERTEX_STRIDE) {
+            let point = Vec3::from_column_slice(&vertex[..3]);
+            let point_indices = &expected[&grid.cell(&point)];
+            assert!(
+                nalgebra_glm::distance(&point, &centroid(&vertex_points, point_indices)) < 1e-5
+            );
+        }
+    }
+
+    #[test]
+    fn representatives_are_nearest_to_their_centroid() {
+        let vertex_points = random_points(5000);
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+        let expected = brute_force_voxels(&vertex_points);
+
+        let representatives = octree.voxel_representatives(VOXEL_SIZE, &vertex_points);
+
+        assert_eq!(representatives.len(), expected.len());
+        let grid = VoxelGrid::new(VOXEL_SIZE);
+        for point_index in representatives {
+            let point = get_point(&vertex_points, point_index);
+            let point_indices = &expected[&grid.cell(&point)];
+            let centroid = centroid(&vertex_points, point_indices);
+            let nearest = point_indices
+                .iter()
+                .map(|&i| nalgebra_glm::distance(&get_point(&vertex_points, i), &centroid))
+                .fold(f32::INFINITY, f32::min);
+            assert!(nalgebra_glm::distance(&point, &centroid) - nearest < 1e-5);
+        }
+        assert!(octree.voxel_representatives(0.0, &vertex_points).is_empty());
+    }
+}
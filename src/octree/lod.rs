@@ -0,0 +1,308 @@
+// lod.rs
+use std::collections::{BinaryHeap, HashSet};
+
+use nalgebra_glm::{Mat4, Vec3};
+
+use super::{get_point, Octree};
+use crate::frustum::{Containment, Frustum};
+use crate::spatial_index::HeapEntry;
+
+/// The view a level-of-detail selection is made for.
+#[derive(Clone, Copy, Debug)]
+pub struct LodCamera {
+    /// Camera position in the same space as the points.
+    pub position: Vec3,
+    pub frustum: Frustum,
+    /// Pixels covered by one unit of length seen face-on at a distance of one unit.
+    pub projection_scale: f32,
+}
+
+impl LodCamera {
+    pub fn new(mv_matrix: &Mat4, p_matrix: &Mat4, viewport_height: f32) -> Self {
+        let eye = mv_matrix.try_inverse().unwrap_or_else(Mat4::identity)
+            * nalgebra_glm::vec4(0.0, 0.0, 0.0, 1.0);
+        LodCamera {
+            position: eye.xyz() / eye.w,
+            frustum: Frustum::from_matrix(&(p_matrix * mv_matrix)),
+            // The projection's y scale is 1 / tan(fov / 2), which spans half the viewport
+            projection_scale: p_matrix[(1, 1)] * viewport_height / 2.0,
+        }
+    }
+}
+
+/// The nodes chosen by `select_lod`. Leaves in `full_nodes` are drawn with all their
+/// points, and interior nodes in `sampled_nodes` are drawn with their LOD samples only.
+pub struct LodSelection<'a> {
+    pub full_nodes: Vec<&'a Octree>,
+    pub sampled_nodes: Vec<&'a Octree>,
+}
+
+impl LodSelection<'_> {
+    /// Returns the indices of every point the selection draws.
+    pub fn point_indices(&self) -> Vec<usize> {
+        let mut point_indices = Vec::new();
+        for node in &self.full_nodes {
+            point_indices.extend_from_slice(&node.point_indices);
+        }
+        for node in &self.sampled_nodes {
+            point_indices.extend_from_slice(&node.lod_samples);
+        }
+        point_indices
+    }
+}
+
+impl Octree {
+    /// Picks a representative subsample of up to `samples_per_node` points for every
+    /// interior node, bottom-up. Each node is divided into a regular grid with at most
+    /// `samples_per_node` cells and keeps one point per occupied cell, drawn from its
+    /// children's samples (or points, for leaf children).
+    ///
+    /// `remove` and `update` drop the points they take out of a node from its samples, so
+    /// samples never name a point outside their node, but `insert` adds none; call this
+    /// again after editing the tree to sample the new points and fill the gaps.
+    pub fn build_lod(&mut self, samples_per_node: usize, vertex_points: &[f32]) {
+        let (min_pos, _) = self.bounds();
+        let Some(children) = self.children.as_mut() else {
+            self.lod_samples.clear();
+            return;
+        };
+        for child in children.iter_mut() {
+            child.build_lod(samples_per_node, vertex_points);
+        }
+
+        let grid_resolution = (samples_per_node as f32).cbrt().floor().max(1.0);
+        let cell_size = self.size / grid_resolution;
+        let max_cell = grid_resolution as u32 - 1;
+
+        let mut occupied_cells = HashSet::new();
+        let mut lod_samples = Vec::new();
+        for child in children.iter() {
+            let candidates = match child.children {
+                None => &child.point_indices,
+                Some(_) => &child.lod_samples,
+            };
+            for &point_index in candidates {
+                let offset = (get_point(vertex_points, point_index) - min_pos) / cell_size;
+                let cell = (
+                    (offset.x.max(0.0) as u32).min(max_cell),
+                    (offset.y.max(0.0) as u32).min(max_cell),
+                    (offset.z.max(0.0) as u32).min(max_cell),
+                );
+                if occupied_cells.insert(cell) {
+                    lod_samples.push(point_index);
+                }
+            }
+        }
+        self.lod_samples = lod_samples;
+    }
+
+    /// Chooses which nodes to draw so that at most `point_budget` points are drawn (unless
+    /// a single visible leaf is larger than that). Starting from the root, the node whose
+    /// samples look coarsest on screen is repeatedly replaced by its visible children,
+    /// until every drawn node's sample spacing projects to at most `screen_error` pixels
+    /// or no further node fits in the budget.
+    ///
+    /// Interior nodes without samples, because `build_lod` has not been run since they were
+    /// created, are always refined. After the tree is edited the samples are only a subset
+    /// of what `build_lod` would pick, so it should be run again before selecting.
+    pub fn select_lod(
+        &self,
+        camera: &LodCamera,
+        point_budget: usize,
+        screen_error: f32,
+    ) -> LodSelection<'_> {
+        let mut selection = LodSelection {
+            full_nodes: Vec::new(),
+            sampled_nodes: Vec::new(),
+        };
+        if !self.is_visible(camera) {
+            return selection;
+        }
+
+        let mut points_drawn = self.lod_cost();
+        let mut pending = BinaryHeap::new();
+        self.queue_for_refinement(camera, &mut selection, &mut pending);
+
+        while let Some(HeapEntry {
+            distance: error,
+            item: node,
+        }) = pending.pop()
+        {
+            let children = node.children.as_ref().unwrap();
+            let visible_children: Vec<&Octree> = children
+                .iter()
+                .filter(|child| child.is_visible(camera))
+                .collect();
+            let refined_cost: usize = visible_children.iter().map(|child| child.lod_cost()).sum();
+            let refined_points_drawn = points_drawn - node.lod_cost() + refined_cost;
+
+            let must_refine = node.lod_samples.is_empty();
+            if !must_refine && (error <= screen_error || refined_points_drawn > point_budget) {
+                selection.sampled_nodes.push(node);
+                continue;
+            }
+
+            points_drawn = refined_points_drawn;
+            for child in visible_children {
+                child.queue_for_refinement(camera, &mut selection, &mut pending);
+            }
+        }
+
+        selection
+    }
+
+    // Leaves go straight into the selection; interior nodes wait in `pending`, keyed by
+    // how far apart their samples appear on screen
+    fn queue_for_refinement<'a>(
+        &'a self,
+        camera: &LodCamera,
+        selection: &mut LodSelection<'a>,
+        pending: &mut BinaryHeap<HeapEntry<&'a Octree>>,
+    ) {
+        if self.children.is_none() {
+            selection.full_nodes.push(self);
+            return;
+        }
+
+        let spacing = self.size / (self.lod_samples.len().max(1) as f32).cbrt();
+        let distance = nalgebra_glm::distance(&self.center, &camera.position)
+            .max(self.size / 2.0)
+            .max(f32::EPSILON);
+        pending.push(HeapEntry {
+            distance: spacing * camera.projection_scale / distance,
+            item: self,
+        });
+    }
+
+    fn lod_cost(&self) -> usize {
+        match self.children {
+            None => self.point_indices.len(),
+            Some(_) => self.lod_samples.len(),
+        }
+    }
+
+    fn is_visible(&self, camera: &LodCamera) -> bool {
+        let (min_pos, max_pos) = self.bounds();
+        camera.frustum.classify_aabb(&min_pos, &max_pos) != Containment::Outside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{OctreeConfig, VERTEX_STRIDE};
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            for _ in 0..3 {
+                vertex_points.push(rng.gen_range(-1.0..1.0));
+            }
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    fn build_tree(vertex_points: &[f32]) -> Octree {
+        let config = OctreeConfig {
+            max_points_per_leaf: 32,
+            ..OctreeConfig::default()
+        };
+        let (mut octree, _) = Octree::build(vertex_points, config);
+        octree.build_lod(64, vertex_points);
+        octree
+    }
+
+    // Looking down -z at the whole cube from z = 3
+    fn camera() -> LodCamera {
+        let mv_matrix = nalgebra_glm::look_at(
+            &Vec3::new(0.0, 0.0, 3.0),
+            &Vec3::zeros(),
+            &Vec3::new(0.0, 1.0, 0.0),
+        );
+        let p_matrix = nalgebra_glm::perspective(1.0, 1.5, 0.1, 10.0);
+        LodCamera::new(&mv_matrix, &p_matrix, 600.0)
+    }
+
+    #[test]
+    fn stays_within_the_point_budget() {
+        let vertex_points = random_points(20000);
+        let octree = build_tree(&vertex_points);
+        for point_budget in [200, 1000, 5000] {
+            for screen_error in [0.0, 2.0] {
+                let selection = octree.select_lod(&camera(), point_budget, screen_error);
+                let num_drawn = selection.point_indices().len();
+                assert!(num_drawn > 0);
+                assert!(num_drawn <= point_budget);
+            }
+        }
+    }
+
+    #[test]
+    fn refines_closer_nodes_first() {
+        let vertex_points = random_points(20000);
+        let octree = build_tree(&vertex_points);
+        let point_indices = octree.select_lod(&camera(), 3000, 0.0).point_indices();
+
+        // The half of the cube facing the camera gets most of the budget
+        let num_near = point_indices
+            .iter()
+            .filter(|&&point_index| get_point(&vertex_points, point_index).z > 0.0)
+            .count();
+        assert!(num_near > 2 * (point_indices.len() - num_near));
+    }
+
+    fn all_samples(node: &Octree, samples: &mut Vec<(usize, Vec3, Vec3)>) {
+        let (min_pos, max_pos) = node.bounds();
+        for &point_index in &node.lod_samples {
+            samples.push((point_index, min_pos, max_pos));
+        }
+        if let Some(children) = node.children.as_ref() {
+            for child in children.iter() {
+                all_samples(child, samples);
+            }
+        }
+    }
+
+    #[test]
+    fn edits_drop_removed_and_moved_points_from_the_samples() {
+        let mut vertex_points = random_points(5000);
+        let mut octree = build_tree(&vertex_points);
+
+        for point_index in (0..5000).step_by(5) {
+            assert!(octree.remove(point_index, &get_point(&vertex_points, point_index)));
+        }
+        // Across the cube, so most moves change leaf
+        for point_index in (1..5000).step_by(5) {
+            let old = get_point(&vertex_points, point_index);
+            let new = -old;
+            let offset = point_index * VERTEX_STRIDE;
+            vertex_points[offset..offset + 3].copy_from_slice(new.as_slice());
+            assert!(octree.update(point_index, &old, &new, &vertex_points));
+        }
+
+        let mut samples = Vec::new();
+        all_samples(&octree, &mut samples);
+        assert!(!samples.is_empty());
+        for (point_index, min_pos, max_pos) in samples {
+            assert!(point_index % 5 != 0);
+            let point = get_point(&vertex_points, point_index);
+            assert!((0..3).all(|i| min_pos[i] <= point[i] && point[i] <= max_pos[i]));
+        }
+    }
+
+    #[test]
+    fn draws_every_point_when_the_budget_allows() {
+        let vertex_points = random_points(5000);
+        let octree = build_tree(&vertex_points);
+        let selection = octree.select_lod(&camera(), 5000, 0.0);
+
+        assert!(selection.sampled_nodes.is_empty());
+        let mut point_indices = selection.point_indices();
+        point_indices.sort_unstable();
+        assert_eq!(point_indices, (0..5000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,249 @@
+// morton.rs
+use nalgebra_glm::Vec3;
+
+use super::{
+    child_center, child_index_of, compute_bounds, get_point, Octree, OctreeConfig, VERTEX_STRIDE,
+};
+
+// 21 levels of three bits each fit in a u64 key
+const MAX_MORTON_LEVELS: usize = 21;
+
+impl Octree {
+    /// Builds an octree over every point in the vertex buffer in one pass, instead of
+    /// inserting points one at a time. Each point gets a Morton (Z-order) key, the points
+    /// are sorted by key once, and nodes are cut out of contiguous runs of the sorted order.
+    ///
+    /// Returns the tree along with the sorted point order. Each leaf's points are a
+    /// contiguous run of that order, copied into the leaf so the tree can still be edited
+    /// with `insert`, `remove` and `update`. Reordering the vertex buffer with
+    /// `reorder_points` and the tree with `renumber_points` lays the points out in spatially
+    /// coherent order, after which every leaf holds a contiguous range of indices.
+    pub fn build(vertex_points: &[f32], config: OctreeConfig) -> (Octree, Vec<usize>) {
+        let mut root = match compute_bounds(vertex_points) {
+            Some((min, max)) => Octree::from_bounds(&min, &max, 0.01, config),
+            None => return (Octree::with_config(Vec3::zeros(), 2.0, config), Vec::new()),
+        };
+
+        let levels = config.max_depth.min(MAX_MORTON_LEVELS);
+        let num_points = vertex_points.len() / VERTEX_STRIDE;
+        let mut keyed_points: Vec<(u64, usize)> = (0..num_points)
+            .map(|i| (root.morton_key(&get_point(vertex_points, i), levels), i))
+            .collect();
+        keyed_points.sort_unstable();
+
+        root.build_node(&keyed_points, levels);
+        let order = keyed_points.into_iter().map(|(_, i)| i).collect();
+        (root, order)
+    }
+
+    /// Replaces every stored point index `order[i]` with `i`, to match a vertex buffer that
+    /// was reordered with `reorder_points(vertex_points, order)`.
+    pub fn renumber_points(&mut self, order: &[usize]) {
+        let mut new_index = vec![0; order.len()];
+        for (i, &old_index) in order.iter().enumerate() {
+            new_index[old_index] = i;
+        }
+        self.renumber_node(&new_index);
+    }
+
+    fn renumber_node(&mut self, new_index: &[usize]) {
+        for point_index in self.point_indices.iter_mut() {
+            *point_index = new_index[*point_index];
+        }
+        if let Some(children) = self.children.as_mut() {
+            for child in children.iter_mut() {
+                child.renumber_node(new_index);
+            }
+        }
+    }
+
+    // Descends from this node the way `insert` would, recording the octant taken at each
+    // level. Sharing the octant arithmetic with `insert` guarantees a point's key agrees
+    // with the cube it would be inserted into, even for points on a boundary.
+    fn morton_key(&self, point: &Vec3, levels: usize) -> u64 {
+        let mut key = 0;
+        let mut center = self.center;
+        let mut size = self.size;
+        for _ in 0..levels {
+            let child_index = child_index_of(&center, point);
+            key = (key << 3) | child_index as u64;
+            center = child_center(&center, size, child_index);
+            size /= 2.0;
+        }
+        key
+    }
+
+    fn build_node(&mut self, keyed_points: &[(u64, usize)], levels: usize) {
+        if keyed_points.len() <= self.config.max_points_per_leaf
+            || self.depth >= levels
+            || self.size / 2.0 < self.config.min_node_size
+        {
+            self.point_indices = keyed_points.iter().map(|&(_, i)| i).collect();
+            return;
+        }
+
+        let shift = 3 * (levels - 1 - self.depth);
+        let mut children: [Octree; 8] = std::array::from_fn(|i| self.new_child(i));
+        let mut start = 0;
+        for (child_index, child) in children.iter_mut().enumerate() {
+            // Keys are sorted, so each child's points form one contiguous run
+            let len = keyed_points[start..]
+                .partition_point(|&(key, _)| ((key >> shift) & 7) as usize == child_index);
+            child.build_node(&keyed_points[start..start + len], levels);
+            start += len;
+        }
+        self.children = Some(Box::new(children));
+    }
+}
+
+/// Returns a copy of the vertex buffer with point `order[i]` moved to position `i`.
+pub fn reorder_points(vertex_points: &[f32], order: &[usize]) -> Vec<f32> {
+    let mut reordered = Vec::with_capacity(vertex_points.len());
+    for &point_index in order {
+        let offset = point_index * VERTEX_STRIDE;
+        reordered.extend_from_slice(&vertex_points[offset..offset + VERTEX_STRIDE]);
+    }
+    reordered
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..0.5),
+            ]);
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    fn small_leaves() -> OctreeConfig {
+        OctreeConfig {
+            max_points_per_leaf: 8,
+            ..OctreeConfig::default()
+        }
+    }
+
+    fn sorted(mut point_indices: Vec<usize>) -> Vec<usize> {
+        point_indices.sort_unstable();
+        point_indices
+    }
+
+    // Both trees split a node once it holds more than `max_points_per_leaf` points, so they
+    // end up with the same nodes holding the same points
+    fn assert_same_membership(bulk: &Octree, incremental: &Octree) {
+        assert_eq!(bulk.center, incremental.center);
+        assert_eq!(bulk.size, incremental.size);
+        assert_eq!(
+            sorted(bulk.point_indices.clone()),
+            sorted(incremental.point_indices.clone())
+        );
+        match (bulk.children.as_ref(), incremental.children.as_ref()) {
+            (None, None) => {}
+            (Some(bulk_children), Some(incremental_children)) => {
+                for (bulk_child, incremental_child) in
+                    bulk_children.iter().zip(incremental_children.iter())
+                {
+                    assert_same_membership(bulk_child, incremental_child);
+                }
+            }
+            _ => panic!("The trees differ in shape"),
+        }
+    }
+
+    fn leaf_indices(octree: &Octree, leaves: &mut Vec<Vec<usize>>) {
+        match octree.children.as_ref() {
+            None => leaves.push(octree.point_indices.clone()),
+            Some(children) => {
+                for child in children.iter() {
+                    leaf_indices(child, leaves);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_incremental_insertion() {
+        let vertex_points = random_points(3000);
+        let (bulk, order) = Octree::build(&vertex_points, small_leaves());
+
+        let (min, max) = compute_bounds(&vertex_points).unwrap();
+        let mut incremental = Octree::from_bounds(&min, &max, 0.01, small_leaves());
+        for point_index in 0..3000 {
+            incremental.insert(point_index, &vertex_points);
+        }
+        assert_same_membership(&bulk, &incremental);
+        assert_eq!(sorted(order), (0..3000).collect::<Vec<_>>());
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+        for _ in 0..30 {
+            let center = Vec3::new(
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..0.5),
+            );
+            let radius = rng.gen_range(0.05..1.0);
+            assert_eq!(
+                sorted(bulk.query_sphere(&center, radius, &vertex_points)),
+                sorted(incremental.query_sphere(&center, radius, &vertex_points))
+            );
+            let (min, max) = (center - Vec3::repeat(radius), center + Vec3::repeat(radius));
+            assert_eq!(
+                sorted(bulk.query_aabb(&min, &max, &vertex_points)),
+                sorted(incremental.query_aabb(&min, &max, &vertex_points))
+            );
+            let k = rng.gen_range(1..20);
+            let distances = |octree: &Octree| -> Vec<f32> {
+                let neighbours = octree.knn(&center, k, &vertex_points);
+                neighbours.iter().map(|&(_, distance)| distance).collect()
+            };
+            assert_eq!(distances(&bulk), distances(&incremental));
+        }
+    }
+
+    #[test]
+    fn leaves_hold_contiguous_ranges_after_reordering() {
+        let vertex_points = random_points(3000);
+        let (mut octree, order) = Octree::build(&vertex_points, small_leaves());
+        let reordered = reorder_points(&vertex_points, &order);
+        octree.renumber_points(&order);
+
+        let mut leaves = Vec::new();
+        leaf_indices(&octree, &mut leaves);
+        let mut next = 0;
+        for leaf in leaves {
+            assert_eq!(leaf, (next..next + leaf.len()).collect::<Vec<_>>());
+            next += leaf.len();
+        }
+        assert_eq!(next, 3000);
+
+        // The reordered buffer holds the same points under their new indices
+        for (new_index, &old_index) in order.iter().enumerate() {
+            assert_eq!(
+                get_point(&reordered, new_index),
+                get_point(&vertex_points, old_index)
+            );
+        }
+        let center = Vec3::new(0.5, 0.2, 0.25);
+        let renumbered = octree.query_sphere(&center, 0.6, &reordered);
+        let original: Vec<usize> = sorted(
+            renumbered
+                .iter()
+                .map(|&new_index| order[new_index])
+                .collect(),
+        );
+        let expected: Vec<usize> = (0..3000)
+            .filter(|&i| nalgebra_glm::distance(&get_point(&vertex_points, i), &center) <= 0.6)
+            .collect();
+        assert_eq!(original, expected);
+    }
+}
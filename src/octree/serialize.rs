@@ -0,0 +1,399 @@
+// serialize.rs
+//
+// Binary layout, all values little-endian:
+//
+//   magic               4 bytes, "OCTR"
+//   version             u16
+//   max_depth           u32
+//   max_points_per_leaf u32
+//   min_node_size       f32
+//   grow_root           u8
+//   root center         3 x f32
+//   root size           f32
+//   index count         u32
+//   point indices       index count x u32, every leaf's indices concatenated in node order
+//   nodes               depth-first, parent before children, each either
+//                         0u8, point count u32          for a leaf
+//                         1u8, child mask u8,           for an interior node
+//                           sample count u32,
+//                           LOD samples                 sample count x u32
+//
+// Only the root's bounds are stored; children's bounds are derived the same way `insert`
+// derives them. A leaf's points are the next `point count` entries of the index array.
+// Children whose bit is clear in the mask are empty leaves and have no record.
+//
+// Version 1 had no LOD samples on interior nodes; it is still read, with samples left empty.
+use nalgebra_glm::Vec3;
+
+use super::{Octree, OctreeConfig};
+
+const MAGIC: &[u8; 4] = b"OCTR";
+const VERSION: u16 = 2;
+
+// `max_depth` is read from the data too, so it is capped before it bounds the recursion
+// in `read_node`. Cube sizes stop halving long before this in f32.
+const MAX_DEPTH: usize = 128;
+
+const LEAF_TAG: u8 = 0;
+const INTERIOR_TAG: u8 = 1;
+
+impl Octree {
+    /// Serializes the tree into the versioned binary format described above.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut point_indices = Vec::new();
+        self.collect_point_indices(&mut point_indices);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.config.max_depth as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.config.max_points_per_leaf as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.config.min_node_size.to_le_bytes());
+        bytes.push(self.config.grow_root as u8);
+        for component in self.center.iter() {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+
+        bytes.extend_from_slice(&(point_indices.len() as u32).to_le_bytes());
+        for point_index in point_indices {
+            bytes.extend_from_slice(&(point_index as u32).to_le_bytes());
+        }
+
+        self.write_node(&mut bytes);
+        bytes
+    }
+
+    fn write_node(&self, bytes: &mut Vec<u8>) {
+        match self.children.as_ref() {
+            None => {
+                bytes.push(LEAF_TAG);
+                bytes.extend_from_slice(&(self.point_indices.len() as u32).to_le_bytes());
+            }
+            Some(children) => {
+                let child_mask = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child)| !child.is_empty_leaf())
+                    .fold(0u8, |mask, (i, _)| mask | (1 << i));
+                bytes.push(INTERIOR_TAG);
+                bytes.push(child_mask);
+                bytes.extend_from_slice(&(self.lod_samples.len() as u32).to_le_bytes());
+                for &point_index in &self.lod_samples {
+                    bytes.extend_from_slice(&(point_index as u32).to_le_bytes());
+                }
+                for child in children.iter().filter(|child| !child.is_empty_leaf()) {
+                    child.write_node(bytes);
+                }
+            }
+        }
+    }
+
+    fn is_empty_leaf(&self) -> bool {
+        self.children.is_none() && self.point_indices.is_empty()
+    }
+
+    /// Reads a tree written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Octree, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err("Not an octree: bad magic number".to_string());
+        }
+        let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+        if version != 1 && version != VERSION {
+            return Err(format!("Unsupported octree format version {}", version));
+        }
+
+        let config = OctreeConfig {
+            max_depth: reader.read_u32()? as usize,
+            max_points_per_leaf: reader.read_u32()? as usize,
+            min_node_size: reader.read_f32()?,
+            grow_root: reader.read_u8()? != 0,
+        };
+        if config.max_depth > MAX_DEPTH {
+            return Err(format!("Octree max_depth {} is too deep", config.max_depth));
+        }
+        let center = Vec3::new(reader.read_f32()?, reader.read_f32()?, reader.read_f32()?);
+        let size = reader.read_f32()?;
+
+        let index_count = reader.read_u32()? as usize;
+        let point_indices = (0..index_count)
+            .map(|_| reader.read_u32().map(|i| i as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut root = Octree::with_config(center, size, config);
+        let mut remaining_indices = point_indices.as_slice();
+        root.read_node(&mut reader, version, &mut remaining_indices)?;
+
+        if !remaining_indices.is_empty() {
+            return Err("Point indices left over after reading every leaf".to_string());
+        }
+        if reader.offset != bytes.len() {
+            return Err("Trailing bytes after octree".to_string());
+        }
+        Ok(root)
+    }
+
+    fn read_node(
+        &mut self,
+        reader: &mut Reader,
+        version: u16,
+        point_indices: &mut &[usize],
+    ) -> Result<(), String> {
+        // Children are created one level below their parent, so this bounds the recursion
+        if self.depth > self.config.max_depth {
+            return Err(format!(
+                "Octree nodes nest deeper than its max_depth of {}",
+                self.config.max_depth
+            ));
+        }
+        match reader.read_u8()? {
+            LEAF_TAG => {
+                let count = reader.read_u32()? as usize;
+                if count > point_indices.len() {
+                    return Err("Leaf refers past the end of the point indices".to_string());
+                }
+                let (leaf_indices, rest) = point_indices.split_at(count);
+                self.point_indices = leaf_indices.to_vec();
+                *point_indices = rest;
+            }
+            INTERIOR_TAG => {
+                let child_mask = reader.read_u8()?;
+                if version >= 2 {
+                    let sample_count = reader.read_u32()? as usize;
+                    self.lod_samples = (0..sample_count)
+                        .map(|_| reader.read_u32().map(|i| i as usize))
+                        .collect::<Result<_, _>>()?;
+                }
+                let mut children: [Octree; 8] = std::array::from_fn(|i| self.new_child(i));
+                for (i, child) in children.iter_mut().enumerate() {
+                    if child_mask & (1 << i) != 0 {
+                        child.read_node(reader, version, point_indices)?;
+                    }
+                }
+                self.children = Some(Box::new(children));
+            }
+            tag => return Err(format!("Unknown octree node tag {}", tag)),
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.offset + len;
+        let slice = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or("Octree data ends unexpectedly")?;
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::VERTEX_STRIDE;
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(0.0..2.0),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        vertex_points
+    }
+
+    fn small_leaves() -> OctreeConfig {
+        OctreeConfig {
+            max_points_per_leaf: 8,
+            ..OctreeConfig::default()
+        }
+    }
+
+    fn assert_same_tree(a: &Octree, b: &Octree) {
+        assert_eq!(a.center, b.center);
+        assert_eq!(a.size, b.size);
+        assert_eq!(a.depth, b.depth);
+        assert_eq!(a.point_indices, b.point_indices);
+        assert_eq!(a.lod_samples, b.lod_samples);
+        match (a.children.as_ref(), b.children.as_ref()) {
+            (None, None) => {}
+            (Some(a_children), Some(b_children)) => {
+                for (a_child, b_child) in a_children.iter().zip(b_children.iter()) {
+                    assert_same_tree(a_child, b_child);
+                }
+            }
+            _ => panic!("Node at depth {} differs in shape", a.depth),
+        }
+    }
+
+    #[test]
+    fn round_trips_a_bulk_built_tree() {
+        let vertex_points = random_points(2000);
+        let (octree, _) = Octree::build(&vertex_points, small_leaves());
+
+        let restored = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_same_tree(&octree, &restored);
+        assert_eq!(restored.config.max_points_per_leaf, 8);
+    }
+
+    #[test]
+    fn round_trips_an_incrementally_built_tree() {
+        let vertex_points = random_points(500);
+        let mut octree = Octree::with_config(Vec3::zeros(), 1.0, small_leaves());
+        for point_index in 0..500 {
+            octree.insert(point_index, &vertex_points);
+        }
+
+        let restored = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_same_tree(&octree, &restored);
+        let query = Vec3::new(1.0, -1.0, 1.0);
+        assert_eq!(
+            octree.query_sphere(&query, 1.5, &vertex_points),
+            restored.query_sphere(&query, 1.5, &vertex_points)
+        );
+    }
+
+    #[test]
+    fn round_trips_lod_samples() {
+        let vertex_points = random_points(2000);
+        let (mut octree, _) = Octree::build(&vertex_points, small_leaves());
+        octree.build_lod(64, &vertex_points);
+        assert!(!octree.lod_samples.is_empty());
+
+        let restored = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_same_tree(&octree, &restored);
+    }
+
+    #[test]
+    fn reads_version_1_without_lod_samples() {
+        let vertex_points = random_points(300);
+        let (octree, _) = Octree::build(&vertex_points, small_leaves());
+        let version_2 = octree.to_bytes();
+
+        // Rewrite as version 1 by dropping every interior node's (empty) sample count
+        let mut version_1 = version_2[..4].to_vec();
+        version_1.extend_from_slice(&1u16.to_le_bytes());
+        const HEADER_LEN: usize = 39;
+        let nodes_start = HEADER_LEN + 4 * octree.num_points();
+        version_1.extend_from_slice(&version_2[6..nodes_start]);
+        let mut offset = nodes_start;
+        while offset < version_2.len() {
+            if version_2[offset] == LEAF_TAG {
+                version_1.extend_from_slice(&version_2[offset..offset + 5]);
+                offset += 5;
+            } else {
+                version_1.extend_from_slice(&version_2[offset..offset + 2]);
+                offset += 6;
+            }
+        }
+
+        let restored = Octree::from_bytes(&version_1).unwrap();
+
+        assert_same_tree(&octree, &restored);
+    }
+
+    #[test]
+    fn round_trips_an_empty_tree() {
+        let octree = Octree::with_config(Vec3::zeros(), 2.0, OctreeConfig::default());
+
+        let restored = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_same_tree(&octree, &restored);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = Octree::with_config(Vec3::zeros(), 2.0, OctreeConfig::default()).to_bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert!(Octree::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn round_trips_a_tree_whose_root_grew() {
+        let vertex_points = random_points(500);
+        let config = OctreeConfig {
+            max_depth: 3,
+            ..small_leaves()
+        };
+        let mut octree = Octree::with_config(Vec3::zeros(), 0.125, config);
+        for point_index in 0..500 {
+            octree.insert(point_index, &vertex_points);
+        }
+        // Growth pushed the first root's leaves below the configured depth
+        assert!(octree.stats().depth > 3);
+
+        let restored = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_same_tree(&octree, &restored);
+    }
+
+    #[test]
+    fn rejects_nodes_deeper_than_max_depth() {
+        let config = OctreeConfig {
+            max_depth: 2,
+            ..OctreeConfig::default()
+        };
+        let mut bytes = Octree::with_config(Vec3::zeros(), 2.0, config).to_bytes();
+        // Replace the root leaf with a chain of interior nodes, each with one child
+        bytes.truncate(bytes.len() - 5);
+        for _ in 0..3 {
+            bytes.extend_from_slice(&[INTERIOR_TAG, 1, 0, 0, 0, 0]);
+        }
+        bytes.extend_from_slice(&[LEAF_TAG, 0, 0, 0, 0]);
+        assert!(Octree::from_bytes(&bytes).is_err());
+
+        // The same chain within the limit is fine
+        bytes.drain(bytes.len() - 11..bytes.len() - 5);
+        assert!(Octree::from_bytes(&bytes).is_ok());
+
+        let mut bytes = Octree::with_config(Vec3::zeros(), 2.0, config).to_bytes();
+        bytes[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Octree::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let vertex_points = random_points(100);
+        let (octree, _) = Octree::build(&vertex_points, small_leaves());
+        let bytes = octree.to_bytes();
+
+        assert!(Octree::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
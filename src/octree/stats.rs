@@ -0,0 +1,152 @@
+// stats.rs
+use wasm_bindgen::prelude::*;
+
+use super::Octree;
+
+/// A summary of the octree's shape, for tuning `OctreeConfig`.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct OctreeStats {
+    /// Depth of the deepest node. A tree that is just a root has depth 0.
+    pub depth: usize,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub empty_leaf_count: usize,
+    pub min_points_per_leaf: usize,
+    pub max_points_per_leaf: usize,
+    pub mean_points_per_leaf: f64,
+    /// Approximate heap and inline size of all nodes and their index lists, in bytes.
+    pub memory_bytes: usize,
+    nodes_per_level: Vec<usize>,
+}
+
+#[wasm_bindgen]
+impl OctreeStats {
+    /// Number of nodes at each depth, starting with the root.
+    #[wasm_bindgen(getter)]
+    pub fn nodes_per_level(&self) -> Vec<u32> {
+        self.nodes_per_level.iter().map(|&n| n as u32).collect()
+    }
+}
+
+impl Octree {
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            min_points_per_leaf: usize::MAX,
+            ..OctreeStats::default()
+        };
+        self.accumulate_stats(&mut stats);
+
+        stats.mean_points_per_leaf = self.num_points() as f64 / stats.leaf_count as f64;
+        stats
+    }
+
+    fn accumulate_stats(&self, stats: &mut OctreeStats) {
+        if stats.nodes_per_level.len() <= self.depth {
+            stats.nodes_per_level.resize(self.depth + 1, 0);
+        }
+        stats.nodes_per_level[self.depth] += 1;
+        stats.depth = stats.depth.max(self.depth);
+        stats.node_count += 1;
+        stats.memory_bytes += std::mem::size_of::<Octree>()
+            + (self.point_indices.capacity() + self.lod_samples.capacity())
+                * std::mem::size_of::<usize>();
+
+        match self.children.as_ref() {
+            None => {
+                let num_points = self.point_indices.len();
+                stats.leaf_count += 1;
+                if num_points == 0 {
+                    stats.empty_leaf_count += 1;
+                }
+                stats.min_points_per_leaf = stats.min_points_per_leaf.min(num_points);
+                stats.max_points_per_leaf = stats.max_points_per_leaf.max(num_points);
+            }
+            Some(children) => {
+                for child in children.iter() {
+                    child.accumulate_stats(stats);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{OctreeConfig, VERTEX_STRIDE};
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            // Clustered towards one corner, so leaf sizes and depths vary
+            let (x, y, z): (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
+            vertex_points.extend_from_slice(&[x * x, y * y * y, z]);
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    #[test]
+    fn matches_a_walk_over_every_node() {
+        let vertex_points = random_points(5000);
+        let config = OctreeConfig {
+            max_points_per_leaf: 16,
+            ..OctreeConfig::default()
+        };
+        let (octree, _) = Octree::build(&vertex_points, config);
+        let stats = octree.stats();
+
+        // Every node with its depth, breadth first
+        let mut nodes: Vec<&Octree> = vec![&octree];
+        let mut next = 0;
+        while next < nodes.len() {
+            if let Some(children) = nodes[next].children.as_ref() {
+                nodes.extend(children.iter());
+            }
+            next += 1;
+        }
+        let leaves: Vec<&Octree> = nodes
+            .iter()
+            .copied()
+            .filter(|node| node.children.is_none())
+            .collect();
+        let leaf_sizes: Vec<usize> = leaves.iter().map(|leaf| leaf.point_indices.len()).collect();
+        let depth = nodes.iter().map(|node| node.depth).max().unwrap();
+
+        assert_eq!(stats.depth, depth);
+        assert_eq!(stats.node_count, nodes.len());
+        assert_eq!(stats.leaf_count, leaves.len());
+        assert_eq!(
+            stats.empty_leaf_count,
+            leaf_sizes.iter().filter(|&&n| n == 0).count()
+        );
+        assert_eq!(stats.min_points_per_leaf, *leaf_sizes.iter().min().unwrap());
+        assert_eq!(stats.max_points_per_leaf, *leaf_sizes.iter().max().unwrap());
+        assert!((stats.mean_points_per_leaf - 5000.0 / leaves.len() as f64).abs() < 1e-9);
+        for (level, &count) in stats.nodes_per_level().iter().enumerate() {
+            let expected = nodes.iter().filter(|node| node.depth == level).count();
+            assert_eq!(count as usize, expected);
+        }
+        assert_eq!(stats.nodes_per_level().len(), depth + 1);
+        assert!(stats.memory_bytes >= nodes.len() * std::mem::size_of::<Octree>());
+    }
+
+    #[test]
+    fn describes_an_empty_tree() {
+        let stats = Octree::with_config(Vec3::zeros(), 2.0, OctreeConfig::default()).stats();
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.node_count, 1);
+        assert_eq!((stats.leaf_count, stats.empty_leaf_count), (1, 1));
+        assert_eq!(
+            (stats.min_points_per_leaf, stats.max_points_per_leaf),
+            (0, 0)
+        );
+        assert_eq!(stats.mean_points_per_leaf, 0.0);
+        assert_eq!(stats.nodes_per_level(), vec![1]);
+    }
+}
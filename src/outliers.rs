@@ -0,0 +1,181 @@
+// outliers.rs
+//
+// Filters for the stray points scans pick up from reflections and moving objects. Each
+// returns the outliers as a `Selection`, so they can be hidden, highlighted or deleted.
+use crate::octree::{get_point, VERTEX_STRIDE};
+use crate::selection::Selection;
+use crate::spatial_index::SpatialIndex;
+
+/// One of the filters below with its parameters, as chosen in the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutlierFilter {
+    Statistical { k: usize, std_ratio: f32 },
+    Radius { radius: f32, min_neighbours: usize },
+}
+
+impl OutlierFilter {
+    /// Builds the filter called `name`: "statistical", which reads `value` as the standard
+    /// deviation ratio, or "radius", which reads it as the radius.
+    pub fn from_parts(name: &str, num_neighbours: usize, value: f32) -> Option<Self> {
+        match name {
+            "statistical" => Some(OutlierFilter::Statistical {
+                k: num_neighbours,
+                std_ratio: value,
+            }),
+            "radius" => Some(OutlierFilter::Radius {
+                radius: value,
+                min_neighbours: num_neighbours,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_parts`, as `(name, num_neighbours, value)`.
+    pub fn parts(&self) -> (&'static str, usize, f32) {
+        match *self {
+            OutlierFilter::Statistical { k, std_ratio } => ("statistical", k, std_ratio),
+            OutlierFilter::Radius {
+                radius,
+                min_neighbours,
+            } => ("radius", min_neighbours, radius),
+        }
+    }
+
+    pub fn find_outliers(&self, index: &dyn SpatialIndex, vertex_points: &[f32]) -> Selection {
+        match *self {
+            OutlierFilter::Statistical { k, std_ratio } => {
+                statistical_outliers(index, vertex_points, k, std_ratio)
+            }
+            OutlierFilter::Radius {
+                radius,
+                min_neighbours,
+            } => radius_outliers(index, vertex_points, radius, min_neighbours),
+        }
+    }
+}
+
+/// Finds the points whose mean distance to their `k` nearest neighbours is more than
+/// `std_ratio` standard deviations above the mean of that distance over all points.
+pub fn statistical_outliers(
+    index: &dyn SpatialIndex,
+    vertex_points: &[f32],
+    k: usize,
+    std_ratio: f32,
+) -> Selection {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    let mut outliers = Selection::empty(num_points);
+    if num_points < 2 || k == 0 {
+        return outliers;
+    }
+
+    // Every point is its own nearest neighbour, so one more is asked for and skipped
+    let mean_distances: Vec<f32> = (0..num_points)
+        .map(|point_index| {
+            let point = get_point(vertex_points, point_index);
+            let neighbours = index.knn(&point, k + 1, vertex_points);
+            let distances: Vec<f32> = neighbours
+                .iter()
+                .filter(|&&(i, _)| i != point_index)
+                .take(k)
+                .map(|&(_, distance)| distance)
+                .collect();
+            distances.iter().sum::<f32>() / distances.len().max(1) as f32
+        })
+        .collect();
+
+    let mean = mean_distances.iter().map(|&d| d as f64).sum::<f64>() / num_points as f64;
+    let variance = mean_distances
+        .iter()
+        .map(|&d| (d as f64 - mean).powi(2))
+        .sum::<f64>()
+        / (num_points - 1) as f64;
+    let threshold = mean + std_ratio as f64 * variance.sqrt();
+
+    for (point_index, &distance) in mean_distances.iter().enumerate() {
+        if distance as f64 > threshold {
+            outliers.insert(point_index);
+        }
+    }
+    outliers
+}
+
+/// Finds the points with fewer than `min_neighbours` other points within `radius`.
+pub fn radius_outliers(
+    index: &dyn SpatialIndex,
+    vertex_points: &[f32],
+    radius: f32,
+    min_neighbours: usize,
+) -> Selection {
+    let num_points = vertex_points.len() / VERTEX_STRIDE;
+    let mut outliers = Selection::empty(num_points);
+    for point_index in 0..num_points {
+        let point = get_point(vertex_points, point_index);
+        // The point itself is always within the radius
+        let num_neighbours = index
+            .query_sphere(&point, radius, vertex_points)
+            .len()
+            .saturating_sub(1);
+        if num_neighbours < min_neighbours {
+            outliers.insert(point_index);
+        }
+    }
+    outliers
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{Octree, OctreeConfig};
+
+    // A dense cube of points with a few strays far outside it, at the end of the buffer
+    fn cube_with_strays(num_strays: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(9);
+        let mut vertex_points = Vec::new();
+        for _ in 0..2000 {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        for stray in 0..num_strays {
+            let direction = Vec3::new(1.0, stray as f32, -2.0).normalize();
+            let point = direction * (4.0 + stray as f32);
+            vertex_points.extend_from_slice(&[point.x, point.y, point.z]);
+            vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        }
+        vertex_points
+    }
+
+    #[test]
+    fn statistical_filter_finds_the_strays() {
+        let vertex_points = cube_with_strays(5);
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+
+        let outliers = statistical_outliers(&octree, &vertex_points, 8, 3.0);
+
+        let strays: Vec<u32> = (2000..2005).collect();
+        assert_eq!(outliers.indices().collect::<Vec<_>>(), strays);
+    }
+
+    #[test]
+    fn radius_filter_finds_the_strays() {
+        let vertex_points = cube_with_strays(5);
+        let (octree, _) = Octree::build(&vertex_points, OctreeConfig::default());
+
+        let outliers = radius_outliers(&octree, &vertex_points, 0.5, 2);
+
+        let strays: Vec<u32> = (2000..2005).collect();
+        assert_eq!(outliers.indices().collect::<Vec<_>>(), strays);
+        assert_eq!(radius_outliers(&octree, &vertex_points, 0.5, 0).len(), 0);
+    }
+}
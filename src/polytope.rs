@@ -0,0 +1,55 @@
+// polytope.rs
+use nalgebra_glm::Vec3;
+
+use crate::frustum::{classify_aabb, Containment, Plane};
+
+/// A convex region, the intersection of the inner sides of a set of planes. It need not
+/// be bounded: a single plane is a half-space, and two opposing planes are a slab.
+#[derive(Clone, Debug)]
+pub struct ConvexPolytope {
+    pub planes: Vec<Plane>,
+}
+
+impl ConvexPolytope {
+    pub fn new(planes: Vec<Plane>) -> Self {
+        ConvexPolytope { planes }
+    }
+
+    /// The points whose offset along `normal`, `normal · p / |normal|`, lies in
+    /// `[min_offset, max_offset]`. Used for section cuts.
+    pub fn slab(normal: &Vec3, min_offset: f32, max_offset: f32) -> Self {
+        let normal = normal.normalize();
+        ConvexPolytope {
+            planes: vec![
+                Plane::new(normal, -min_offset),
+                Plane::new(-normal, max_offset),
+            ],
+        }
+    }
+
+    /// A box centred on `center` whose faces are perpendicular to `axes`, reaching
+    /// `half_extents[i]` along `axes[i]` on either side. The axes need not be unit length,
+    /// but should be mutually perpendicular.
+    pub fn oriented_box(center: &Vec3, axes: &[Vec3; 3], half_extents: &Vec3) -> Self {
+        let mut planes = Vec::with_capacity(6);
+        for (axis, &half_extent) in axes.iter().zip(half_extents.iter()) {
+            let axis = axis.normalize();
+            let offset = axis.dot(center);
+            planes.push(Plane::new(axis, half_extent - offset));
+            planes.push(Plane::new(-axis, half_extent + offset));
+        }
+        ConvexPolytope { planes }
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Classifies the axis-aligned box `[min, max]` against the region. Like
+    /// `Frustum::classify_aabb`, a box reported as `Intersecting` may lie just outside.
+    pub fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        classify_aabb(&self.planes, min, max)
+    }
+}
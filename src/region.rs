@@ -0,0 +1,162 @@
+// region.rs
+use nalgebra_glm::Vec3;
+
+use crate::frustum::Containment;
+use crate::polytope::ConvexPolytope;
+
+/// A region of space the spatial indexes can select points from. Every region shares one
+/// traversal: nodes classified `Outside` are skipped, nodes `Inside` are accepted whole,
+/// and the points of `Intersecting` leaves are tested one by one.
+pub trait Region {
+    fn contains_point(&self, point: &Vec3) -> bool;
+
+    /// Classifies the axis-aligned box `[min, max]`. May report `Intersecting` for boxes
+    /// that are actually outside, but never `Outside` or `Inside` wrongly.
+    fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment;
+}
+
+impl Region for ConvexPolytope {
+    fn contains_point(&self, point: &Vec3) -> bool {
+        ConvexPolytope::contains_point(self, point)
+    }
+
+    fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        ConvexPolytope::classify_aabb(self, min, max)
+    }
+}
+
+/// The points within `radius` of the segment from `start` to `end`: a cylinder with
+/// hemispherical caps.
+#[derive(Clone, Copy, Debug)]
+pub struct Capsule {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+}
+
+impl Region for Capsule {
+    fn contains_point(&self, point: &Vec3) -> bool {
+        segment_distance(&self.start, &self.end, point) <= self.radius
+    }
+
+    fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        // Pruned with the box's bounding sphere, which is cheap and tight enough for
+        // roughly cubic nodes
+        let center = (min + max) / 2.0;
+        let half_diagonal = nalgebra_glm::distance(min, max) / 2.0;
+        if segment_distance(&self.start, &self.end, &center) - half_diagonal > self.radius {
+            return Containment::Outside;
+        }
+        classify_by_corners(self, min, max)
+    }
+}
+
+/// The points within `radius` of the segment from `start` to `end` whose projection onto
+/// its line falls between the two: a cylinder with flat caps.
+#[derive(Clone, Copy, Debug)]
+pub struct Cylinder {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+}
+
+impl Cylinder {
+    fn axis_slab(&self) -> ConvexPolytope {
+        let normal = (self.end - self.start).normalize();
+        ConvexPolytope::slab(&normal, normal.dot(&self.start), normal.dot(&self.end))
+    }
+}
+
+impl Region for Cylinder {
+    fn contains_point(&self, point: &Vec3) -> bool {
+        let axis = self.end - self.start;
+        let along_axis = (point - self.start).dot(&axis);
+        if along_axis < 0.0 || along_axis > axis.norm_squared() {
+            return false;
+        }
+        segment_distance(&self.start, &self.end, point) <= self.radius
+    }
+
+    fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        // The cylinder lies within both the capsule on the same segment and the slab
+        // between its caps, so a box outside either is outside the cylinder
+        let capsule = Capsule {
+            start: self.start,
+            end: self.end,
+            radius: self.radius,
+        };
+        if capsule.classify_aabb(min, max) == Containment::Outside {
+            return Containment::Outside;
+        }
+        if self.start != self.end
+            && self.axis_slab().classify_aabb(min, max) == Containment::Outside
+        {
+            return Containment::Outside;
+        }
+        classify_by_corners(self, min, max)
+    }
+}
+
+/// The shape the draggable filter selects points with.
+#[derive(Clone, Copy, Debug)]
+pub enum FilterShape {
+    Sphere { center: Vec3, radius: f32 },
+    Capsule(Capsule),
+    Cylinder(Cylinder),
+}
+
+impl FilterShape {
+    /// Builds the shape called `name`: "sphere", "capsule" or "cylinder". A sphere is
+    /// centred on `start` and ignores `end`.
+    pub fn from_parts(name: &str, start: Vec3, end: Vec3, radius: f32) -> Option<Self> {
+        match name {
+            "sphere" => Some(FilterShape::Sphere {
+                center: start,
+                radius,
+            }),
+            "capsule" => Some(FilterShape::Capsule(Capsule { start, end, radius })),
+            "cylinder" => Some(FilterShape::Cylinder(Cylinder { start, end, radius })),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_parts`, as `(name, start, end, radius)`.
+    pub fn parts(&self) -> (&'static str, Vec3, Vec3, f32) {
+        match *self {
+            FilterShape::Sphere { center, radius } => ("sphere", center, center, radius),
+            FilterShape::Capsule(Capsule { start, end, radius }) => ("capsule", start, end, radius),
+            FilterShape::Cylinder(Cylinder { start, end, radius }) => {
+                ("cylinder", start, end, radius)
+            }
+        }
+    }
+}
+
+/// Distance from `point` to the closest point of the segment from `start` to `end`.
+pub fn segment_distance(start: &Vec3, end: &Vec3, point: &Vec3) -> f32 {
+    let segment = end - start;
+    let length_squared = segment.norm_squared();
+    let t = if length_squared > 0.0 {
+        ((point - start).dot(&segment) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    nalgebra_glm::distance(&(start + segment * t), point)
+}
+
+// For a convex region, a box is inside exactly when all eight of its corners are
+fn classify_by_corners(region: &impl Region, min: &Vec3, max: &Vec3) -> Containment {
+    let all_corners_inside = (0..8).all(|corner: usize| {
+        let point = Vec3::new(
+            if corner & 1 != 0 { max.x } else { min.x },
+            if corner & 2 != 0 { max.y } else { min.y },
+            if corner & 4 != 0 { max.z } else { min.z },
+        );
+        region.contains_point(&point)
+    });
+    if all_corners_inside {
+        Containment::Inside
+    } else {
+        Containment::Intersecting
+    }
+}
@@ -0,0 +1,349 @@
+// registration.rs
+//
+// Aligns a second scan to the scene with iterative closest point (ICP). Each iteration
+// pairs every point of the moving cloud with its nearest scene point, then moves the
+// cloud to bring the pairs together.
+use nalgebra_glm::{Mat3, Mat4, Vec3};
+use wasm_bindgen::prelude::*;
+
+use crate::octree::{get_point, NORMAL_OFFSET, VERTEX_STRIDE};
+use crate::spatial_index::SpatialIndex;
+
+/// What the distance between a pair of points is measured as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcpMethod {
+    /// The distance between the points.
+    PointToPoint,
+    /// The distance from the moving point to the tangent plane of the scene point, which
+    /// lets flat surfaces slide along each other. Needs the scene's normals.
+    PointToPlane,
+}
+
+impl IcpMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "point-to-point" => Some(IcpMethod::PointToPoint),
+            "point-to-plane" => Some(IcpMethod::PointToPlane),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IcpConfig {
+    pub max_iterations: usize,
+    /// Pairs further apart than this are left out.
+    pub max_correspondence_distance: f32,
+    /// The alignment has converged once the RMS error changes by less than this.
+    pub tolerance: f32,
+}
+
+impl Default for IcpConfig {
+    fn default() -> Self {
+        IcpConfig {
+            max_iterations: 50,
+            max_correspondence_distance: 0.1,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+/// The outcome of an alignment.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct IcpResult {
+    pub iterations: usize,
+    /// Whether the RMS error settled before `max_iterations` ran out.
+    pub converged: bool,
+    transform: Mat4,
+    rms_errors: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl IcpResult {
+    /// The rigid transform that moves the cloud onto the scene, as a column-major 4x4 matrix.
+    #[wasm_bindgen(getter)]
+    pub fn transform(&self) -> Vec<f32> {
+        self.transform.as_slice().to_vec()
+    }
+
+    /// RMS distance between the paired points at the start of each iteration.
+    #[wasm_bindgen(getter)]
+    pub fn rms_errors(&self) -> Vec<f32> {
+        self.rms_errors.clone()
+    }
+}
+
+impl IcpResult {
+    pub fn transform_matrix(&self) -> &Mat4 {
+        &self.transform
+    }
+}
+
+/// Finds the rigid transform that moves `moving_points` onto the scene points indexed by
+/// `index`. Both are in the point buffer's layout.
+pub fn icp(
+    index: &dyn SpatialIndex,
+    scene_points: &[f32],
+    moving_points: &[f32],
+    method: IcpMethod,
+    config: &IcpConfig,
+) -> IcpResult {
+    let mut rotation = Mat3::identity();
+    let mut translation = Vec3::zeros();
+    let mut rms_errors: Vec<f32> = Vec::new();
+    let mut converged = false;
+
+    for _ in 0..config.max_iterations {
+        let pairs = find_pairs(
+            index,
+            scene_points,
+            moving_points,
+            &rotation,
+            &translation,
+            config.max_correspondence_distance,
+        );
+        let rms_error = match method {
+            IcpMethod::PointToPoint => rms(pairs.iter().map(|(moving, scene, _)| moving - scene)),
+            IcpMethod::PointToPlane => rms(pairs
+                .iter()
+                .filter(|(_, _, normal)| *normal != Vec3::zeros())
+                .map(|(moving, scene, normal)| normal * normal.dot(&(moving - scene)))),
+        };
+        if let Some(&previous) = rms_errors.last() {
+            if (previous - rms_error).abs() < config.tolerance {
+                rms_errors.push(rms_error);
+                converged = true;
+                break;
+            }
+        }
+        rms_errors.push(rms_error);
+
+        let step = match method {
+            IcpMethod::PointToPoint => point_to_point_step(&pairs),
+            IcpMethod::PointToPlane => point_to_plane_step(&pairs),
+        };
+        let Some((step_rotation, step_translation)) = step else {
+            break;
+        };
+        rotation = step_rotation * rotation;
+        translation = step_rotation * translation + step_translation;
+    }
+
+    let mut transform = nalgebra_glm::mat3_to_mat4(&rotation);
+    transform.set_column(3, &translation.push(1.0));
+    IcpResult {
+        iterations: rms_errors.len(),
+        converged,
+        transform,
+        rms_errors,
+    }
+}
+
+fn rms(offsets: impl Iterator<Item = Vec3>) -> f32 {
+    let (sum, count) = offsets.fold((0.0, 0), |(sum, count), offset| {
+        (sum + offset.norm_squared(), count + 1)
+    });
+    if count == 0 {
+        0.0
+    } else {
+        (sum / count as f32).sqrt()
+    }
+}
+
+// Each moved point with its nearest scene point and that point's normal
+fn find_pairs(
+    index: &dyn SpatialIndex,
+    scene_points: &[f32],
+    moving_points: &[f32],
+    rotation: &Mat3,
+    translation: &Vec3,
+    max_distance: f32,
+) -> Vec<(Vec3, Vec3, Vec3)> {
+    (0..moving_points.len() / VERTEX_STRIDE)
+        .filter_map(|point_index| {
+            let moved = rotation * get_point(moving_points, point_index) + translation;
+            let &(nearest, distance) = index.knn(&moved, 1, scene_points).first()?;
+            if distance > max_distance {
+                return None;
+            }
+            let normal = nearest * VERTEX_STRIDE + NORMAL_OFFSET;
+            Some((
+                moved,
+                get_point(scene_points, nearest),
+                Vec3::from_column_slice(&scene_points[normal..normal + 3]),
+            ))
+        })
+        .collect()
+}
+
+// The rotation and translation that best align the pairs, by the Kabsch algorithm
+fn point_to_point_step(pairs: &[(Vec3, Vec3, Vec3)]) -> Option<(Mat3, Vec3)> {
+    if pairs.len() < 3 {
+        return None;
+    }
+    let moving_centroid =
+        pairs.iter().map(|(moving, _, _)| moving).sum::<Vec3>() / pairs.len() as f32;
+    let scene_centroid = pairs.iter().map(|(_, scene, _)| scene).sum::<Vec3>() / pairs.len() as f32;
+    let mut covariance = Mat3::zeros();
+    for (moving, scene, _) in pairs {
+        covariance += (moving - moving_centroid) * (scene - scene_centroid).transpose();
+    }
+
+    let svd = covariance.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    // Flip the weakest axis if needed so that the result is a rotation, not a reflection
+    let mut correction = Mat3::identity();
+    correction[(2, 2)] = (v_t.transpose() * u.transpose()).determinant().signum();
+    let rotation = v_t.transpose() * correction * u.transpose();
+    Some((rotation, scene_centroid - rotation * moving_centroid))
+}
+
+// Minimises the point-to-plane distances for a small rotation, where rotating by the
+// angles w is close to adding w × p, which leaves a linear least squares problem in w and
+// the translation
+fn point_to_plane_step(pairs: &[(Vec3, Vec3, Vec3)]) -> Option<(Mat3, Vec3)> {
+    let mut normal_matrix = [[0.0f64; 6]; 6];
+    let mut rhs = [0.0f64; 6];
+    let mut num_pairs = 0;
+    for (moving, scene, normal) in pairs {
+        if *normal == Vec3::zeros() {
+            continue;
+        }
+        let angular = moving.cross(normal);
+        let row = [
+            angular.x, angular.y, angular.z, normal.x, normal.y, normal.z,
+        ]
+        .map(f64::from);
+        let residual = f64::from(normal.dot(&(scene - moving)));
+        for i in 0..6 {
+            for j in 0..6 {
+                normal_matrix[i][j] += row[i] * row[j];
+            }
+            rhs[i] += row[i] * residual;
+        }
+        num_pairs += 1;
+    }
+    if num_pairs < 6 {
+        return None;
+    }
+
+    let solution = solve(normal_matrix, rhs)?;
+    let angles = Vec3::new(solution[0] as f32, solution[1] as f32, solution[2] as f32);
+    let translation = Vec3::new(solution[3] as f32, solution[4] as f32, solution[5] as f32);
+    let rotation = match angles.try_normalize(f32::EPSILON) {
+        Some(axis) => nalgebra_glm::mat4_to_mat3(&nalgebra_glm::rotation(angles.norm(), &axis)),
+        None => Mat3::identity(),
+    };
+    Some((rotation, translation))
+}
+
+// Gaussian elimination with partial pivoting. Returns `None` for a singular system
+fn solve(mut matrix: [[f64; 6]; 6], mut rhs: [f64; 6]) -> Option<[f64; 6]> {
+    for column in 0..6 {
+        let pivot = (column..6)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+        let pivot_row = matrix[column];
+        for row in column + 1..6 {
+            let factor = matrix[row][column] / pivot_row[column];
+            for (value, pivot_value) in matrix[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[column];
+        }
+    }
+    let mut solution = [0.0; 6];
+    for row in (0..6).rev() {
+        let known: f64 = (row + 1..6).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - known) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+/// Moves the points, and turns their normals, by the rigid `transform`.
+pub fn transform_points(vertex_points: &mut [f32], transform: &Mat4) {
+    let rotation = nalgebra_glm::mat4_to_mat3(transform);
+    let translation = transform.column(3).xyz();
+    for vertex in vertex_points.chunks_exact_mut(VERTEX_STRIDE) {
+        let point = rotation * Vec3::from_column_slice(&vertex[..3]) + translation;
+        let normal = rotation * Vec3::from_column_slice(&vertex[NORMAL_OFFSET..NORMAL_OFFSET + 3]);
+        vertex[..3].copy_from_slice(point.as_slice());
+        vertex[NORMAL_OFFSET..NORMAL_OFFSET + 3].copy_from_slice(normal.as_slice());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::octree::{Octree, OctreeConfig};
+
+    // Points on the faces of a box, uneven so that it has only one good alignment, with
+    // outward normals
+    fn box_scan() -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(24);
+        let half_extents = Vec3::new(0.8, 0.5, 0.3);
+        let mut vertex_points = Vec::new();
+        for _ in 0..3000 {
+            let axis = rng.gen_range(0..3);
+            let side = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+            let mut point = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .component_mul(&half_extents);
+            point[axis] = side * half_extents[axis];
+            let mut normal = Vec3::zeros();
+            normal[axis] = side;
+            vertex_points.extend_from_slice(point.as_slice());
+            vertex_points.extend_from_slice(&[0.5; 3]);
+            vertex_points.extend_from_slice(normal.as_slice());
+        }
+        vertex_points
+    }
+
+    #[test]
+    fn both_methods_undo_a_small_rigid_motion() {
+        let scene_points = box_scan();
+        let (octree, _) = Octree::build(&scene_points, OctreeConfig::default());
+        let motion = nalgebra_glm::translation(&Vec3::new(0.04, -0.03, 0.02))
+            * nalgebra_glm::rotation(0.08, &Vec3::new(0.3, 1.0, 0.2).normalize());
+        let mut moving_points = scene_points.clone();
+        transform_points(&mut moving_points, &motion);
+
+        for method in [IcpMethod::PointToPoint, IcpMethod::PointToPlane] {
+            let config = IcpConfig {
+                max_iterations: 100,
+                max_correspondence_distance: 0.3,
+                tolerance: 1e-7,
+            };
+            let result = icp(&octree, &scene_points, &moving_points, method, &config);
+
+            assert!(result.converged, "{:?} did not converge", method);
+            assert!(result.rms_errors[0] > 0.01);
+            assert!(*result.rms_errors.last().unwrap() < 1e-3);
+            let recovered = result.transform_matrix() * motion;
+            assert!(
+                (recovered - Mat4::identity()).abs().max() < 1e-3,
+                "{:?}",
+                method
+            );
+
+            let mut aligned = moving_points.clone();
+            transform_points(&mut aligned, result.transform_matrix());
+            let worst = (0..aligned.len() / VERTEX_STRIDE)
+                .map(|i| {
+                    nalgebra_glm::distance(&get_point(&aligned, i), &get_point(&scene_points, i))
+                })
+                .fold(0.0, f32::max);
+            assert!(worst < 2e-3);
+        }
+    }
+}
@@ -1,8 +1,38 @@
+use crate::input::add_class_filter_event_listener;
+use crate::input::add_cloud_distance_event_listener;
+use crate::input::add_cluster_event_listener;
+use crate::input::add_color_mode_event_listener;
+use crate::input::add_downsample_event_listener;
+use crate::input::add_fit_event_listener;
+use crate::input::add_ground_event_listener;
+use crate::input::add_icp_event_listener;
+use crate::input::add_normals_event_listener;
 use crate::input::add_num_points_event_listener;
+use crate::input::add_outlier_event_listener;
+use crate::input::add_pick_event_listener;
+use crate::input::add_screen_selection_event_listener;
+use crate::input::add_second_cloud_event_listener;
+use crate::input::add_selection_button_event_listener;
+use crate::input::add_selection_tool_event_listener;
 use crate::input::add_slider_event_listener;
 use crate::input::add_wheel_event_listener;
 use crate::input::add_xyz_event_listener;
+use crate::input::create_class_filter_handler;
+use crate::input::create_cloud_distance_handler;
+use crate::input::create_cluster_handler;
+use crate::input::create_color_mode_handler;
+use crate::input::create_downsample_handler;
+use crate::input::create_fit_handler;
+use crate::input::create_ground_handler;
+use crate::input::create_icp_handler;
+use crate::input::create_normals_handler;
 use crate::input::create_num_points_handler;
+use crate::input::create_outlier_handler;
+use crate::input::create_pick_handler;
+use crate::input::create_screen_selection_handler;
+use crate::input::create_second_cloud_handler;
+use crate::input::create_selection_button_handler;
+use crate::input::create_selection_tool_handler;
 use crate::input::create_slider_handler;
 use crate::input::create_wheel_handler;
 use crate::input::create_xyz_handler;
@@ -12,13 +42,28 @@ use crate::matrix::set_uniform_matrices;
 // render.rs
 use crate::mouse::MouseState;
 
-use crate::vertex_buffer::VertexData;
+use crate::octree::{get_point, LodCamera, COLOR_OFFSET, NORMAL_OFFSET, VERTEX_STRIDE};
+use crate::vertex_buffer::{
+    generate_selection_outline_vertices, update_edited_vbos, update_point_ebo,
+    update_second_cloud_vbos, update_selection_outline_vbo, ColorMode, VertexData,
+};
+use crate::worker_pool::SpatialWorkers;
 use crate::MVMatrixValues;
+use nalgebra_glm::Mat4;
 use std::{cell::RefCell, rc::Rc};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::WebGlBuffer;
-use web_sys::{WebGlProgram, WebGl2RenderingContext};
+use web_sys::{WebGl2RenderingContext, WebGlProgram};
+
+type RenderLoop = Rc<RefCell<Option<Closure<dyn FnMut()>>>>;
+
+// Most points drawn per frame when the whole cloud is shown
+const POINT_BUDGET: usize = 1_000_000;
+// Floats per vertex in the axis, cube and filter shape buffers: x, y, z, r, g, b
+const SHAPE_VERTEX_STRIDE: usize = 6;
+// Largest on-screen gap, in pixels, between the samples of a node drawn at reduced detail
+const LOD_SCREEN_ERROR: f32 = 2.0;
 
 pub fn render_scene(
     gl: &WebGl2RenderingContext,
@@ -33,38 +78,71 @@ pub fn render_scene(
 
     set_uniform_matrices(gl, program, &mv_matrix, &p_matrix);
     setup_rendering(gl);
+    {
+        let mut vertex_data = vertex_data.borrow_mut();
+        if vertex_data.points_changed {
+            update_edited_vbos(gl, &mut vertex_data);
+        }
+        if let Some(spatial_workers) = crate::spatial_workers() {
+            // The shards are also loaded again if the workers holding them failed
+            if vertex_data.points_changed || spatial_workers.shards_lost() {
+                spatial_workers
+                    .load_shards(&vertex_data.point_vertices, vertex_data.spatial_index_kind);
+            }
+        }
+        vertex_data.points_changed = false;
+    }
+    cull_points(gl, &mut vertex_data.borrow_mut(), &mv_matrix, &p_matrix);
 
-    let scale_factor_location = gl.get_uniform_location(&program, "uScaleFactor").unwrap();
+    let scale_factor_location = gl.get_uniform_location(program, "uScaleFactor").unwrap();
+    let u_is_rendering_highlight = gl
+        .get_uniform_location(program, "uIsRenderingHighlight")
+        .unwrap();
     let u_is_rendering_cubes = gl
-        .get_uniform_location(&program, "uIsRenderingCubes")
+        .get_uniform_location(program, "uIsRenderingCubes")
         .unwrap();
     let u_cube_transparency = gl
-        .get_uniform_location(&program, "uCubeTransparency")
+        .get_uniform_location(program, "uCubeTransparency")
         .unwrap();
     let u_is_rendering_points = gl
-        .get_uniform_location(&program, "uIsRenderingPoints")
+        .get_uniform_location(program, "uIsRenderingPoints")
         .unwrap();
     let u_is_rendering_draggable_point = gl
-        .get_uniform_location(&program, "uIsRenderingDraggablePoint")
+        .get_uniform_location(program, "uIsRenderingDraggablePoint")
         .unwrap();
     let u_draggable_point_transparency = gl
-        .get_uniform_location(&program, "uDraggablePointTransparency")
+        .get_uniform_location(program, "uDraggablePointTransparency")
         .unwrap();
     let u_is_rendering_sphere_surface = gl
-        .get_uniform_location(&program, "uIsRenderingSphereSurface")
+        .get_uniform_location(program, "uIsRenderingSphereSurface")
         .unwrap();
     let u_sphere_surface_transparency = gl
-        .get_uniform_location(&program, "uSphereSurfaceTransparency")
+        .get_uniform_location(program, "uSphereSurfaceTransparency")
+        .unwrap();
+    let u_color_mode = gl.get_uniform_location(program, "uColorMode").unwrap();
+    let u_is_rendering_second_cloud = gl
+        .get_uniform_location(program, "uIsRenderingSecondCloud")
         .unwrap();
 
     gl.uniform1f(Some(&scale_factor_location), -distance);
+    let color_mode = match vertex_data.borrow().color_mode {
+        ColorMode::Vertex => 0,
+        ColorMode::Shaded => 1,
+        ColorMode::Cluster => 2,
+        ColorMode::Classification => 3,
+    };
+    gl.uniform1i(Some(&u_color_mode), color_mode);
 
     let vertex_data_ref = vertex_data.clone();
 
+    // The point buffer has a normal after the position and color; the other buffers stop
+    // at the color, and their normal attribute is held at zero. The scalar attribute is
+    // held at -1 until `bind_scalar_attribute` is called
     fn bind_and_enable_attributes(
         gl: &WebGl2RenderingContext,
         vbo: &WebGlBuffer,
         ebo: Option<&WebGlBuffer>,
+        stride: usize,
     ) {
         gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(vbo));
 
@@ -72,12 +150,13 @@ pub fn render_scene(
             gl.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(ebo));
         }
 
+        let float_size = std::mem::size_of::<f32>() as i32;
         gl.vertex_attrib_pointer_with_i32(
             0,
             3,
             WebGl2RenderingContext::FLOAT,
             false,
-            6 * std::mem::size_of::<f32>() as i32,
+            stride as i32 * float_size,
             0,
         );
         gl.enable_vertex_attrib_array(0);
@@ -87,10 +166,35 @@ pub fn render_scene(
             3,
             WebGl2RenderingContext::FLOAT,
             false,
-            6 * std::mem::size_of::<f32>() as i32,
-            3 * std::mem::size_of::<f32>() as i32,
+            stride as i32 * float_size,
+            COLOR_OFFSET as i32 * float_size,
         );
         gl.enable_vertex_attrib_array(1);
+
+        gl.disable_vertex_attrib_array(3);
+        gl.vertex_attrib1f(3, -1.0);
+
+        if stride == VERTEX_STRIDE {
+            gl.vertex_attrib_pointer_with_i32(
+                2,
+                3,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                stride as i32 * float_size,
+                NORMAL_OFFSET as i32 * float_size,
+            );
+            gl.enable_vertex_attrib_array(2);
+        } else {
+            gl.disable_vertex_attrib_array(2);
+            gl.vertex_attrib3f(2, 0.0, 0.0, 0.0);
+        }
+    }
+
+    // The value each point is colored by, kept in its own buffer alongside the point buffer
+    fn bind_scalar_attribute(gl: &WebGl2RenderingContext, scalar_vbo: &WebGlBuffer) {
+        gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(scalar_vbo));
+        gl.vertex_attrib_pointer_with_i32(3, 1, WebGl2RenderingContext::FLOAT, false, 0, 0);
+        gl.enable_vertex_attrib_array(3);
     }
 
     // Render XYZ axis lines
@@ -98,9 +202,35 @@ pub fn render_scene(
     gl.uniform1i(Some(&u_is_rendering_cubes), 0);
     gl.uniform1i(Some(&u_is_rendering_draggable_point), 0);
     gl.uniform1i(Some(&u_is_rendering_sphere_surface), 0);
-    bind_and_enable_attributes(gl, &vertex_data_ref.borrow().axis_vbo, None);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().axis_vbo,
+        None,
+        SHAPE_VERTEX_STRIDE,
+    );
     gl.draw_arrays(WebGl2RenderingContext::LINES, 0, 6);
 
+    // Render highlighted points, before the others so that they show around them
+    gl.uniform1i(Some(&u_is_rendering_points), 1);
+    gl.uniform1i(Some(&u_is_rendering_highlight), 1);
+    gl.uniform1i(Some(&u_is_rendering_cubes), 0);
+    gl.uniform1i(Some(&u_is_rendering_draggable_point), 0);
+    gl.uniform1i(Some(&u_is_rendering_sphere_surface), 0);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().point_vbo,
+        Some(&vertex_data_ref.borrow().highlight_ebo),
+        VERTEX_STRIDE,
+    );
+    bind_scalar_attribute(gl, &vertex_data_ref.borrow().scalar_vbo);
+    gl.draw_elements_with_i32(
+        WebGl2RenderingContext::POINTS,
+        vertex_data_ref.borrow().num_highlighted_points as i32,
+        WebGl2RenderingContext::UNSIGNED_INT,
+        0,
+    );
+    gl.uniform1i(Some(&u_is_rendering_highlight), 0);
+
     // Render points
     gl.uniform1i(Some(&u_is_rendering_points), 1);
     gl.uniform1i(Some(&u_is_rendering_cubes), 0);
@@ -111,8 +241,9 @@ pub fn render_scene(
         gl,
         &vertex_data_ref.borrow().point_vbo,
         Some(&vertex_data_ref.borrow().point_ebo),
+        VERTEX_STRIDE,
     );
-
+    bind_scalar_attribute(gl, &vertex_data_ref.borrow().scalar_vbo);
 
     web_sys::console::log_1(&JsValue::from(vertex_data_ref.borrow().num_points));
 
@@ -123,17 +254,45 @@ pub fn render_scene(
         0,
     );
 
+    // Render the second cloud
+    {
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        if vertex_data.second_cloud_changed {
+            update_second_cloud_vbos(gl, &vertex_data);
+            vertex_data.second_cloud_changed = false;
+        }
+    }
+    gl.uniform1i(Some(&u_is_rendering_second_cloud), 1);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().second_cloud_vbo,
+        None,
+        VERTEX_STRIDE,
+    );
+    bind_scalar_attribute(gl, &vertex_data_ref.borrow().second_cloud_scalar_vbo);
+    gl.draw_arrays(
+        WebGl2RenderingContext::POINTS,
+        0,
+        (vertex_data_ref.borrow().second_cloud_vertices.len() / VERTEX_STRIDE) as i32,
+    );
+    gl.uniform1i(Some(&u_is_rendering_second_cloud), 0);
+
     // Render octree cubes
     gl.uniform1i(Some(&u_is_rendering_points), 0);
     gl.uniform1i(Some(&u_is_rendering_cubes), 1);
     gl.uniform1i(Some(&u_is_rendering_draggable_point), 0);
     gl.uniform1i(Some(&u_is_rendering_sphere_surface), 0);
     gl.uniform1f(Some(&u_cube_transparency), 0.3); // Set the desired transparency value
-    bind_and_enable_attributes(gl, &vertex_data_ref.borrow().cube_vbo, None);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().cube_vbo,
+        None,
+        SHAPE_VERTEX_STRIDE,
+    );
     gl.draw_arrays(
         WebGl2RenderingContext::TRIANGLES,
         0,
-        vertex_data_ref.borrow().octree.get_num_cubes() as i32 * 36,
+        vertex_data_ref.borrow().octree.get_num_leaves() as i32 * 36,
     );
 
     // Render draggable point
@@ -142,7 +301,12 @@ pub fn render_scene(
     gl.uniform1i(Some(&u_is_rendering_draggable_point), 1);
     gl.uniform1i(Some(&u_is_rendering_sphere_surface), 0);
     gl.uniform1f(Some(&u_draggable_point_transparency), 1.0); // Set the desired transparency value
-    bind_and_enable_attributes(gl, &vertex_data_ref.borrow().draggable_point_vbo, None);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().draggable_point_vbo,
+        None,
+        SHAPE_VERTEX_STRIDE,
+    );
     gl.draw_arrays(WebGl2RenderingContext::POINTS, 0, 1);
 
     // Render sphere surface
@@ -151,12 +315,86 @@ pub fn render_scene(
     gl.uniform1i(Some(&u_is_rendering_draggable_point), 0);
     gl.uniform1i(Some(&u_is_rendering_sphere_surface), 1);
     gl.uniform1f(Some(&u_sphere_surface_transparency), 1.0); // Set the desired transparency value
-    bind_and_enable_attributes(gl, &vertex_data_ref.borrow().sphere_vbo, None);
+    bind_and_enable_attributes(
+        gl,
+        &vertex_data_ref.borrow().sphere_vbo,
+        None,
+        SHAPE_VERTEX_STRIDE,
+    );
     gl.draw_arrays(
         WebGl2RenderingContext::TRIANGLES,
         0,
         vertex_data_ref.borrow().num_sphere_vertices as i32,
     );
+
+    // Render the outline of the selection being dragged
+    let selection_outline = mouse_state.selection_outline();
+    if !selection_outline.is_empty() {
+        let outline_vertices =
+            generate_selection_outline_vertices(&selection_outline, &mv_matrix, &p_matrix);
+        gl.uniform1i(Some(&u_is_rendering_points), 0);
+        gl.uniform1i(Some(&u_is_rendering_cubes), 0);
+        gl.uniform1i(Some(&u_is_rendering_draggable_point), 0);
+        gl.uniform1i(Some(&u_is_rendering_sphere_surface), 0);
+        let vertex_data = vertex_data_ref.borrow();
+        update_selection_outline_vbo(gl, &vertex_data.selection_outline_vbo, &outline_vertices);
+        bind_and_enable_attributes(
+            gl,
+            &vertex_data.selection_outline_vbo,
+            None,
+            SHAPE_VERTEX_STRIDE,
+        );
+        gl.draw_arrays(
+            WebGl2RenderingContext::LINE_LOOP,
+            0,
+            selection_outline.len() as i32,
+        );
+    }
+}
+
+// Refills the point EBO with the points the camera can see. With nothing selected, a
+// cloud within `POINT_BUDGET` is culled node by node, and a larger one goes through the
+// octree's level-of-detail selection to keep the count within it. The work is skipped
+// while the camera and the selection are unchanged.
+fn cull_points(
+    gl: &WebGl2RenderingContext,
+    vertex_data: &mut VertexData,
+    mv_matrix: &Mat4,
+    p_matrix: &Mat4,
+) {
+    let mvp = p_matrix * mv_matrix;
+    if vertex_data.culled_mvp.as_ref() == Some(&mvp) {
+        return;
+    }
+
+    let camera = LodCamera::new(mv_matrix, p_matrix, gl.drawing_buffer_height() as f32);
+    let visible_points: Vec<u32> = match vertex_data.selected_points.as_ref() {
+        Some(selected_points) => selected_points
+            .indices()
+            .filter(|&i| {
+                camera
+                    .frustum
+                    .contains_point(&get_point(&vertex_data.point_vertices, i as usize))
+            })
+            .collect(),
+        None if vertex_data.octree.num_points() <= POINT_BUDGET => vertex_data
+            .octree
+            .query_frustum(&camera.frustum)
+            .into_iter()
+            .map(|i| i as u32)
+            .collect(),
+        None => vertex_data
+            .octree
+            .select_lod(&camera, POINT_BUDGET, LOD_SCREEN_ERROR)
+            .point_indices()
+            .into_iter()
+            .map(|i| i as u32)
+            .collect(),
+    };
+
+    update_point_ebo(gl, &vertex_data.point_ebo, &visible_points);
+    vertex_data.num_points = visible_points.len() as u32;
+    vertex_data.culled_mvp = Some(mvp);
 }
 
 fn setup_rendering(gl: &WebGl2RenderingContext) {
@@ -177,6 +415,7 @@ pub fn start_render_loop(
     gl: Rc<WebGl2RenderingContext>,
     program: WebGlProgram,
     vertex_data: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
     scale_factor: f32,
     mouse_state: Rc<RefCell<MouseState>>,
     mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
@@ -194,12 +433,84 @@ pub fn start_render_loop(
     let slider_handler = create_slider_handler(mv_matrix_values_clone.clone());
     add_slider_event_listener(slider_handler);
 
-    let num_points_handler = create_num_points_handler(gl.clone(), vertex_data.clone());
+    let num_points_handler =
+        create_num_points_handler(gl.clone(), vertex_data.clone(), spatial_workers.clone());
     add_num_points_event_listener(num_points_handler);
 
-    let xyz_handler = create_xyz_handler(gl.clone(), vertex_data.clone());
+    let xyz_handler = create_xyz_handler(
+        gl.clone(),
+        vertex_data.clone(),
+        spatial_workers.clone(),
+        mouse_state.clone(),
+    );
     add_xyz_event_listener(xyz_handler);
 
+    let pick_handler = create_pick_handler(
+        vertex_data.clone(),
+        spatial_workers.clone(),
+        scale_factor_ref.clone(),
+        mouse_state.clone(),
+        mv_matrix_values.clone(),
+    );
+    add_pick_event_listener(pick_handler);
+
+    let selection_tool_handler = create_selection_tool_handler(mouse_state.clone());
+    add_selection_tool_event_listener(selection_tool_handler);
+
+    let screen_selection_handler = create_screen_selection_handler(
+        vertex_data.clone(),
+        spatial_workers.clone(),
+        scale_factor_ref.clone(),
+        mouse_state.clone(),
+        mv_matrix_values.clone(),
+    );
+    add_screen_selection_event_listener(screen_selection_handler);
+
+    let selection_button_handler = create_selection_button_handler(vertex_data.clone());
+    add_selection_button_event_listener(selection_button_handler);
+
+    let downsample_handler = create_downsample_handler(vertex_data.clone());
+    add_downsample_event_listener(downsample_handler);
+
+    let outlier_handler =
+        create_outlier_handler(gl.clone(), vertex_data.clone(), spatial_workers.clone());
+    add_outlier_event_listener(outlier_handler);
+
+    let normals_handler = create_normals_handler(
+        gl.clone(),
+        vertex_data.clone(),
+        spatial_workers.clone(),
+        scale_factor_ref.clone(),
+        mouse_state.clone(),
+        mv_matrix_values.clone(),
+    );
+    add_normals_event_listener(normals_handler);
+
+    let color_mode_handler = create_color_mode_handler(gl.clone(), vertex_data.clone());
+    add_color_mode_event_listener(color_mode_handler);
+
+    let cluster_handler =
+        create_cluster_handler(gl.clone(), vertex_data.clone(), spatial_workers.clone());
+    add_cluster_event_listener(cluster_handler);
+
+    let fit_handler = create_fit_handler(gl.clone(), vertex_data.clone());
+    add_fit_event_listener(fit_handler);
+
+    let ground_handler = create_ground_handler(gl.clone(), vertex_data.clone());
+    add_ground_event_listener(ground_handler);
+
+    let class_filter_handler = create_class_filter_handler(vertex_data.clone());
+    add_class_filter_event_listener(class_filter_handler);
+
+    let second_cloud_handler = create_second_cloud_handler(vertex_data.clone());
+    add_second_cloud_event_listener(second_cloud_handler);
+
+    let icp_handler = create_icp_handler(vertex_data.clone());
+    add_icp_event_listener(icp_handler);
+
+    let cloud_distance_handler = create_cloud_distance_handler(vertex_data.clone());
+    add_cloud_distance_event_listener(cloud_distance_handler);
+
     *render_loop_clone.borrow_mut() = Some(create_render_loop_closure(
         gl.clone(),
         program,
@@ -219,7 +530,7 @@ fn create_render_loop_closure(
     vertex_data: Rc<RefCell<VertexData>>,
     scale_factor_ref: Rc<RefCell<f32>>,
     mouse_state: Rc<RefCell<MouseState>>,
-    render_loop: Rc<RefCell<Option<Closure<dyn FnMut()>>>>,
+    render_loop: RenderLoop,
     mv_matrix_values: Rc<RefCell<MVMatrixValues>>,
 ) -> Closure<dyn FnMut()> {
     Closure::wrap(Box::new(move || {
@@ -238,7 +549,7 @@ fn create_render_loop_closure(
     }) as Box<dyn FnMut()>)
 }
 
-fn request_animation_frame(render_loop: Rc<RefCell<Option<Closure<dyn FnMut()>>>>) {
+fn request_animation_frame(render_loop: RenderLoop) {
     web_sys::window()
         .unwrap()
         .request_animation_frame(
@@ -0,0 +1,122 @@
+// screen_selection.rs
+use nalgebra_glm::{Mat4, Vec2, Vec3};
+
+use crate::frustum::Containment;
+use crate::region::Region;
+
+/// The points whose projection falls inside a polygon drawn on the screen, such as a
+/// dragged rectangle or a freehand lasso. The polygon is in normalized device coordinates
+/// (x right, y up, both from -1 to 1) and may cross itself; crossings are resolved with
+/// the even-odd rule. Points behind the camera are never inside.
+#[derive(Clone, Debug)]
+pub struct ScreenPolygon {
+    /// The projection * model-view matrix the polygon was drawn over.
+    pub mvp: Mat4,
+    pub vertices: Vec<Vec2>,
+}
+
+impl ScreenPolygon {
+    pub fn new(mvp: Mat4, vertices: Vec<Vec2>) -> Self {
+        ScreenPolygon { mvp, vertices }
+    }
+
+    // Returns the point's position on the screen, or `None` if it is behind the camera
+    fn project(&self, point: &Vec3) -> Option<Vec2> {
+        let clip = self.mvp * nalgebra_glm::vec4(point.x, point.y, point.z, 1.0);
+        (clip.w > 0.0).then(|| clip.xy() / clip.w)
+    }
+
+    fn polygon_contains(&self, point: &Vec2) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > point.y) != (b.y > point.y) {
+                let crossing_x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                if point.x < crossing_x {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (&Vec2, &Vec2)> {
+        let next = self.vertices.iter().cycle().skip(1);
+        self.vertices.iter().zip(next)
+    }
+}
+
+impl Region for ScreenPolygon {
+    fn contains_point(&self, point: &Vec3) -> bool {
+        self.vertices.len() >= 3
+            && self
+                .project(point)
+                .is_some_and(|screen_point| self.polygon_contains(&screen_point))
+    }
+
+    fn classify_aabb(&self, min: &Vec3, max: &Vec3) -> Containment {
+        if self.vertices.len() < 3 {
+            return Containment::Outside;
+        }
+
+        // The box's projection lies within the screen rectangle bounding its projected
+        // corners, as long as none of them is behind the camera
+        let mut screen_min = Vec2::repeat(f32::INFINITY);
+        let mut screen_max = Vec2::repeat(f32::NEG_INFINITY);
+        let mut corners_behind = 0;
+        for corner in 0..8 {
+            let point = Vec3::new(
+                if corner & 1 != 0 { max.x } else { min.x },
+                if corner & 2 != 0 { max.y } else { min.y },
+                if corner & 4 != 0 { max.z } else { min.z },
+            );
+            match self.project(&point) {
+                Some(screen_point) => {
+                    screen_min = screen_min.inf(&screen_point);
+                    screen_max = screen_max.sup(&screen_point);
+                }
+                None => corners_behind += 1,
+            }
+        }
+        match corners_behind {
+            8 => return Containment::Outside,
+            0 => {}
+            _ => return Containment::Intersecting,
+        }
+
+        // With no edge crossing the rectangle, it lies either wholly inside the polygon or
+        // wholly outside it
+        if self
+            .edges()
+            .any(|(a, b)| segment_intersects_rect(a, b, &screen_min, &screen_max))
+        {
+            Containment::Intersecting
+        } else if self.polygon_contains(&screen_min) {
+            Containment::Inside
+        } else {
+            Containment::Outside
+        }
+    }
+}
+
+// Liang-Barsky clipping of the segment from `a` to `b` against the rectangle
+fn segment_intersects_rect(a: &Vec2, b: &Vec2, min: &Vec2, max: &Vec2) -> bool {
+    let delta = b - a;
+    let mut t_min = 0.0_f32;
+    let mut t_max = 1.0_f32;
+    for axis in 0..2 {
+        if delta[axis] == 0.0 {
+            if a[axis] < min[axis] || a[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let t0 = (min[axis] - a[axis]) / delta[axis];
+        let t1 = (max[axis] - a[axis]) / delta[axis];
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+        if t_min > t_max {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,176 @@
+// selection.rs
+
+/// A set of point indices into a scene of `num_points` points, stored as a bitset so that
+/// combining selections of millions of points stays cheap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    num_points: usize,
+    words: Vec<u64>,
+}
+
+/// How a new selection is combined with the current one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionOp {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl SelectionOp {
+    /// Shift adds to the selection, alt subtracts from it, and both together keep only
+    /// the points in both.
+    pub fn from_modifiers(shift_key: bool, alt_key: bool) -> Self {
+        match (shift_key, alt_key) {
+            (false, false) => SelectionOp::Replace,
+            (true, false) => SelectionOp::Add,
+            (false, true) => SelectionOp::Subtract,
+            (true, true) => SelectionOp::Intersect,
+        }
+    }
+}
+
+impl Selection {
+    pub fn empty(num_points: usize) -> Self {
+        Selection {
+            num_points,
+            words: vec![0; num_points.div_ceil(64)],
+        }
+    }
+
+    pub fn all(num_points: usize) -> Self {
+        Selection::empty(num_points).invert()
+    }
+
+    /// Indices past the end of the scene are ignored.
+    pub fn from_indices(num_points: usize, point_indices: impl IntoIterator<Item = u32>) -> Self {
+        let mut selection = Selection::empty(num_points);
+        for point_index in point_indices {
+            selection.insert(point_index as usize);
+        }
+        selection
+    }
+
+    /// The number of selected points.
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn insert(&mut self, point_index: usize) {
+        if point_index < self.num_points {
+            self.words[point_index / 64] |= 1 << (point_index % 64);
+        }
+    }
+
+    /// The selected indices in ascending order.
+    pub fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(word_index, &word)| {
+                let mut bits = word;
+                std::iter::from_fn(move || {
+                    if bits == 0 {
+                        return None;
+                    }
+                    let bit = bits.trailing_zeros();
+                    bits &= bits - 1;
+                    Some((word_index * 64) as u32 + bit)
+                })
+            })
+    }
+
+    pub fn union(&self, other: &Selection) -> Selection {
+        self.zip_words(other, |a, b| a | b)
+    }
+
+    pub fn intersection(&self, other: &Selection) -> Selection {
+        self.zip_words(other, |a, b| a & b)
+    }
+
+    pub fn difference(&self, other: &Selection) -> Selection {
+        self.zip_words(other, |a, b| a & !b)
+    }
+
+    /// Every point of the scene that is not selected.
+    pub fn invert(&self) -> Selection {
+        let mut inverted = Selection {
+            num_points: self.num_points,
+            words: self.words.iter().map(|word| !word).collect(),
+        };
+        inverted.clear_unused_bits();
+        inverted
+    }
+
+    /// Combines `other` into this selection with `op`.
+    pub fn apply(&self, op: SelectionOp, other: &Selection) -> Selection {
+        match op {
+            SelectionOp::Replace => other.clone(),
+            SelectionOp::Add => self.union(other),
+            SelectionOp::Subtract => self.difference(other),
+            SelectionOp::Intersect => self.intersection(other),
+        }
+    }
+
+    // Selections of different scenes are combined over the smaller of the two
+    fn zip_words(&self, other: &Selection, op: impl Fn(u64, u64) -> u64) -> Selection {
+        let mut combined = Selection {
+            num_points: self.num_points.min(other.num_points),
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(&a, &b)| op(a, b))
+                .collect(),
+        };
+        combined.clear_unused_bits();
+        combined
+    }
+
+    // Keeps the bits past `num_points` in the last word zero
+    fn clear_unused_bits(&mut self) {
+        let used_bits = self.num_points % 64;
+        if used_bits != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << used_bits) - 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(selection: &Selection) -> Vec<u32> {
+        selection.indices().collect()
+    }
+
+    #[test]
+    fn boolean_operations_match_set_semantics() {
+        let a = Selection::from_indices(130, [0, 5, 63, 64, 100, 129]);
+        let b = Selection::from_indices(130, [5, 64, 65, 128]);
+
+        assert_eq!(indices(&a.union(&b)), vec![0, 5, 63, 64, 65, 100, 128, 129]);
+        assert_eq!(indices(&a.intersection(&b)), vec![5, 64]);
+        assert_eq!(indices(&a.difference(&b)), vec![0, 63, 100, 129]);
+        assert_eq!(a.apply(SelectionOp::Replace, &b), b);
+        assert_eq!(a.apply(SelectionOp::Subtract, &b), a.difference(&b));
+    }
+
+    #[test]
+    fn invert_stays_within_the_scene() {
+        let selection = Selection::from_indices(70, [1, 69, 70, 500]);
+        assert_eq!(indices(&selection), vec![1, 69]);
+
+        let inverted = selection.invert();
+        let expected: Vec<u32> = (0..70).filter(|&i| i != 1 && i != 69).collect();
+        assert_eq!(indices(&inverted), expected);
+        assert_eq!(inverted.invert(), selection);
+        assert_eq!(indices(&Selection::all(70)), (0..70).collect::<Vec<u32>>());
+        assert_eq!(indices(&Selection::all(70).invert()), Vec::<u32>::new());
+    }
+}
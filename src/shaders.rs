@@ -2,10 +2,12 @@
 // wasm_bindgen is a library that facilitates interoperability between Rust and JavaScript.
 // web_sys is a crate that provides bindings for Web APIs, including WebGL.
 use wasm_bindgen::JsValue;
-use web_sys::{WebGlProgram, WebGl2RenderingContext, WebGlShader};
+use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader};
 
-pub fn create_shader_program(gl: &WebGl2RenderingContext) -> Result<web_sys::WebGlProgram, JsValue> {
-    let program = create_program(&gl)?;
+pub fn create_shader_program(
+    gl: &WebGl2RenderingContext,
+) -> Result<web_sys::WebGlProgram, JsValue> {
+    let program = create_program(gl)?;
     gl.use_program(Some(&program));
     Ok(program)
 }
@@ -15,18 +17,18 @@ pub fn create_shader_program(gl: &WebGl2RenderingContext) -> Result<web_sys::Web
 // The fragment shader determines the color of each pixel that makes up the geometry.
 pub fn create_program(gl: &WebGl2RenderingContext) -> Result<WebGlProgram, JsValue> {
     let vert_shader = compile_shader(
-        &gl,
+        gl,
         WebGl2RenderingContext::VERTEX_SHADER,
         include_str!("shaders/vertex_shader.glsl"),
     )?;
 
     let frag_shader = compile_shader(
-        &gl,
+        gl,
         WebGl2RenderingContext::FRAGMENT_SHADER,
         include_str!("shaders/fragment_shader.glsl"),
     )?;
 
-    link_program(&gl, &vert_shader, &frag_shader)
+    link_program(gl, &vert_shader, &frag_shader)
 }
 // This function compiles a shader from its source code.
 // It takes the WebGL rendering context, the shader type (vertex or fragment), and the shader source code as input.
@@ -76,6 +78,11 @@ fn link_program(
 
     gl.attach_shader(&program, vert_shader);
     gl.attach_shader(&program, frag_shader);
+    // Fix the attribute locations the vertex buffers are bound to
+    gl.bind_attrib_location(&program, 0, "position");
+    gl.bind_attrib_location(&program, 1, "color");
+    gl.bind_attrib_location(&program, 2, "normal");
+    gl.bind_attrib_location(&program, 3, "scalar");
     gl.link_program(&program);
 
     // Check if the shader program linking was successful.
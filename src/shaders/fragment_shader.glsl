@@ -3,6 +3,7 @@ precision mediump float;
 varying vec3 vColor;
 
 uniform bool uIsRenderingPoints;
+uniform bool uIsRenderingHighlight;
 uniform bool uIsRenderingCubes;
 uniform bool uIsRenderingDraggablePoint;
 uniform bool uIsRenderingSphereSurface;
@@ -15,7 +16,7 @@ void main() {
     if (uIsRenderingPoints) {
         float distance = length(gl_PointCoord - vec2(0.5, 0.5));
         if (distance > 0.5) discard;
-        gl_FragColor = vec4(vColor, 1.0);
+        gl_FragColor = uIsRenderingHighlight ? vec4(1.0, 0.0, 0.0, 1.0) : vec4(vColor, 1.0);
     } else if (uIsRenderingCubes) {
         // Set the transparency for the cubes
         gl_FragColor = vec4(1.0, 0.0, 1.0, uCubeTransparency);
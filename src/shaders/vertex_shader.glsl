@@ -1,10 +1,19 @@
 attribute vec3 position;
 attribute vec3 color;
+attribute vec3 normal;
+// The value the color mode colors by, or -1 for none. For the second cloud, its scaled
+// distance to the scene
+attribute float scalar;
 
 uniform mat4 uMVMatrix;
 uniform mat4 uPMatrix;
 uniform float uScaleFactor;
+// 0: vertex colors, 1: vertex colors shaded by the point normals, 2: cluster colors,
+// 3: ground classification colors
+uniform int uColorMode;
 uniform bool uIsRenderingPoints;
+uniform bool uIsRenderingHighlight;
+uniform bool uIsRenderingSecondCloud;
 uniform bool uIsRenderingCubes;
 uniform bool uIsRenderingDraggablePoint;
 uniform bool uIsRenderingSphereSurface;
@@ -18,7 +27,31 @@ void main() {
     if (uIsRenderingCubes) {
         // No additional processing needed for cubes
     } else if (uIsRenderingPoints) {
-        gl_PointSize = 5.0 * uScaleFactor;
+        // Highlighted points are drawn first and larger, so a ring shows around the point
+        gl_PointSize = (uIsRenderingHighlight ? 9.0 : 5.0) * uScaleFactor;
+
+        // Light from the camera; points without a normal are left unshaded
+        if (uColorMode == 1 && dot(normal, normal) > 0.0) {
+            vec4 eyePosition = uMVMatrix * vec4(position, 1.0);
+            vec3 eyeNormal = normalize(mat3(uMVMatrix) * normal);
+            float diffuse = abs(dot(eyeNormal, normalize(-eyePosition.xyz)));
+            vColor = color * (0.35 + 0.65 * diffuse);
+        } else if (uColorMode == 2) {
+            // Steps of the golden ratio spread neighbouring cluster ids across the colors
+            vColor = scalar < 0.0
+                ? vec3(0.5)
+                : 0.25 + 0.75 * fract(vec3(0.618034, 0.414214, 0.732051) * (scalar + 1.0));
+        } else if (uColorMode == 3 && scalar >= 0.0) {
+            // LAS class 2 is ground
+            vColor = scalar == 2.0 ? vec3(0.6, 0.45, 0.25) : vec3(0.3, 0.75, 0.35);
+        }
+
+        if (uIsRenderingSecondCloud) {
+            // Distances to the scene, scaled to [0, 1], run from blue through green to red
+            vColor = scalar < 0.0
+                ? vec3(0.1, 0.8, 0.9)
+                : clamp(1.5 - abs(4.0 * scalar - vec3(3.0, 2.0, 1.0)), 0.0, 1.0);
+        }
     } else if (uIsRenderingDraggablePoint) {
         gl_PointSize = 10.0 * uScaleFactor; // Larger size for the draggable point
     } else if (uIsRenderingSphereSurface) {
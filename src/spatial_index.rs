@@ -0,0 +1,483 @@
+// spatial_index.rs
+use std::cmp::Ordering;
+
+use nalgebra_glm::Vec3;
+
+use crate::polytope::ConvexPolytope;
+use crate::region::{Capsule, Cylinder, Region};
+
+/// Point queries shared by the spatial indexes. Every index stores point indices only, and
+/// reads positions from the interleaved vertex buffer it was built from.
+pub trait SpatialIndex {
+    /// Returns the indices of all points within `radius` of `center`.
+    fn query_sphere(&self, center: &Vec3, radius: f32, vertex_points: &[f32]) -> Vec<usize>;
+
+    /// Returns the indices of all points inside the axis-aligned box `[min, max]`.
+    fn query_aabb(&self, min: &Vec3, max: &Vec3, vertex_points: &[f32]) -> Vec<usize>;
+
+    /// Returns the indices of all points inside `region`.
+    fn query_region(&self, region: &dyn Region, vertex_points: &[f32]) -> Vec<usize>;
+
+    /// Returns the indices of all points inside the convex region.
+    fn query_polytope(&self, polytope: &ConvexPolytope, vertex_points: &[f32]) -> Vec<usize> {
+        self.query_region(polytope, vertex_points)
+    }
+
+    /// Returns the indices of all points within `radius` of the segment from `start` to
+    /// `end`.
+    fn query_capsule(
+        &self,
+        start: &Vec3,
+        end: &Vec3,
+        radius: f32,
+        vertex_points: &[f32],
+    ) -> Vec<usize> {
+        let capsule = Capsule {
+            start: *start,
+            end: *end,
+            radius,
+        };
+        self.query_region(&capsule, vertex_points)
+    }
+
+    /// Returns the indices of all points inside the flat-capped cylinder of `radius`
+    /// around the segment from `start` to `end`.
+    fn query_cylinder(
+        &self,
+        start: &Vec3,
+        end: &Vec3,
+        radius: f32,
+        vertex_points: &[f32],
+    ) -> Vec<usize> {
+        let cylinder = Cylinder {
+            start: *start,
+            end: *end,
+            radius,
+        };
+        self.query_region(&cylinder, vertex_points)
+    }
+
+    /// Returns the `k` points closest to `query` as `(point_index, distance)`, nearest first.
+    fn knn(&self, query: &Vec3, k: usize, vertex_points: &[f32]) -> Vec<(usize, f32)>;
+
+    /// Returns the point nearest to `ray_origin` along the ray among those within
+    /// `pick_radius` of it, as `(point_index, distance_along_ray)`.
+    fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        vertex_points: &[f32],
+    ) -> Option<(usize, f32)>;
+}
+
+/// Which spatial index answers point queries, chosen when the scene is built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpatialIndexKind {
+    Octree,
+    KdTree,
+}
+
+impl SpatialIndexKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "octree" => Some(SpatialIndexKind::Octree),
+            "kdtree" => Some(SpatialIndexKind::KdTree),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_name`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SpatialIndexKind::Octree => "octree",
+            SpatialIndexKind::KdTree => "kdtree",
+        }
+    }
+}
+
+// Orders heap entries by distance only, so nodes and point indices can share one type
+pub(crate) struct HeapEntry<T> {
+    pub(crate) distance: f32,
+    pub(crate) item: T,
+}
+
+impl<T> PartialEq for HeapEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for HeapEntry<T> {}
+
+impl<T> PartialOrd for HeapEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for HeapEntry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+pub(crate) fn aabb_overlaps(a_min: &Vec3, a_max: &Vec3, b_min: &Vec3, b_max: &Vec3) -> bool {
+    a_min.x <= b_max.x
+        && a_max.x >= b_min.x
+        && a_min.y <= b_max.y
+        && a_max.y >= b_min.y
+        && a_min.z <= b_max.z
+        && a_max.z >= b_min.z
+}
+
+pub(crate) fn aabb_contains(min: &Vec3, max: &Vec3, point: &Vec3) -> bool {
+    point.x >= min.x
+        && point.x <= max.x
+        && point.y >= min.y
+        && point.y <= max.y
+        && point.z >= min.z
+        && point.z <= max.z
+}
+
+// Distance from `point` to the closest point of the box, or 0 if it lies inside
+pub(crate) fn aabb_distance(min: &Vec3, max: &Vec3, point: &Vec3) -> f32 {
+    let closest_point = Vec3::new(
+        point.x.clamp(min.x, max.x),
+        point.y.clamp(min.y, max.y),
+        point.z.clamp(min.z, max.z),
+    );
+
+    nalgebra_glm::distance(&closest_point, point)
+}
+
+// Slab test against the box grown by `padding` on every side. Returns the distance along
+// the ray at which it enters the box, or 0 if it starts inside.
+pub(crate) fn ray_aabb_entry(
+    min: &Vec3,
+    max: &Vec3,
+    ray_origin: &Vec3,
+    ray_dir: &Vec3,
+    padding: f32,
+) -> Option<f32> {
+    let padding = Vec3::new(padding, padding, padding);
+    let (min_pos, max_pos) = (min - padding, max + padding);
+
+    let mut t_min = 0.0_f32;
+    let mut t_max = f32::INFINITY;
+    for axis in 0..3 {
+        if ray_dir[axis].abs() < f32::EPSILON {
+            if ray_origin[axis] < min_pos[axis] || ray_origin[axis] > max_pos[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min_pos[axis] - ray_origin[axis]) / ray_dir[axis];
+        let t1 = (max_pos[axis] - ray_origin[axis]) / ray_dir[axis];
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+        if t_min > t_max {
+            return None;
+        }
+    }
+    Some(t_min)
+}
+
+// Returns how far along the ray `point` lies, if it is in front of the origin and within
+// `pick_radius` of the ray. `ray_dir` must be normalized.
+pub(crate) fn ray_point_hit(
+    point: &Vec3,
+    ray_origin: &Vec3,
+    ray_dir: &Vec3,
+    pick_radius: f32,
+) -> Option<f32> {
+    let along_ray = (point - ray_origin).dot(ray_dir);
+    if along_ray < 0.0 {
+        return None;
+    }
+    let closest_on_ray = ray_origin + ray_dir * along_ray;
+    if nalgebra_glm::distance(point, &closest_on_ray) > pick_radius {
+        return None;
+    }
+    Some(along_ray)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::kdtree::KdTree;
+    use crate::octree::{get_point, Octree, OctreeConfig, VERTEX_STRIDE};
+    use crate::region::segment_distance;
+    use crate::screen_selection::ScreenPolygon;
+
+    const NUM_POINTS: usize = 3000;
+
+    fn uniform_points(rng: &mut impl Rng) -> Vec<f32> {
+        let mut vertex_points = Vec::with_capacity(NUM_POINTS * VERTEX_STRIDE);
+        for _ in 0..NUM_POINTS {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        vertex_points
+    }
+
+    // A long, thin scan along x, like a road or tunnel corridor
+    fn corridor_points(rng: &mut impl Rng) -> Vec<f32> {
+        let mut vertex_points = Vec::with_capacity(NUM_POINTS * VERTEX_STRIDE);
+        for _ in 0..NUM_POINTS {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(0.0..200.0),
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(0.0..0.5),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        vertex_points
+    }
+
+    fn random_query_point(rng: &mut impl Rng, vertex_points: &[f32]) -> Vec3 {
+        let near = get_point(vertex_points, rng.gen_range(0..NUM_POINTS));
+        near + Vec3::new(
+            rng.gen_range(-0.2..0.2),
+            rng.gen_range(-0.2..0.2),
+            rng.gen_range(-0.2..0.2),
+        )
+    }
+
+    fn random_direction(rng: &mut impl Rng) -> Vec3 {
+        Vec3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+        )
+        .normalize()
+    }
+
+    fn all_points(vertex_points: &[f32]) -> impl Iterator<Item = (usize, Vec3)> + '_ {
+        (0..vertex_points.len() / VERTEX_STRIDE).map(|i| (i, get_point(vertex_points, i)))
+    }
+
+    fn sorted(mut point_indices: Vec<usize>) -> Vec<usize> {
+        point_indices.sort_unstable();
+        point_indices
+    }
+
+    // Compares every query against a linear scan over all points
+    fn check_against_brute_force(index: &dyn SpatialIndex, vertex_points: &[f32], seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+
+        for _ in 0..20 {
+            let center = random_query_point(&mut rng, vertex_points);
+            let radius = rng.gen_range(0.05..0.8);
+            let expected: Vec<usize> = all_points(vertex_points)
+                .filter(|(_, point)| nalgebra_glm::distance(point, &center) <= radius)
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(
+                sorted(index.query_sphere(&center, radius, vertex_points)),
+                expected
+            );
+
+            let half_extent = Vec3::new(
+                rng.gen_range(0.05..1.0),
+                rng.gen_range(0.05..1.0),
+                rng.gen_range(0.05..1.0),
+            );
+            let (min, max) = (center - half_extent, center + half_extent);
+            let expected: Vec<usize> = all_points(vertex_points)
+                .filter(|(_, point)| aabb_contains(&min, &max, point))
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(
+                sorted(index.query_aabb(&min, &max, vertex_points)),
+                expected
+            );
+
+            // An oriented box with random axes, and a section slab through `center`
+            let axis_x = random_direction(&mut rng);
+            let axis_y = axis_x.cross(&random_direction(&mut rng)).normalize();
+            let axes = [axis_x, axis_y, axis_x.cross(&axis_y)];
+            let normal = random_direction(&mut rng);
+            let thickness = rng.gen_range(0.01..0.3);
+            let offset = normal.dot(&center);
+            for polytope in [
+                ConvexPolytope::oriented_box(&center, &axes, &half_extent),
+                ConvexPolytope::slab(&normal, offset - thickness, offset + thickness),
+            ] {
+                let expected: Vec<usize> = all_points(vertex_points)
+                    .filter(|(_, point)| polytope.contains_point(point))
+                    .map(|(i, _)| i)
+                    .collect();
+                assert_eq!(
+                    sorted(index.query_polytope(&polytope, vertex_points)),
+                    expected
+                );
+            }
+
+            let end = center + random_direction(&mut rng) * rng.gen_range(0.0..1.5);
+            let radius = rng.gen_range(0.02..0.4);
+            let capsule = Capsule {
+                start: center,
+                end,
+                radius,
+            };
+            let cylinder = Cylinder {
+                start: center,
+                end,
+                radius,
+            };
+            let expected: Vec<usize> = all_points(vertex_points)
+                .filter(|(_, point)| segment_distance(&center, &end, point) <= radius)
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(
+                sorted(index.query_capsule(&center, &end, radius, vertex_points)),
+                expected
+            );
+            let expected: Vec<usize> = all_points(vertex_points)
+                .filter(|(_, point)| cylinder.contains_point(point))
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(
+                sorted(index.query_cylinder(&center, &end, radius, vertex_points)),
+                expected
+            );
+            // A cylinder is a capsule without its end caps
+            let capsule_points = sorted(index.query_region(&capsule, vertex_points));
+            assert!(expected
+                .iter()
+                .all(|i| capsule_points.binary_search(i).is_ok()));
+
+            let k = rng.gen_range(1..20);
+            let mut expected: Vec<(usize, f32)> = all_points(vertex_points)
+                .map(|(i, point)| (i, nalgebra_glm::distance(&point, &center)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            expected.truncate(k);
+            assert_eq!(index.knn(&center, k, vertex_points), expected);
+
+            let ray_origin = center
+                + Vec3::new(
+                    rng.gen_range(-3.0..3.0),
+                    rng.gen_range(-3.0..3.0),
+                    rng.gen_range(2.0..3.0),
+                );
+            let ray_dir = (center - ray_origin).normalize();
+            let pick_radius = rng.gen_range(0.01..0.05);
+            let expected = all_points(vertex_points)
+                .filter_map(|(i, point)| {
+                    ray_point_hit(&point, &ray_origin, &ray_dir, pick_radius).map(|t| (i, t))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            // Indexes renormalize `ray_dir`, so distances may differ in the last bit
+            let picked = index.pick(&ray_origin, &ray_dir, pick_radius, vertex_points);
+            assert_eq!(picked.map(|(i, _)| i), expected.map(|(i, _)| i));
+            if let (Some((_, t)), Some((_, expected_t))) = (picked, expected) {
+                assert!((t - expected_t).abs() < 1e-4);
+            }
+
+            // A lasso drawn around `center` as seen from the ray origin, with points on
+            // both sides of the camera
+            let view = nalgebra_glm::look_at(&ray_origin, &center, &Vec3::y());
+            let projection =
+                nalgebra_glm::perspective(4.0 / 3.0, 45.0_f32.to_radians(), 0.1, 100.0);
+            let num_vertices = rng.gen_range(3..12);
+            let lasso = (0..num_vertices)
+                .map(|i| {
+                    let angle = i as f32 / num_vertices as f32 * std::f32::consts::TAU;
+                    nalgebra_glm::Vec2::new(angle.cos(), angle.sin()) * rng.gen_range(0.05..0.8)
+                })
+                .collect();
+            let polygon = ScreenPolygon::new(projection * view, lasso);
+            let expected: Vec<usize> = all_points(vertex_points)
+                .filter(|(_, point)| polygon.contains_point(point))
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(
+                sorted(index.query_region(&polygon, vertex_points)),
+                expected
+            );
+        }
+    }
+
+    fn inserted_octree(vertex_points: &[f32]) -> Octree {
+        let config = OctreeConfig {
+            max_points_per_leaf: 16,
+            ..OctreeConfig::default()
+        };
+        let mut octree = Octree::with_config(Vec3::zeros(), 1.0, config);
+        for point_index in 0..vertex_points.len() / VERTEX_STRIDE {
+            octree.insert(point_index, vertex_points);
+        }
+        octree
+    }
+
+    fn bulk_built_octree(vertex_points: &[f32]) -> Octree {
+        let config = OctreeConfig {
+            max_points_per_leaf: 16,
+            ..OctreeConfig::default()
+        };
+        Octree::build(vertex_points, config).0
+    }
+
+    #[test]
+    fn octree_matches_brute_force_on_uniform_points() {
+        let vertex_points = uniform_points(&mut rand::rngs::StdRng::seed_from_u64(1));
+        check_against_brute_force(&inserted_octree(&vertex_points), &vertex_points, 11);
+        check_against_brute_force(&bulk_built_octree(&vertex_points), &vertex_points, 12);
+    }
+
+    #[test]
+    fn octree_matches_brute_force_on_a_corridor() {
+        let vertex_points = corridor_points(&mut rand::rngs::StdRng::seed_from_u64(2));
+        check_against_brute_force(&inserted_octree(&vertex_points), &vertex_points, 21);
+        check_against_brute_force(&bulk_built_octree(&vertex_points), &vertex_points, 22);
+    }
+
+    #[test]
+    fn kd_tree_matches_brute_force_on_uniform_points() {
+        let vertex_points = uniform_points(&mut rand::rngs::StdRng::seed_from_u64(1));
+        check_against_brute_force(&KdTree::build(&vertex_points), &vertex_points, 31);
+    }
+
+    #[test]
+    fn kd_tree_matches_brute_force_on_a_corridor() {
+        let vertex_points = corridor_points(&mut rand::rngs::StdRng::seed_from_u64(2));
+        check_against_brute_force(&KdTree::build(&vertex_points), &vertex_points, 41);
+    }
+
+    #[test]
+    fn empty_indexes_return_nothing() {
+        let indexes: [Box<dyn SpatialIndex>; 2] = [
+            Box::new(Octree::build(&[], OctreeConfig::default()).0),
+            Box::new(KdTree::build(&[])),
+        ];
+        for index in &indexes {
+            let origin = Vec3::zeros();
+            assert!(index.query_sphere(&origin, 1.0, &[]).is_empty());
+            assert!(index
+                .query_aabb(&-Vec3::repeat(1.0), &Vec3::repeat(1.0), &[])
+                .is_empty());
+            let slab = ConvexPolytope::slab(&Vec3::z(), -1.0, 1.0);
+            assert!(index.query_polytope(&slab, &[]).is_empty());
+            assert!(index.knn(&origin, 3, &[]).is_empty());
+            assert_eq!(index.pick(&origin, &Vec3::z(), 0.1, &[]), None);
+        }
+    }
+}
@@ -1,9 +1,45 @@
-use nalgebra_glm::Vec3;
+use std::{cell::RefCell, rc::Rc};
+
+use nalgebra_glm::{Mat4, Vec2, Vec3};
 use rand::Rng;
 use wasm_bindgen::JsValue;
-use web_sys::{WebGlBuffer, WebGl2RenderingContext};
+use web_sys::{WebGl2RenderingContext, WebGlBuffer};
+
+use crate::cloud_distance::cloud_to_cloud_distances;
+use crate::clustering::NOISE;
+use crate::matrix::unproject;
+use crate::octree::{get_point, Octree, OctreeConfig, VERTEX_STRIDE};
+use crate::region::{Capsule, Cylinder, FilterShape};
+use crate::registration::{icp, transform_points, IcpConfig, IcpMethod, IcpResult};
+use crate::selection::Selection;
+use crate::spatial_index::SpatialIndexKind;
+use crate::worker::LOD_SAMPLES_PER_NODE;
+use crate::worker_pool::{JobError, SpatialWorkers};
+
+/// How the points are colored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Each point's own color.
+    Vertex,
+    /// The point's color lit by a light at the camera, using its estimated normal.
+    Shaded,
+    /// A color per cluster of `VertexData::cluster_ids`, with noise in grey.
+    Cluster,
+    /// Ground in brown and everything else in green, from `VertexData::classes`.
+    Classification,
+}
 
-use crate::octree::Octree;
+impl ColorMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vertex" => Some(ColorMode::Vertex),
+            "shaded" => Some(ColorMode::Shaded),
+            "cluster" => Some(ColorMode::Cluster),
+            "class" => Some(ColorMode::Classification),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Clone)] // Add this line
 pub struct VertexData {
@@ -12,22 +48,62 @@ pub struct VertexData {
     pub axis_vbo: web_sys::WebGlBuffer,
     pub cube_vbo: web_sys::WebGlBuffer,
     pub point_vertices: Vec<f32>,
+    // Empty until the build started by `start_scene_build` comes back
     pub octree: Octree,
+    // The index the spatial workers answer point queries with
+    pub spatial_index_kind: SpatialIndexKind,
+    // Number of indices currently in `point_ebo`
     pub num_points: u32,
+    // Points kept by the filter shape and screen selections, or `None` to draw every point
+    pub selected_points: Option<Selection>,
+    // The selection the filter shape's result is combined with while it's being moved,
+    // or `None` until it next moves
+    pub filter_base: Option<Selection>,
+    // The projection * model-view matrix `point_ebo` was last culled against
+    pub culled_mvp: Option<Mat4>,
+    // Set when points or the octree are changed in place, so the render loop re-uploads
+    // the points and the octree's cubes and reloads the spatial workers
+    pub points_changed: bool,
     pub draggable_point_vbo: web_sys::WebGlBuffer,
     pub sphere_radius: f32,
     pub sphere_vbo: web_sys::WebGlBuffer,
     pub num_sphere_vertices: u32,
+    // Points drawn again, larger and in red, on top of the others
+    pub highlight_ebo: web_sys::WebGlBuffer,
+    pub num_highlighted_points: u32,
+    // Outline of the rectangle or lasso being dragged, refilled every frame
+    pub selection_outline_vbo: web_sys::WebGlBuffer,
+    pub color_mode: ColorMode,
+    // One float per point for the color mode to color by, refilled by `update_scalar_vbo`
+    pub scalar_vbo: web_sys::WebGlBuffer,
+    // The cluster of each point from the last clustering, or `None` if it hasn't been run
+    // since the points last changed
+    pub cluster_ids: Option<Vec<u32>>,
+    // The LAS class of each point from the last ground classification, or `None` if it
+    // hasn't been run since the points last changed
+    pub classes: Option<Vec<u8>>,
+    // A second scan, such as another survey of the same site, drawn over the scene in its
+    // own color. Uploaded to `second_cloud_vbo` by the render loop when it changes
+    pub second_cloud_vertices: Vec<f32>,
+    pub second_cloud_vbo: web_sys::WebGlBuffer,
+    pub second_cloud_changed: bool,
+    // Distance from each point of the second cloud to the nearest scene point, or `None`
+    // if it hasn't been computed since either cloud last changed
+    pub second_cloud_distances: Option<Vec<f32>>,
+    // The distances scaled to [0, 1] for the color ramp, or -1 where there are none
+    pub second_cloud_scalar_vbo: web_sys::WebGlBuffer,
 }
 
 pub fn create_vertex_buffers(
     gl: &WebGl2RenderingContext,
     num_points: u32,
+    spatial_index_kind: SpatialIndexKind,
 ) -> Result<VertexData, JsValue> {
     let axis_vertices = generate_axis_vertices();
     let (point_vertices, point_indices) = generate_point_vertices(num_points);
     let mut cube_vertices: Vec<f32> = Vec::new();
-    let octree = generate_octree(&point_vertices, &mut cube_vertices);
+    let (octree, _) = Octree::build(&[], OctreeConfig::default());
+    octree.get_vertices(&mut cube_vertices);
     let draggable_point_vertex = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0];
     let (sphere_vertices, num_sphere_vertices) = generate_sphere_vertices(&[0.0, 0.0, 0.0], 0.1);
 
@@ -37,20 +113,118 @@ pub fn create_vertex_buffers(
     let cube_buffer = create_cube_vbo(gl, &cube_vertices)?;
     let draggable_point_buffer = create_draggable_point_vbo(gl, &draggable_point_vertex)?;
     let sphere_buffer = create_sphere_vbo(gl, &sphere_vertices)?;
+    let highlight_index_buffer = create_point_ebo(gl, &[])?;
+    let selection_outline_buffer = create_selection_outline_vbo(gl, &[])?;
+    let scalar_buffer = gl.create_buffer().unwrap();
+    let second_cloud_buffer = create_point_vbo(gl, &[])?;
+    let second_cloud_scalar_buffer = gl.create_buffer().unwrap();
 
-    Ok(VertexData {
+    let vertex_data = VertexData {
         point_vbo: point_buffer,
         point_ebo: point_index_buffer,
         axis_vbo: axis_buffer,
         cube_vbo: cube_buffer,
-        point_vertices: point_vertices,
-        octree: octree,
-        num_points: num_points,
+        point_vertices,
+        octree,
+        spatial_index_kind,
+        num_points,
+        selected_points: None,
+        filter_base: None,
+        culled_mvp: None,
+        points_changed: false,
         draggable_point_vbo: draggable_point_buffer,
         sphere_radius: 0.1,
         sphere_vbo: sphere_buffer,
-        num_sphere_vertices: num_sphere_vertices,
-    })
+        num_sphere_vertices,
+        highlight_ebo: highlight_index_buffer,
+        num_highlighted_points: 0,
+        selection_outline_vbo: selection_outline_buffer,
+        color_mode: ColorMode::Vertex,
+        scalar_vbo: scalar_buffer,
+        cluster_ids: None,
+        classes: None,
+        second_cloud_vertices: Vec::new(),
+        second_cloud_vbo: second_cloud_buffer,
+        second_cloud_changed: false,
+        second_cloud_distances: None,
+        second_cloud_scalar_vbo: second_cloud_scalar_buffer,
+    };
+    update_scalar_vbo(gl, &vertex_data);
+    Ok(vertex_data)
+}
+
+/// Moves a point to `position`. The octree is updated in place rather than rebuilt.
+pub fn move_point(
+    vertex_data: &mut VertexData,
+    point_index: usize,
+    position: &Vec3,
+) -> Result<(), String> {
+    if point_index >= vertex_data.point_vertices.len() / VERTEX_STRIDE {
+        return Err(format!("There is no point {}", point_index));
+    }
+    // The octree turns away points it can't place
+    if !position.iter().all(|c| c.is_finite()) {
+        return Err(format!("Invalid position for point {}", point_index));
+    }
+    check_scene_built(vertex_data)?;
+    let old = get_point(&vertex_data.point_vertices, point_index);
+    let offset = point_index * VERTEX_STRIDE;
+    vertex_data.point_vertices[offset..offset + 3].copy_from_slice(position.as_slice());
+    vertex_data
+        .octree
+        .update(point_index, &old, position, &vertex_data.point_vertices);
+
+    vertex_data.culled_mvp = None;
+    vertex_data.points_changed = true;
+    Ok(())
+}
+
+/// Replaces the octree with one read from `bytes`, which must index exactly the current
+/// points.
+pub fn load_octree(vertex_data: &mut VertexData, bytes: &[u8]) -> Result<(), String> {
+    check_scene_built(vertex_data)?;
+    let octree = Octree::from_bytes(bytes)?;
+    let mut point_indices = Vec::new();
+    octree.collect_point_indices(&mut point_indices);
+    point_indices.sort_unstable();
+    let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+    if !point_indices.into_iter().eq(0..num_points) {
+        return Err("The octree was built over different points".to_string());
+    }
+
+    vertex_data.octree = octree;
+    vertex_data.culled_mvp = None;
+    vertex_data.points_changed = true;
+    Ok(())
+}
+
+// Edits made while a build is in flight would be lost when it replaces the points
+fn check_scene_built(vertex_data: &VertexData) -> Result<(), String> {
+    if vertex_data.octree.num_points() != vertex_data.point_vertices.len() / VERTEX_STRIDE {
+        return Err("The octree is still being built".to_string());
+    }
+    Ok(())
+}
+
+/// Uploads the points and the octree's cubes after they were edited in place, first
+/// rebuilding the LOD samples, which the edits only thinned out.
+pub fn update_edited_vbos(gl: &WebGl2RenderingContext, vertex_data: &mut VertexData) {
+    vertex_data
+        .octree
+        .build_lod(LOD_SAMPLES_PER_NODE, &vertex_data.point_vertices);
+    update_point_vbo(gl, &vertex_data.point_vbo, &vertex_data.point_vertices);
+
+    let mut cube_vertices = Vec::new();
+    vertex_data.octree.get_vertices(&mut cube_vertices);
+    gl.bind_buffer(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        Some(&vertex_data.cube_vbo),
+    );
+    gl.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &js_sys::Float32Array::from(cube_vertices.as_slice()),
+        WebGl2RenderingContext::STATIC_DRAW,
+    );
 }
 
 fn generate_axis_vertices() -> Vec<f32> {
@@ -73,27 +247,190 @@ fn generate_point_vertices(num_points: u32) -> (Vec<f32>, Vec<u32>) {
         let x = rng.gen_range(-1.0..=1.0);
         let y = rng.gen_range(-1.0..=1.0);
         let z = rng.gen_range(-1.0..=1.0);
-        point_vertices.extend_from_slice(&[x, y, z, 0.0, 0.0, 0.0]); // Black color for points
-        point_indices.push(i as u32); // Account for stride
+        // Black color for points, with no normal yet
+        point_vertices.extend_from_slice(&[x, y, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        point_indices.push(i); // Account for stride
     }
 
     (point_vertices, point_indices)
 }
 
-fn generate_octree(point_vertices: &[f32], cube_vertices: &mut Vec<f32>) -> Octree {
-    let mut octree = Octree::new(Vec3::new(0.0, 0.0, 0.0), 2.0);
+/// Sends the current points off to be indexed. When the build comes back, the points
+/// are uploaded again in the octree's Morton order, the octree and its cubes replace the
+/// current ones, the workers are loaded with the new points, and an `octree-built` event
+/// is fired on the document.
+pub fn start_scene_build(
+    gl: Rc<WebGl2RenderingContext>,
+    vertex_data_ref: Rc<RefCell<VertexData>>,
+    spatial_workers: Rc<SpatialWorkers>,
+) {
+    let point_vertices = vertex_data_ref.borrow().point_vertices.clone();
+    let workers = spatial_workers.clone();
+    spatial_workers.build_scene(point_vertices, move |result| {
+        let (octree, point_vertices) = match result {
+            Ok(built) => built,
+            // The workers failed, so the build is started again to run inline
+            Err(JobError::Failed(_)) if workers.runs_inline() => {
+                start_scene_build(gl, vertex_data_ref, workers);
+                return;
+            }
+            Err(err) => {
+                err.report();
+                return;
+            }
+        };
+        let mut vertex_data = vertex_data_ref.borrow_mut();
+        if let Err(err) = apply_scene_build(&gl, &mut vertex_data, octree, point_vertices) {
+            web_sys::console::error_1(&err);
+            return;
+        }
+        workers.load_shards(&vertex_data.point_vertices, vertex_data.spatial_index_kind);
+        drop(vertex_data);
+
+        let document = web_sys::window().and_then(|window| window.document());
+        if let (Some(document), Ok(event)) = (document, web_sys::Event::new("octree-built")) {
+            let _ = document.dispatch_event(&event);
+        }
+    });
+}
+
+fn apply_scene_build(
+    gl: &WebGl2RenderingContext,
+    vertex_data: &mut VertexData,
+    octree: Octree,
+    point_vertices: Vec<f32>,
+) -> Result<(), JsValue> {
+    let mut cube_vertices: Vec<f32> = Vec::new();
+    octree.get_vertices(&mut cube_vertices);
+
+    vertex_data.point_vbo = create_point_vbo(gl, &point_vertices)?;
+    vertex_data.cube_vbo = create_cube_vbo(gl, &cube_vertices)?;
+    vertex_data.point_vertices = point_vertices;
+    vertex_data.octree = octree;
+    vertex_data.selected_points = None;
+    vertex_data.filter_base = None;
+    vertex_data.num_highlighted_points = 0;
+    vertex_data.culled_mvp = None;
+    vertex_data.cluster_ids = None;
+    vertex_data.classes = None;
+    vertex_data.second_cloud_distances = None;
+    vertex_data.second_cloud_changed = true;
+    update_scalar_vbo(gl, vertex_data);
+    Ok(())
+}
+
+/// Drops the `deleted` points from the scene. The octree is emptied until the caller
+/// starts a new build with `start_scene_build`.
+pub fn delete_points(vertex_data: &mut VertexData, deleted: &Selection) {
+    let kept = deleted.invert();
+    let mut point_vertices = Vec::with_capacity(kept.len() * VERTEX_STRIDE);
+    for point_index in kept.indices() {
+        let offset = point_index as usize * VERTEX_STRIDE;
+        point_vertices
+            .extend_from_slice(&vertex_data.point_vertices[offset..offset + VERTEX_STRIDE]);
+    }
+
+    vertex_data.point_vertices = point_vertices;
+    vertex_data.octree = Octree::build(&[], OctreeConfig::default()).0;
+    vertex_data.num_points = 0;
+    vertex_data.selected_points = None;
+    vertex_data.filter_base = None;
+    vertex_data.num_highlighted_points = 0;
+    vertex_data.culled_mvp = None;
+    vertex_data.cluster_ids = None;
+    vertex_data.classes = None;
+    vertex_data.second_cloud_distances = None;
+    vertex_data.second_cloud_changed = true;
+}
+
+/// Replaces the second cloud with `vertices`, in the point buffer's layout.
+pub fn set_second_cloud(vertex_data: &mut VertexData, vertices: Vec<f32>) {
+    vertex_data.second_cloud_vertices = vertices;
+    vertex_data.second_cloud_distances = None;
+    vertex_data.second_cloud_changed = true;
+}
+
+/// Aligns the second cloud to the scene by ICP and moves it into place.
+pub fn align_second_cloud(
+    vertex_data: &mut VertexData,
+    method: IcpMethod,
+    config: &IcpConfig,
+) -> Result<IcpResult, String> {
+    check_scene_built(vertex_data)?;
+    if vertex_data.second_cloud_vertices.is_empty() {
+        return Err("There is no second cloud to align".to_string());
+    }
+
+    let result = icp(
+        &vertex_data.octree,
+        &vertex_data.point_vertices,
+        &vertex_data.second_cloud_vertices,
+        method,
+        config,
+    );
+    transform_points(
+        &mut vertex_data.second_cloud_vertices,
+        result.transform_matrix(),
+    );
+    vertex_data.second_cloud_distances = None;
+    vertex_data.second_cloud_changed = true;
+    Ok(result)
+}
 
-    for i in (0..point_vertices.len()).step_by(6) {
-        let x = point_vertices[i];
-        let y = point_vertices[i + 1];
-        let z = point_vertices[i + 2];
-        let point = Vec3::new(x, y, z);
-        let point_index = i / 6;
-        octree.insert(point_index, point);
+/// Measures how far each point of the second cloud is from the nearest scene point, which
+/// colors the second cloud by distance.
+pub fn compute_second_cloud_distances(vertex_data: &mut VertexData) -> Result<&[f32], String> {
+    check_scene_built(vertex_data)?;
+    if vertex_data.second_cloud_vertices.is_empty() {
+        return Err("There is no second cloud to compare".to_string());
     }
 
-    octree.get_vertices(cube_vertices);
-    octree
+    let distances = cloud_to_cloud_distances(
+        &vertex_data.octree,
+        &vertex_data.point_vertices,
+        &vertex_data.second_cloud_vertices,
+    );
+    vertex_data.second_cloud_changed = true;
+    Ok(vertex_data.second_cloud_distances.insert(distances))
+}
+
+/// Uploads the second cloud and its distances, scaled to [0, 1] by the largest, for the
+/// color ramp.
+pub fn update_second_cloud_vbos(gl: &WebGl2RenderingContext, vertex_data: &VertexData) {
+    update_point_vbo(
+        gl,
+        &vertex_data.second_cloud_vbo,
+        &vertex_data.second_cloud_vertices,
+    );
+
+    let num_points = vertex_data.second_cloud_vertices.len() / VERTEX_STRIDE;
+    let scalars: Vec<f32> = match vertex_data.second_cloud_distances.as_ref() {
+        Some(distances) => {
+            let max_distance = distances
+                .iter()
+                .copied()
+                .filter(|distance| distance.is_finite())
+                .fold(0.0, f32::max);
+            distances
+                .iter()
+                .map(|&distance| match distance.is_finite() {
+                    true if max_distance > 0.0 => distance / max_distance,
+                    true => 0.0,
+                    false => -1.0,
+                })
+                .collect()
+        }
+        None => vec![-1.0; num_points],
+    };
+    gl.bind_buffer(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        Some(&vertex_data.second_cloud_scalar_vbo),
+    );
+    gl.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &js_sys::Float32Array::from(scalars.as_slice()),
+        WebGl2RenderingContext::STATIC_DRAW,
+    );
 }
 
 pub fn generate_sphere_vertices(center: &[f32; 3], radius: f32) -> (Vec<f32>, u32) {
@@ -126,6 +463,85 @@ pub fn generate_sphere_vertices(center: &[f32; 3], radius: f32) -> (Vec<f32>, u3
     (sphere_vertices, ((stacks + 1) * (sectors + 1)) as u32)
 }
 
+/// Generates the outline of the filter shape, laid out like `generate_sphere_vertices`.
+pub fn generate_filter_shape_vertices(shape: &FilterShape) -> (Vec<f32>, u32) {
+    let rings = 160;
+    let sectors = 160;
+    let quarter_turn = std::f32::consts::FRAC_PI_2;
+
+    // Each ring is an offset along the axis from `start` and a radius around it
+    let (start, end, profile): (Vec3, Vec3, Vec<(f32, f32)>) = match *shape {
+        FilterShape::Sphere { center, radius } => {
+            return generate_sphere_vertices(&[center.x, center.y, center.z], radius)
+        }
+        FilterShape::Capsule(Capsule { start, end, radius }) => {
+            let length = nalgebra_glm::distance(&start, &end);
+            let half_rings = rings / 2;
+            let start_cap = (0..=half_rings).map(|i| {
+                let angle = -quarter_turn + quarter_turn * i as f32 / half_rings as f32;
+                (radius * angle.sin(), radius * angle.cos())
+            });
+            let end_cap = (0..=half_rings).map(|i| {
+                let angle = quarter_turn * i as f32 / half_rings as f32;
+                (length + radius * angle.sin(), radius * angle.cos())
+            });
+            (start, end, start_cap.chain(end_cap).collect())
+        }
+        FilterShape::Cylinder(Cylinder { start, end, radius }) => {
+            let length = nalgebra_glm::distance(&start, &end);
+            let cap_rings = rings / 8;
+            let side_rings = rings - 2 * cap_rings;
+            let start_cap = (0..=cap_rings).map(|i| (0.0, radius * i as f32 / cap_rings as f32));
+            let side = (0..=side_rings).map(|i| (length * i as f32 / side_rings as f32, radius));
+            let end_cap = (0..=cap_rings)
+                .map(|i| (length, radius * (cap_rings - i) as f32 / cap_rings as f32));
+            (start, end, start_cap.chain(side).chain(end_cap).collect())
+        }
+    };
+
+    // An orthonormal frame around the axis; a zero-length axis is taken to point along z
+    let axis = (end - start)
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vec3::z);
+    let helper = if axis.x.abs() < 0.9 {
+        Vec3::x()
+    } else {
+        Vec3::y()
+    };
+    let u = axis.cross(&helper).normalize();
+    let v = axis.cross(&u);
+
+    let mut shape_vertices: Vec<f32> = Vec::new();
+    for &(along_axis, ring_radius) in &profile {
+        for j in 0..sectors + 1 {
+            let sector_angle = (j as f32) * 2.0 * std::f32::consts::PI / (sectors as f32);
+            let point = start
+                + axis * along_axis
+                + (u * sector_angle.cos() + v * sector_angle.sin()) * ring_radius;
+            shape_vertices.extend_from_slice(&[point.x, point.y, point.z, 0.0, 1.0, 0.0]);
+        }
+    }
+
+    let num_vertices = (profile.len() * (sectors + 1)) as u32;
+    (shape_vertices, num_vertices)
+}
+
+/// Places the corners of a selection outline, given in normalized device coordinates,
+/// just behind the near plane so the outline is drawn over the points. Returns them as
+/// interleaved orange vertices for a line loop.
+pub fn generate_selection_outline_vertices(
+    outline: &[Vec2],
+    mv_matrix: &Mat4,
+    p_matrix: &Mat4,
+) -> Vec<f32> {
+    let mut vertices = Vec::with_capacity(outline.len() * 6);
+    for corner in outline {
+        let position = unproject(&Vec3::new(corner.x, corner.y, -0.99), mv_matrix, p_matrix);
+        vertices.extend_from_slice(&[position.x, position.y, position.z, 1.0, 0.5, 0.0]);
+    }
+    vertices
+}
+
 fn create_axis_vbo(gl: &WebGl2RenderingContext, vertices: &[f32]) -> Result<WebGlBuffer, JsValue> {
     let buffer = gl.create_buffer().unwrap();
     gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
@@ -139,16 +555,58 @@ fn create_axis_vbo(gl: &WebGl2RenderingContext, vertices: &[f32]) -> Result<WebG
 
 fn create_point_vbo(gl: &WebGl2RenderingContext, vertices: &[f32]) -> Result<WebGlBuffer, JsValue> {
     let buffer = gl.create_buffer().unwrap();
-    gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+    update_point_vbo(gl, &buffer, vertices);
+    Ok(buffer)
+}
+
+pub fn update_point_vbo(gl: &WebGl2RenderingContext, buffer: &WebGlBuffer, vertices: &[f32]) {
+    gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
     gl.buffer_data_with_array_buffer_view(
         WebGl2RenderingContext::ARRAY_BUFFER,
         &js_sys::Float32Array::from(vertices),
         WebGl2RenderingContext::STATIC_DRAW,
     );
-    Ok(buffer)
 }
 
-pub fn create_point_ebo(gl: &WebGl2RenderingContext, indices: &[u32]) -> Result<WebGlBuffer, JsValue> {
+/// Fills `scalar_vbo` with the value each point is colored by in the current color mode,
+/// or -1 for points without one.
+pub fn update_scalar_vbo(gl: &WebGl2RenderingContext, vertex_data: &VertexData) {
+    let num_points = vertex_data.point_vertices.len() / VERTEX_STRIDE;
+    let scalars: Vec<f32> = match vertex_data.color_mode {
+        ColorMode::Cluster => match vertex_data.cluster_ids.as_ref() {
+            Some(cluster_ids) => cluster_ids
+                .iter()
+                .map(|&cluster_id| {
+                    if cluster_id == NOISE {
+                        -1.0
+                    } else {
+                        cluster_id as f32
+                    }
+                })
+                .collect(),
+            None => vec![-1.0; num_points],
+        },
+        ColorMode::Classification => match vertex_data.classes.as_ref() {
+            Some(classes) => classes.iter().map(|&class| class as f32).collect(),
+            None => vec![-1.0; num_points],
+        },
+        ColorMode::Vertex | ColorMode::Shaded => vec![-1.0; num_points],
+    };
+    gl.bind_buffer(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        Some(&vertex_data.scalar_vbo),
+    );
+    gl.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &js_sys::Float32Array::from(scalars.as_slice()),
+        WebGl2RenderingContext::STATIC_DRAW,
+    );
+}
+
+pub fn create_point_ebo(
+    gl: &WebGl2RenderingContext,
+    indices: &[u32],
+) -> Result<WebGlBuffer, JsValue> {
     let buffer = gl.create_buffer().unwrap();
     gl.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(&buffer));
     gl.buffer_data_with_array_buffer_view(
@@ -159,6 +617,17 @@ pub fn create_point_ebo(gl: &WebGl2RenderingContext, indices: &[u32]) -> Result<
     Ok(buffer)
 }
 
+/// Replaces the contents of an existing point EBO, so it can be refilled every frame
+/// without allocating a new GPU buffer.
+pub fn update_point_ebo(gl: &WebGl2RenderingContext, buffer: &WebGlBuffer, indices: &[u32]) {
+    gl.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(buffer));
+    gl.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+        &js_sys::Uint32Array::from(indices),
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+    );
+}
+
 fn create_cube_vbo(gl: &WebGl2RenderingContext, vertices: &[f32]) -> Result<WebGlBuffer, JsValue> {
     let buffer = gl.create_buffer().unwrap();
     gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
@@ -197,28 +666,24 @@ pub fn create_sphere_vbo(
     Ok(buffer)
 }
 
-pub fn set_vertex_attribute_pointer(
+pub fn create_selection_outline_vbo(
+    gl: &WebGl2RenderingContext,
+    vertices: &[f32],
+) -> Result<WebGlBuffer, JsValue> {
+    let buffer = gl.create_buffer().unwrap();
+    update_selection_outline_vbo(gl, &buffer, vertices);
+    Ok(buffer)
+}
+
+pub fn update_selection_outline_vbo(
     gl: &WebGl2RenderingContext,
-    position_attribute_location: u32,
-    color_attribute_location: u32,
+    buffer: &WebGlBuffer,
+    vertices: &[f32],
 ) {
-    gl.vertex_attrib_pointer_with_i32(
-        position_attribute_location,
-        3,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        6 * std::mem::size_of::<f32>() as i32,
-        0,
-    );
-    gl.enable_vertex_attrib_array(position_attribute_location);
-
-    gl.vertex_attrib_pointer_with_i32(
-        color_attribute_location,
-        3,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        6 * std::mem::size_of::<f32>() as i32,
-        3 * std::mem::size_of::<f32>() as i32,
-    );
-    gl.enable_vertex_attrib_array(color_attribute_location);
+    gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
+    gl.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &js_sys::Float32Array::from(vertices),
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+    );
 }
@@ -0,0 +1,318 @@
+// worker.rs
+//
+// The spatial work done by `worker.js`. Each worker loads this same module and keeps one
+// shard of the points with its own spatial index; the main thread broadcasts queries to
+// every shard and merges the replies. Analyses that need each point's neighbours from the
+// whole scene, like the outlier filters, normal estimation and clustering, instead run on
+// one worker over all the points.
+// See `worker_pool.rs` for the main thread's side.
+use std::cell::RefCell;
+
+use nalgebra_glm::{Mat4, Vec2, Vec3};
+use wasm_bindgen::prelude::*;
+
+use crate::clustering::ClusterMethod;
+use crate::frustum::Plane;
+use crate::kdtree::KdTree;
+use crate::normals::estimate_normals;
+use crate::octree::{reorder_points, Octree, OctreeConfig};
+use crate::outliers::OutlierFilter;
+use crate::polytope::ConvexPolytope;
+use crate::region::{FilterShape, Region};
+use crate::screen_selection::ScreenPolygon;
+use crate::selection::Selection;
+use crate::spatial_index::{SpatialIndex, SpatialIndexKind};
+
+/// Points kept by each interior octree node for drawing it at reduced detail.
+pub const LOD_SAMPLES_PER_NODE: usize = 512;
+
+/// Bulk-builds the render octree, with LOD samples, and returns it together with the
+/// vertices in the tree's Morton order, so that points drawn together sit together in
+/// the VBO.
+pub fn build_scene(point_vertices: &[f32]) -> (Octree, Vec<f32>) {
+    let (mut octree, order) = Octree::build(point_vertices, OctreeConfig::default());
+    let point_vertices = reorder_points(point_vertices, &order);
+    octree.renumber_points(&order);
+    octree.build_lod(LOD_SAMPLES_PER_NODE, &point_vertices);
+    (octree, point_vertices)
+}
+
+/// Indexes `point_vertices` with an octree and finds the outliers `filter` picks out.
+pub fn find_outliers(point_vertices: &[f32], filter: &OutlierFilter) -> Selection {
+    let (octree, _) = Octree::build(point_vertices, OctreeConfig::default());
+    filter.find_outliers(&octree, point_vertices)
+}
+
+/// Indexes `point_vertices` with an octree and estimates each point's normal from its `k`
+/// nearest neighbours, facing `viewpoint`.
+pub fn find_normals(point_vertices: &[f32], k: usize, viewpoint: &Vec3) -> Vec<Vec3> {
+    let (octree, _) = Octree::build(point_vertices, OctreeConfig::default());
+    estimate_normals(&octree, point_vertices, k, viewpoint)
+}
+
+/// Indexes `point_vertices` with an octree and returns the cluster id `method` gives each
+/// point.
+pub fn find_clusters(point_vertices: &[f32], method: &ClusterMethod) -> Vec<u32> {
+    let (octree, _) = Octree::build(point_vertices, OctreeConfig::default());
+    method.cluster(&octree, point_vertices)
+}
+
+/// A contiguous run of the scene's points with a spatial index of its own. Query results
+/// are translated back to indices into the whole scene.
+pub struct Shard {
+    first_point: usize,
+    vertex_points: Vec<f32>,
+    index: Box<dyn SpatialIndex>,
+}
+
+impl Shard {
+    pub fn build(first_point: usize, vertex_points: Vec<f32>, kind: SpatialIndexKind) -> Self {
+        let index: Box<dyn SpatialIndex> = match kind {
+            // The shard's points are already in Morton order, so the build order is unused
+            SpatialIndexKind::Octree => {
+                Box::new(Octree::build(&vertex_points, OctreeConfig::default()).0)
+            }
+            SpatialIndexKind::KdTree => Box::new(KdTree::build(&vertex_points)),
+        };
+        Shard {
+            first_point,
+            vertex_points,
+            index,
+        }
+    }
+
+    pub fn query_shape(&self, shape: &FilterShape) -> Vec<u32> {
+        match shape {
+            FilterShape::Sphere { center, radius } => {
+                let point_indices = self
+                    .index
+                    .query_sphere(center, *radius, &self.vertex_points);
+                self.to_scene_indices(point_indices)
+            }
+            FilterShape::Capsule(capsule) => {
+                let point_indices = self.index.query_capsule(
+                    &capsule.start,
+                    &capsule.end,
+                    capsule.radius,
+                    &self.vertex_points,
+                );
+                self.to_scene_indices(point_indices)
+            }
+            FilterShape::Cylinder(cylinder) => {
+                let point_indices = self.index.query_cylinder(
+                    &cylinder.start,
+                    &cylinder.end,
+                    cylinder.radius,
+                    &self.vertex_points,
+                );
+                self.to_scene_indices(point_indices)
+            }
+        }
+    }
+
+    pub fn query_region(&self, region: &dyn Region) -> Vec<u32> {
+        let point_indices = self.index.query_region(region, &self.vertex_points);
+        self.to_scene_indices(point_indices)
+    }
+
+    fn to_scene_indices(&self, point_indices: Vec<usize>) -> Vec<u32> {
+        point_indices
+            .into_iter()
+            .map(|i| (self.first_point + i) as u32)
+            .collect()
+    }
+
+    pub fn query_aabb(&self, min: &Vec3, max: &Vec3) -> Vec<u32> {
+        let point_indices = self.index.query_aabb(min, max, &self.vertex_points);
+        self.to_scene_indices(point_indices)
+    }
+
+    pub fn query_polytope(&self, polytope: &ConvexPolytope) -> Vec<u32> {
+        let point_indices = self.index.query_polytope(polytope, &self.vertex_points);
+        self.to_scene_indices(point_indices)
+    }
+
+    pub fn knn(&self, query: &Vec3, k: usize) -> Vec<(usize, f32)> {
+        self.index
+            .knn(query, k, &self.vertex_points)
+            .into_iter()
+            .map(|(i, distance)| (self.first_point + i, distance))
+            .collect()
+    }
+
+    pub fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+    ) -> Option<(usize, f32)> {
+        self.index
+            .pick(ray_origin, ray_dir, pick_radius, &self.vertex_points)
+            .map(|(i, along_ray)| (self.first_point + i, along_ray))
+    }
+}
+
+thread_local! {
+    // The shard this worker answers queries for
+    static SHARD: RefCell<Option<Shard>> = const { RefCell::new(None) };
+}
+
+fn with_shard<T>(f: impl FnOnce(&Shard) -> T) -> Result<T, JsValue> {
+    SHARD.with(|shard| {
+        let shard = shard.borrow();
+        let shard = shard.as_ref().ok_or("No shard has been loaded")?;
+        Ok(f(shard))
+    })
+}
+
+/// Builds the render octree for `point_vertices`, reorders them in place into the tree's
+/// Morton order, and returns the tree serialized with `Octree::to_bytes`.
+#[wasm_bindgen]
+pub fn worker_build_scene(point_vertices: &mut [f32]) -> Vec<u8> {
+    let (octree, ordered_vertices) = build_scene(point_vertices);
+    point_vertices.copy_from_slice(&ordered_vertices);
+    octree.to_bytes()
+}
+
+/// Returns the indices of the outliers among `point_vertices` found by the `OutlierFilter`
+/// described by the remaining arguments, as passed to `OutlierFilter::from_parts`.
+#[wasm_bindgen]
+pub fn worker_find_outliers(
+    point_vertices: &[f32],
+    filter: &str,
+    num_neighbours: usize,
+    value: f32,
+) -> Result<Vec<u32>, JsValue> {
+    let filter = OutlierFilter::from_parts(filter, num_neighbours, value)
+        .ok_or_else(|| JsValue::from_str("Invalid outlier filter"))?;
+    Ok(find_outliers(point_vertices, &filter).indices().collect())
+}
+
+/// Returns the estimated normal of each of `point_vertices` as flat x, y, z triples.
+#[wasm_bindgen]
+pub fn worker_estimate_normals(point_vertices: &[f32], k: usize, viewpoint: &[f32]) -> Vec<f32> {
+    let viewpoint = Vec3::from_column_slice(viewpoint);
+    find_normals(point_vertices, k, &viewpoint)
+        .iter()
+        .flat_map(|normal| [normal.x, normal.y, normal.z])
+        .collect()
+}
+
+/// Returns the cluster id of each of `point_vertices` under the `ClusterMethod` described
+/// by the remaining arguments, as passed to `ClusterMethod::from_parts`.
+#[wasm_bindgen]
+pub fn worker_find_clusters(
+    point_vertices: &[f32],
+    method: &str,
+    distance: f32,
+    min_points: usize,
+) -> Result<Vec<u32>, JsValue> {
+    let method = ClusterMethod::from_parts(method, distance, min_points)
+        .ok_or_else(|| JsValue::from_str("Invalid clustering method"))?;
+    Ok(find_clusters(point_vertices, &method))
+}
+
+/// Replaces this worker's shard with `vertex_points`, which start at scene point
+/// `first_point`.
+#[wasm_bindgen]
+pub fn worker_load_shard(
+    first_point: u32,
+    vertex_points: Vec<f32>,
+    spatial_index: &str,
+) -> Result<(), JsValue> {
+    let kind = SpatialIndexKind::from_name(spatial_index)
+        .ok_or_else(|| JsValue::from_str("Invalid spatial index"))?;
+    let shard = Shard::build(first_point as usize, vertex_points, kind);
+    SHARD.with(|current| *current.borrow_mut() = Some(shard));
+    Ok(())
+}
+
+/// Returns the indices of the shard's points inside the `FilterShape` described by the
+/// arguments, as passed to `FilterShape::from_parts`.
+#[wasm_bindgen]
+pub fn worker_query_shape(
+    shape: &str,
+    start: &[f32],
+    end: &[f32],
+    radius: f32,
+) -> Result<Vec<u32>, JsValue> {
+    let start = Vec3::from_column_slice(start);
+    let end = Vec3::from_column_slice(end);
+    let shape = FilterShape::from_parts(shape, start, end, radius)
+        .ok_or_else(|| JsValue::from_str("Invalid filter shape"))?;
+    with_shard(|shard| shard.query_shape(&shape))
+}
+
+#[wasm_bindgen]
+pub fn worker_query_aabb(min: &[f32], max: &[f32]) -> Result<Vec<u32>, JsValue> {
+    let min = Vec3::from_column_slice(min);
+    let max = Vec3::from_column_slice(max);
+    with_shard(|shard| shard.query_aabb(&min, &max))
+}
+
+/// Returns the indices of the shard's points inside the convex region bounded by `planes`,
+/// given as `[normal_x, normal_y, normal_z, distance, ...]`.
+#[wasm_bindgen]
+pub fn worker_query_polytope(planes: &[f32]) -> Result<Vec<u32>, JsValue> {
+    let polytope = ConvexPolytope::new(
+        planes
+            .chunks_exact(4)
+            .map(|plane| Plane::new(Vec3::new(plane[0], plane[1], plane[2]), plane[3]))
+            .collect(),
+    );
+    with_shard(|shard| shard.query_polytope(&polytope))
+}
+
+/// Returns the shard's `k` nearest points as `[point_index, distance, ...]`, nearest
+/// first.
+#[wasm_bindgen]
+pub fn worker_knn(query: &[f32], k: usize) -> Result<Vec<f64>, JsValue> {
+    let query = Vec3::from_column_slice(query);
+    with_shard(|shard| {
+        shard
+            .knn(&query, k)
+            .into_iter()
+            .flat_map(|(point_index, distance)| [point_index as f64, distance as f64])
+            .collect()
+    })
+}
+
+/// Returns the indices of the shard's points inside a `ScreenPolygon`. `mvp` is the
+/// column-major matrix the polygon was drawn over and `vertices` its corners as x, y
+/// pairs.
+#[wasm_bindgen]
+pub fn worker_query_screen_polygon(mvp: &[f32], vertices: &[f32]) -> Result<Vec<u32>, JsValue> {
+    let polygon = screen_polygon_from_parts(mvp, vertices)
+        .ok_or_else(|| JsValue::from_str("Invalid screen polygon"))?;
+    with_shard(|shard| shard.query_region(&polygon))
+}
+
+// Rebuilds a `ScreenPolygon` from the flat arrays it is posted to the workers as
+fn screen_polygon_from_parts(mvp: &[f32], vertices: &[f32]) -> Option<ScreenPolygon> {
+    if mvp.len() != 16 || vertices.len() % 2 != 0 {
+        return None;
+    }
+    let vertices = vertices
+        .chunks_exact(2)
+        .map(|xy| Vec2::new(xy[0], xy[1]))
+        .collect();
+    Some(ScreenPolygon::new(Mat4::from_column_slice(mvp), vertices))
+}
+
+/// Returns `[point_index, distance_along_ray]` for the shard's nearest hit, or an empty
+/// array if the ray misses every point.
+#[wasm_bindgen]
+pub fn worker_pick(
+    ray_origin: &[f32],
+    ray_dir: &[f32],
+    pick_radius: f32,
+) -> Result<Vec<f64>, JsValue> {
+    let ray_origin = Vec3::from_column_slice(ray_origin);
+    let ray_dir = Vec3::from_column_slice(ray_dir);
+    with_shard(
+        |shard| match shard.pick(&ray_origin, &ray_dir, pick_radius) {
+            Some((point_index, along_ray)) => vec![point_index as f64, along_ray as f64],
+            None => Vec::new(),
+        },
+    )
+}
@@ -0,0 +1,1053 @@
+// worker_pool.rs
+use std::{cell::RefCell, collections::HashMap, ops::Range, rc::Rc};
+
+use js_sys::{Array, Float32Array, Float64Array, Object, Reflect, Uint32Array, Uint8Array};
+use nalgebra_glm::Vec3;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{MessageEvent, Worker, WorkerOptions, WorkerType};
+
+use crate::clustering::ClusterMethod;
+use crate::octree::{Octree, VERTEX_STRIDE};
+use crate::outliers::OutlierFilter;
+use crate::polytope::ConvexPolytope;
+use crate::region::FilterShape;
+use crate::screen_selection::ScreenPolygon;
+use crate::selection::Selection;
+use crate::spatial_index::SpatialIndexKind;
+use crate::worker::{build_scene, find_clusters, find_normals, find_outliers, Shard};
+
+const WORKER_SCRIPT: &str = "./worker.js";
+// Upper bound on the pool size, whatever core count the browser reports
+const MAX_WORKERS: usize = 8;
+
+/// Why a job finished without a result.
+#[derive(Debug)]
+pub enum JobError {
+    /// The data the job ran over was replaced, by a newer build or a `load_shards`, before
+    /// it finished.
+    Superseded,
+    /// A worker reported an error.
+    Failed(JsValue),
+}
+
+impl JobError {
+    /// Logs failures to the console. Superseded jobs are expected whenever the points change
+    /// while a job runs, and are not logged.
+    pub fn report(&self) {
+        if let JobError::Failed(err) = self {
+            web_sys::console::error_1(err);
+        }
+    }
+}
+
+impl From<JobError> for JsValue {
+    fn from(err: JobError) -> Self {
+        match err {
+            JobError::Superseded => {
+                JsValue::from_str("The points were replaced before the job finished")
+            }
+            JobError::Failed(err) => err,
+        }
+    }
+}
+
+pub type JobResult<T> = Result<T, JobError>;
+
+/// Runs octree builds and point queries away from the main thread, handing each result to
+/// the callback it was started with. Queries are split across shards of the scene's
+/// points, one per worker, and the partial results merged.
+///
+/// Every job's callback is called exactly once, with an error if a worker failed or the
+/// data the job ran over was replaced before it finished.
+pub enum SpatialWorkers {
+    /// Runs jobs on web workers, or inline like `Inline` once a worker has failed to load
+    /// or to run.
+    Pool(WorkerPool),
+    /// Runs every job on the calling thread before returning, for native builds and for
+    /// browsers that cannot start module workers.
+    Inline(InlineShards),
+}
+
+// Where jobs are run
+enum Runner<'a> {
+    Pool(&'a WorkerPool),
+    Inline(&'a InlineShards),
+}
+
+impl SpatialWorkers {
+    /// Starts one worker per core, up to `MAX_WORKERS`, or falls back to running inline if
+    /// they can't be started.
+    pub fn start() -> Self {
+        let num_workers = web_sys::window()
+            .map(|window| window.navigator().hardware_concurrency() as usize)
+            .unwrap_or(1)
+            .clamp(1, MAX_WORKERS);
+        match WorkerPool::new(num_workers) {
+            Ok(pool) => SpatialWorkers::Pool(pool),
+            Err(err) => {
+                web_sys::console::warn_2(
+                    &JsValue::from_str("Could not start workers, running queries inline:"),
+                    &err,
+                );
+                SpatialWorkers::inline(1)
+            }
+        }
+    }
+
+    pub fn inline(num_shards: usize) -> Self {
+        SpatialWorkers::Inline(InlineShards::new(num_shards))
+    }
+
+    fn runner(&self) -> Runner<'_> {
+        match self {
+            SpatialWorkers::Pool(pool) if pool.state.borrow().failed => {
+                Runner::Inline(&pool.fallback)
+            }
+            SpatialWorkers::Pool(pool) => Runner::Pool(pool),
+            SpatialWorkers::Inline(inline) => Runner::Inline(inline),
+        }
+    }
+
+    /// Whether jobs run on the calling thread, either by choice or because the workers
+    /// failed.
+    pub fn runs_inline(&self) -> bool {
+        matches!(self.runner(), Runner::Inline(_))
+    }
+
+    /// Whether the shards have to be loaded again with `load_shards`, because the workers
+    /// holding them failed.
+    pub fn shards_lost(&self) -> bool {
+        match self {
+            SpatialWorkers::Pool(pool) => pool.state.borrow().shards_lost,
+            SpatialWorkers::Inline(_) => false,
+        }
+    }
+
+    /// Builds the render octree for `point_vertices`, as `worker::build_scene` does, and
+    /// passes it on with the reordered vertices.
+    pub fn build_scene(
+        &self,
+        point_vertices: Vec<f32>,
+        on_done: impl FnOnce(JobResult<(Octree, Vec<f32>)>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.build_scene(point_vertices, on_done),
+            Runner::Inline(_) => on_done(Ok(build_scene(&point_vertices))),
+        }
+    }
+
+    /// Runs `filter` over all of `point_vertices` and passes on the outliers it finds.
+    pub fn find_outliers(
+        &self,
+        point_vertices: Vec<f32>,
+        filter: &OutlierFilter,
+        on_done: impl FnOnce(JobResult<Selection>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.find_outliers(point_vertices, filter, on_done),
+            Runner::Inline(_) => on_done(Ok(find_outliers(&point_vertices, filter))),
+        }
+    }
+
+    /// Estimates the normal of each of `point_vertices` from its `k` nearest neighbours,
+    /// facing `viewpoint`, and passes them on in point order.
+    pub fn estimate_normals(
+        &self,
+        point_vertices: Vec<f32>,
+        k: usize,
+        viewpoint: &Vec3,
+        on_done: impl FnOnce(JobResult<Vec<Vec3>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.estimate_normals(point_vertices, k, viewpoint, on_done),
+            Runner::Inline(_) => on_done(Ok(find_normals(&point_vertices, k, viewpoint))),
+        }
+    }
+
+    /// Clusters `point_vertices` with `method` and passes on each point's cluster id.
+    pub fn find_clusters(
+        &self,
+        point_vertices: Vec<f32>,
+        method: &ClusterMethod,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.find_clusters(point_vertices, method, on_done),
+            Runner::Inline(_) => on_done(Ok(find_clusters(&point_vertices, method))),
+        }
+    }
+
+    /// Splits `point_vertices` into shards and indexes each with `kind`. Queries started
+    /// before this answer for the previous points, and finish with `JobError::Superseded`.
+    pub fn load_shards(&self, point_vertices: &[f32], kind: SpatialIndexKind) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.load_shards(point_vertices, kind),
+            Runner::Inline(inline) => inline.load_shards(point_vertices, kind),
+        }
+        if let SpatialWorkers::Pool(pool) = self {
+            pool.state.borrow_mut().shards_lost = false;
+        }
+    }
+
+    /// Finds the indices of all points inside `shape`, in no particular order.
+    pub fn query_shape(
+        &self,
+        shape: &FilterShape,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.query_shape(shape, on_done),
+            Runner::Inline(inline) => on_done(Ok(inline
+                .shards
+                .borrow()
+                .iter()
+                .flat_map(|shard| shard.query_shape(shape))
+                .collect())),
+        }
+    }
+
+    /// Finds the indices of all points inside the axis-aligned box from `min` to `max`, in
+    /// no particular order.
+    pub fn query_aabb(
+        &self,
+        min: &Vec3,
+        max: &Vec3,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.query_aabb(min, max, on_done),
+            Runner::Inline(inline) => on_done(Ok(inline
+                .shards
+                .borrow()
+                .iter()
+                .flat_map(|shard| shard.query_aabb(min, max))
+                .collect())),
+        }
+    }
+
+    /// Finds the indices of all points inside the convex region, in no particular order.
+    pub fn query_polytope(
+        &self,
+        polytope: &ConvexPolytope,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.query_polytope(polytope, on_done),
+            Runner::Inline(inline) => on_done(Ok(inline
+                .shards
+                .borrow()
+                .iter()
+                .flat_map(|shard| shard.query_polytope(polytope))
+                .collect())),
+        }
+    }
+
+    /// Finds the indices of all points drawn inside `polygon`, in no particular order.
+    pub fn query_screen_polygon(
+        &self,
+        polygon: &ScreenPolygon,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.query_screen_polygon(polygon, on_done),
+            Runner::Inline(inline) => on_done(Ok(inline
+                .shards
+                .borrow()
+                .iter()
+                .flat_map(|shard| shard.query_region(polygon))
+                .collect())),
+        }
+    }
+
+    /// Finds the `k` points nearest to `query` as `(point_index, distance)`, nearest first.
+    pub fn knn(
+        &self,
+        query: &Vec3,
+        k: usize,
+        on_done: impl FnOnce(JobResult<Vec<(usize, f32)>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.knn(query, k, on_done),
+            Runner::Inline(inline) => on_done(Ok(nearest_k(
+                inline
+                    .shards
+                    .borrow()
+                    .iter()
+                    .flat_map(|shard| shard.knn(query, k))
+                    .collect(),
+                k,
+            ))),
+        }
+    }
+
+    /// Finds the point nearest to `ray_origin` along the ray among those within
+    /// `pick_radius` of it, as `(point_index, distance_along_ray)`.
+    pub fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        on_done: impl FnOnce(JobResult<Option<(usize, f32)>>) + 'static,
+    ) {
+        match self.runner() {
+            Runner::Pool(pool) => pool.pick(ray_origin, ray_dir, pick_radius, on_done),
+            Runner::Inline(inline) => on_done(Ok(nearest_hit(
+                inline
+                    .shards
+                    .borrow()
+                    .iter()
+                    .map(|shard| shard.pick(ray_origin, ray_dir, pick_radius)),
+            ))),
+        }
+    }
+}
+
+pub struct InlineShards {
+    num_shards: usize,
+    shards: RefCell<Vec<Shard>>,
+}
+
+impl InlineShards {
+    fn new(num_shards: usize) -> Self {
+        InlineShards {
+            num_shards,
+            shards: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn load_shards(&self, point_vertices: &[f32], kind: SpatialIndexKind) {
+        let num_points = point_vertices.len() / VERTEX_STRIDE;
+        *self.shards.borrow_mut() = shard_ranges(num_points, self.num_shards)
+            .into_iter()
+            .map(|range| {
+                Shard::build(
+                    range.start,
+                    shard_vertices(point_vertices, &range).to_vec(),
+                    kind,
+                )
+            })
+            .collect();
+    }
+}
+
+pub struct WorkerPool {
+    workers: Vec<Worker>,
+    state: Rc<RefCell<PoolState>>,
+    // Runs the jobs once the workers have failed
+    fallback: InlineShards,
+    // Shared by every worker, and kept alive for as long as they can reply
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    next_job_id: u32,
+    next_worker: usize,
+    build_generation: u32,
+    shard_generation: u32,
+    num_shards: usize,
+    pending: HashMap<u32, PendingJob>,
+    // Set for good once a worker fails to load or to run
+    failed: bool,
+    // Set when the workers fail with shards loaded, until they are loaded again inline
+    shards_lost: bool,
+}
+
+// The data a job's replies were computed from, checked before its callback runs
+#[derive(Clone, Copy)]
+enum JobScope {
+    Build(u32),
+    Shards(u32),
+    // A job over all of the scene's points, stale once they are rebuilt or reloaded
+    Scene { build: u32, shards: u32 },
+}
+
+struct PendingJob {
+    scope: JobScope,
+    remaining_replies: usize,
+    replies: Vec<JsValue>,
+    on_done: Box<dyn FnOnce(JobResult<Vec<JsValue>>)>,
+}
+
+impl PoolState {
+    fn is_current(&self, scope: JobScope) -> bool {
+        match scope {
+            JobScope::Build(generation) => generation == self.build_generation,
+            JobScope::Shards(generation) => generation == self.shard_generation,
+            JobScope::Scene { build, shards } => {
+                build == self.build_generation && shards == self.shard_generation
+            }
+        }
+    }
+}
+
+impl WorkerPool {
+    fn new(num_workers: usize) -> Result<Self, JsValue> {
+        let options = WorkerOptions::new();
+        options.set_type(WorkerType::Module);
+        let workers = (0..num_workers)
+            .map(|_| Worker::new_with_options(WORKER_SCRIPT, &options))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let state = Rc::new(RefCell::new(PoolState::default()));
+        let on_message = {
+            let state = state.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                handle_reply(&state, event.data());
+            }) as Box<dyn FnMut(_)>)
+        };
+        // `Worker::new_with_options` only reports failures to start. A script or module
+        // that fails to load, or a worker that dies, is reported here
+        let on_error = {
+            let state = state.clone();
+            let workers = workers.clone();
+            Closure::wrap(Box::new(move |error: JsValue| {
+                for worker in &workers {
+                    worker.terminate();
+                }
+                fail_pool(&state, error);
+            }) as Box<dyn FnMut(_)>)
+        };
+        for worker in &workers {
+            worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            worker.set_onmessageerror(Some(on_error.as_ref().unchecked_ref()));
+        }
+
+        Ok(WorkerPool {
+            workers,
+            state,
+            fallback: InlineShards::new(1),
+            _on_message: on_message,
+            _on_error: on_error,
+        })
+    }
+
+    fn build_scene(
+        &self,
+        point_vertices: Vec<f32>,
+        on_done: impl FnOnce(JobResult<(Octree, Vec<f32>)>) + 'static,
+    ) {
+        let (generation, worker) = {
+            let mut state = self.state.borrow_mut();
+            state.build_generation += 1;
+            state.next_worker = (state.next_worker + 1) % self.workers.len();
+            (state.build_generation, state.next_worker)
+        };
+        let id = self.add_job(JobScope::Build(generation), 1, move |result| {
+            on_done(result.and_then(|replies| {
+                let reply = &replies[0];
+                let point_vertices = get_field::<Float32Array>(reply, "vertices").to_vec();
+                let octree_bytes = get_field::<Uint8Array>(reply, "octree").to_vec();
+                let octree = Octree::from_bytes(&octree_bytes)
+                    .map_err(|err| JobError::Failed(JsValue::from_str(&err)))?;
+                Ok((octree, point_vertices))
+            }))
+        });
+
+        let vertices = Float32Array::from(point_vertices.as_slice());
+        let message = job_message(id, "build");
+        set_field(&message, "vertices", &vertices);
+        self.post(worker, &message, &[vertices.buffer().into()]);
+    }
+
+    fn find_outliers(
+        &self,
+        point_vertices: Vec<f32>,
+        filter: &OutlierFilter,
+        on_done: impl FnOnce(JobResult<Selection>) + 'static,
+    ) {
+        let num_points = point_vertices.len() / VERTEX_STRIDE;
+        let (name, num_neighbours, value) = filter.parts();
+        self.run_scene_job(
+            "find-outliers",
+            &point_vertices,
+            |message| {
+                set_field(message, "filter", &JsValue::from_str(name));
+                set_field(
+                    message,
+                    "numNeighbours",
+                    &JsValue::from(num_neighbours as u32),
+                );
+                set_field(message, "value", &JsValue::from(value));
+            },
+            move |result| {
+                on_done(result.map(|reply| {
+                    let indices = get_field::<Uint32Array>(&reply, "indices").to_vec();
+                    Selection::from_indices(num_points, indices)
+                }))
+            },
+        );
+    }
+
+    fn estimate_normals(
+        &self,
+        point_vertices: Vec<f32>,
+        k: usize,
+        viewpoint: &Vec3,
+        on_done: impl FnOnce(JobResult<Vec<Vec3>>) + 'static,
+    ) {
+        let viewpoint = Float32Array::from(viewpoint.as_slice());
+        self.run_scene_job(
+            "estimate-normals",
+            &point_vertices,
+            |message| {
+                set_field(message, "k", &JsValue::from(k as u32));
+                set_field(message, "viewpoint", &viewpoint);
+            },
+            move |result| {
+                on_done(result.map(|reply| {
+                    let normals = get_field::<Float32Array>(&reply, "normals").to_vec();
+                    normals
+                        .chunks_exact(3)
+                        .map(Vec3::from_column_slice)
+                        .collect()
+                }))
+            },
+        );
+    }
+
+    fn find_clusters(
+        &self,
+        point_vertices: Vec<f32>,
+        method: &ClusterMethod,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        let (name, distance, min_points) = method.parts();
+        self.run_scene_job(
+            "find-clusters",
+            &point_vertices,
+            |message| {
+                set_field(message, "method", &JsValue::from_str(name));
+                set_field(message, "distance", &JsValue::from(distance));
+                set_field(message, "minPoints", &JsValue::from(min_points as u32));
+            },
+            move |result| {
+                on_done(result.map(|reply| get_field::<Uint32Array>(&reply, "clusterIds").to_vec()))
+            },
+        );
+    }
+
+    fn load_shards(&self, point_vertices: &[f32], kind: SpatialIndexKind) {
+        let num_points = point_vertices.len() / VERTEX_STRIDE;
+        let ranges = shard_ranges(num_points, self.workers.len());
+        let generation = {
+            let mut state = self.state.borrow_mut();
+            state.shard_generation += 1;
+            state.num_shards = ranges.len();
+            state.shard_generation
+        };
+        let id = self.add_job(JobScope::Shards(generation), ranges.len(), |result| {
+            if let Err(err) = result {
+                err.report();
+            }
+        });
+
+        for (worker, range) in ranges.iter().enumerate() {
+            let vertices = Float32Array::from(shard_vertices(point_vertices, range));
+            let message = job_message(id, "load");
+            set_field(&message, "firstPoint", &JsValue::from(range.start as u32));
+            set_field(&message, "vertices", &vertices);
+            set_field(&message, "spatialIndex", &JsValue::from_str(kind.name()));
+            self.post(worker, &message, &[vertices.buffer().into()]);
+        }
+    }
+
+    fn query_shape(
+        &self,
+        shape: &FilterShape,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        let (name, start, end, radius) = shape.parts();
+        let start = Float32Array::from(start.as_slice());
+        let end = Float32Array::from(end.as_slice());
+        self.broadcast(
+            "query-shape",
+            |message| {
+                set_field(message, "shape", &JsValue::from_str(name));
+                set_field(message, "start", &start);
+                set_field(message, "end", &end);
+                set_field(message, "radius", &JsValue::from(radius));
+            },
+            move |result| on_done(result.map(|replies| merged_indices(&replies))),
+        );
+    }
+
+    fn query_screen_polygon(
+        &self,
+        polygon: &ScreenPolygon,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        let mvp = Float32Array::from(polygon.mvp.as_slice());
+        let vertices: Vec<f32> = polygon.vertices.iter().flat_map(|v| [v.x, v.y]).collect();
+        let vertices = Float32Array::from(vertices.as_slice());
+        self.broadcast(
+            "query-screen",
+            |message| {
+                set_field(message, "mvp", &mvp);
+                set_field(message, "vertices", &vertices);
+            },
+            move |result| on_done(result.map(|replies| merged_indices(&replies))),
+        );
+    }
+
+    fn query_aabb(
+        &self,
+        min: &Vec3,
+        max: &Vec3,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        let min = Float32Array::from(min.as_slice());
+        let max = Float32Array::from(max.as_slice());
+        self.broadcast(
+            "query-aabb",
+            |message| {
+                set_field(message, "min", &min);
+                set_field(message, "max", &max);
+            },
+            move |result| on_done(result.map(|replies| merged_indices(&replies))),
+        );
+    }
+
+    fn query_polytope(
+        &self,
+        polytope: &ConvexPolytope,
+        on_done: impl FnOnce(JobResult<Vec<u32>>) + 'static,
+    ) {
+        let planes: Vec<f32> = polytope
+            .planes
+            .iter()
+            .flat_map(|plane| {
+                [
+                    plane.normal.x,
+                    plane.normal.y,
+                    plane.normal.z,
+                    plane.distance,
+                ]
+            })
+            .collect();
+        let planes = Float32Array::from(planes.as_slice());
+        self.broadcast(
+            "query-polytope",
+            |message| set_field(message, "planes", &planes),
+            move |result| on_done(result.map(|replies| merged_indices(&replies))),
+        );
+    }
+
+    fn knn(
+        &self,
+        query: &Vec3,
+        k: usize,
+        on_done: impl FnOnce(JobResult<Vec<(usize, f32)>>) + 'static,
+    ) {
+        let query = Float32Array::from(query.as_slice());
+        self.broadcast(
+            "knn",
+            |message| {
+                set_field(message, "query", &query);
+                set_field(message, "k", &JsValue::from(k as u32));
+            },
+            move |result| {
+                on_done(result.map(|replies| {
+                    let neighbours = replies
+                        .iter()
+                        .flat_map(|reply| {
+                            let neighbours =
+                                get_field::<Float64Array>(reply, "neighbours").to_vec();
+                            neighbours
+                                .chunks_exact(2)
+                                .map(|pair| (pair[0] as usize, pair[1] as f32))
+                                .collect::<Vec<_>>()
+                        })
+                        .collect();
+                    nearest_k(neighbours, k)
+                }))
+            },
+        );
+    }
+
+    fn pick(
+        &self,
+        ray_origin: &Vec3,
+        ray_dir: &Vec3,
+        pick_radius: f32,
+        on_done: impl FnOnce(JobResult<Option<(usize, f32)>>) + 'static,
+    ) {
+        let ray_origin = Float32Array::from(ray_origin.as_slice());
+        let ray_dir = Float32Array::from(ray_dir.as_slice());
+        self.broadcast(
+            "pick",
+            |message| {
+                set_field(message, "rayOrigin", &ray_origin);
+                set_field(message, "rayDir", &ray_dir);
+                set_field(message, "pickRadius", &JsValue::from(pick_radius));
+            },
+            move |result| {
+                on_done(result.map(|replies| {
+                    nearest_hit(replies.iter().map(|reply| {
+                        let hit = get_field::<Float64Array>(reply, "hit").to_vec();
+                        (hit.len() == 2).then(|| (hit[0] as usize, hit[1] as f32))
+                    }))
+                }))
+            },
+        );
+    }
+
+    // Sends a query to every loaded shard and calls `on_done` with all of their replies
+    fn broadcast(
+        &self,
+        kind: &str,
+        fill_message: impl Fn(&Object),
+        on_done: impl FnOnce(JobResult<Vec<JsValue>>) + 'static,
+    ) {
+        let (generation, num_shards) = {
+            let state = self.state.borrow();
+            (state.shard_generation, state.num_shards)
+        };
+        if num_shards == 0 {
+            on_done(Ok(Vec::new()));
+            return;
+        }
+
+        let id = self.add_job(JobScope::Shards(generation), num_shards, on_done);
+        for worker in 0..num_shards {
+            let message = job_message(id, kind);
+            fill_message(&message);
+            self.post(worker, &message, &[]);
+        }
+    }
+
+    // Sends a job over all of `point_vertices` to the next worker in turn
+    fn run_scene_job(
+        &self,
+        kind: &str,
+        point_vertices: &[f32],
+        fill_message: impl Fn(&Object),
+        on_done: impl FnOnce(JobResult<JsValue>) + 'static,
+    ) {
+        let (scope, worker) = {
+            let mut state = self.state.borrow_mut();
+            state.next_worker = (state.next_worker + 1) % self.workers.len();
+            let scope = JobScope::Scene {
+                build: state.build_generation,
+                shards: state.shard_generation,
+            };
+            (scope, state.next_worker)
+        };
+        let id = self.add_job(scope, 1, move |result| {
+            on_done(result.map(|mut replies| replies.remove(0)))
+        });
+
+        let vertices = Float32Array::from(point_vertices);
+        let message = job_message(id, kind);
+        set_field(&message, "vertices", &vertices);
+        fill_message(&message);
+        self.post(worker, &message, &[vertices.buffer().into()]);
+    }
+
+    fn add_job(
+        &self,
+        scope: JobScope,
+        num_replies: usize,
+        on_done: impl FnOnce(JobResult<Vec<JsValue>>) + 'static,
+    ) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_job_id;
+        state.next_job_id = state.next_job_id.wrapping_add(1);
+        state.pending.insert(
+            id,
+            PendingJob {
+                scope,
+                remaining_replies: num_replies,
+                replies: Vec::with_capacity(num_replies),
+                on_done: Box::new(on_done),
+            },
+        );
+        id
+    }
+
+    // Buffers in `transfer` are moved to the worker rather than copied
+    fn post(&self, worker: usize, message: &Object, transfer: &[JsValue]) {
+        let transfer: Array = transfer.iter().collect();
+        if let Err(err) = self.workers[worker].post_message_with_transfer(message, &transfer) {
+            web_sys::console::error_1(&err);
+        }
+    }
+}
+
+fn handle_reply(state: &RefCell<PoolState>, reply: JsValue) {
+    let id = get_field::<JsValue>(&reply, "id").as_f64().unwrap_or(-1.0) as u32;
+    let error = get_field::<JsValue>(&reply, "error");
+
+    let (on_done, result) = {
+        let mut state = state.borrow_mut();
+        let Some(job) = state.pending.get_mut(&id) else {
+            return;
+        };
+        // One shard's error fails the whole job, and the other shards' replies are ignored
+        if error.is_undefined() {
+            job.replies.push(reply);
+            job.remaining_replies -= 1;
+            if job.remaining_replies > 0 {
+                return;
+            }
+        }
+        let job = state.pending.remove(&id).unwrap();
+        let result = if !error.is_undefined() {
+            Err(JobError::Failed(error))
+        } else if !state.is_current(job.scope) {
+            Err(JobError::Superseded)
+        } else {
+            Ok(job.replies)
+        };
+        (job.on_done, result)
+    };
+
+    // Called with the pool unborrowed, since callbacks often start new jobs
+    on_done(result);
+}
+
+// Fails every pending job, and has the pool run jobs inline from then on. A failed worker
+// may never reply, and may have lost its shard.
+fn fail_pool(state: &RefCell<PoolState>, error: JsValue) {
+    let failed_jobs: Vec<PendingJob> = {
+        let mut state = state.borrow_mut();
+        if !state.failed {
+            web_sys::console::warn_2(
+                &JsValue::from_str("The workers failed, running queries inline:"),
+                &error,
+            );
+            state.failed = true;
+            state.shards_lost = state.num_shards > 0;
+        }
+        state.pending.drain().map(|(_, job)| job).collect()
+    };
+
+    // Called with the pool unborrowed, as in `handle_reply`
+    for job in failed_jobs {
+        (job.on_done)(Err(JobError::Failed(error.clone())));
+    }
+}
+
+fn job_message(id: u32, kind: &str) -> Object {
+    let message = Object::new();
+    set_field(&message, "id", &JsValue::from(id));
+    set_field(&message, "kind", &JsValue::from_str(kind));
+    message
+}
+
+fn set_field(object: &Object, key: &str, value: &JsValue) {
+    Reflect::set(object, &JsValue::from_str(key), value).unwrap();
+}
+
+fn get_field<T: JsCast>(object: &JsValue, key: &str) -> T {
+    Reflect::get(object, &JsValue::from_str(key))
+        .unwrap_or(JsValue::UNDEFINED)
+        .unchecked_into()
+}
+
+// Splits the points into at most `num_shards` contiguous runs of nearly equal length
+fn shard_ranges(num_points: usize, num_shards: usize) -> Vec<Range<usize>> {
+    let shard_len = num_points.div_ceil(num_shards.max(1)).max(1);
+    (0..num_points)
+        .step_by(shard_len)
+        .map(|start| start..(start + shard_len).min(num_points))
+        .collect()
+}
+
+fn shard_vertices<'a>(point_vertices: &'a [f32], range: &Range<usize>) -> &'a [f32] {
+    &point_vertices[range.start * VERTEX_STRIDE..range.end * VERTEX_STRIDE]
+}
+
+// Each shard's `k` nearest, merged into the `k` nearest overall
+fn nearest_k(mut neighbours: Vec<(usize, f32)>, k: usize) -> Vec<(usize, f32)> {
+    neighbours.sort_by(|a, b| a.1.total_cmp(&b.1));
+    neighbours.truncate(k);
+    neighbours
+}
+
+fn merged_indices(replies: &[JsValue]) -> Vec<u32> {
+    replies
+        .iter()
+        .flat_map(|reply| get_field::<Uint32Array>(reply, "indices").to_vec())
+        .collect()
+}
+
+fn nearest_hit(hits: impl Iterator<Item = Option<(usize, f32)>>) -> Option<(usize, f32)> {
+    hits.flatten().min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::region::Capsule;
+    use crate::spatial_index::SpatialIndex;
+
+    fn random_points(num_points: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let mut vertex_points = Vec::with_capacity(num_points * VERTEX_STRIDE);
+        for _ in 0..num_points {
+            vertex_points.extend_from_slice(&[
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ]);
+        }
+        vertex_points
+    }
+
+    // Inline jobs finish before returning, so their results can be read straight back
+    fn built_scene(workers: &SpatialWorkers, vertex_points: Vec<f32>) -> (Octree, Vec<f32>) {
+        let result = Rc::new(RefCell::new(None));
+        let result_ref = result.clone();
+        workers.build_scene(vertex_points, move |built| {
+            *result_ref.borrow_mut() = Some(built.unwrap());
+        });
+        let result = result.borrow_mut().take();
+        result.unwrap()
+    }
+
+    #[test]
+    fn shard_ranges_cover_every_point_once() {
+        assert_eq!(shard_ranges(10, 3), vec![0..4, 4..8, 8..10]);
+        assert_eq!(shard_ranges(2, 4), vec![0..1, 1..2]);
+        assert!(shard_ranges(0, 4).is_empty());
+    }
+
+    #[test]
+    fn inline_build_reorders_every_point() {
+        let vertex_points = random_points(1000);
+        let workers = SpatialWorkers::inline(1);
+
+        let (octree, ordered_points) = built_scene(&workers, vertex_points.clone());
+
+        assert_eq!(octree.num_points(), 1000);
+        let mut before: Vec<u32> = vertex_points.iter().map(|v| v.to_bits()).collect();
+        let mut after: Vec<u32> = ordered_points.iter().map(|v| v.to_bits()).collect();
+        before.sort_unstable();
+        after.sort_unstable();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn inline_outlier_job_runs_over_the_whole_scene() {
+        let mut vertex_points = random_points(2000);
+        vertex_points.extend_from_slice(&[5.0, 5.0, 5.0]);
+        vertex_points.extend_from_slice(&[0.0; VERTEX_STRIDE - 3]);
+        // Split into shards, though the filter still sees each point's neighbours in the others
+        let workers = SpatialWorkers::inline(3);
+        workers.load_shards(&vertex_points, SpatialIndexKind::Octree);
+
+        let outliers = Rc::new(RefCell::new(None));
+        let outliers_ref = outliers.clone();
+        let filter = OutlierFilter::Radius {
+            radius: 0.3,
+            min_neighbours: 2,
+        };
+        workers.find_outliers(vertex_points, &filter, move |found| {
+            *outliers_ref.borrow_mut() = Some(found.unwrap());
+        });
+
+        let outliers = outliers.borrow_mut().take().unwrap();
+        assert_eq!(outliers.indices().collect::<Vec<_>>(), vec![2000]);
+    }
+
+    #[test]
+    fn sharded_queries_match_the_render_octree() {
+        let workers = SpatialWorkers::inline(3);
+        let (octree, vertex_points) = built_scene(&workers, random_points(2000));
+
+        for kind in [SpatialIndexKind::Octree, SpatialIndexKind::KdTree] {
+            workers.load_shards(&vertex_points, kind);
+
+            let center = Vec3::new(0.2, -0.1, 0.3);
+            let mut expected = octree.query_sphere(&center, 0.4, &vertex_points);
+            expected.sort_unstable();
+            let point_indices = Rc::new(RefCell::new(Vec::new()));
+            let point_indices_ref = point_indices.clone();
+            let sphere = FilterShape::Sphere {
+                center,
+                radius: 0.4,
+            };
+            workers.query_shape(&sphere, move |found| {
+                *point_indices_ref.borrow_mut() = found.unwrap();
+            });
+            let mut point_indices: Vec<usize> =
+                point_indices.borrow().iter().map(|&i| i as usize).collect();
+            point_indices.sort_unstable();
+            assert_eq!(point_indices, expected);
+
+            let end = Vec3::new(-0.3, 0.5, 0.0);
+            let mut expected = octree.query_capsule(&center, &end, 0.2, &vertex_points);
+            expected.sort_unstable();
+            let point_indices = Rc::new(RefCell::new(Vec::new()));
+            let point_indices_ref = point_indices.clone();
+            let capsule = FilterShape::Capsule(Capsule {
+                start: center,
+                end,
+                radius: 0.2,
+            });
+            workers.query_shape(&capsule, move |found| {
+                *point_indices_ref.borrow_mut() = found.unwrap();
+            });
+            let mut point_indices: Vec<usize> =
+                point_indices.borrow().iter().map(|&i| i as usize).collect();
+            point_indices.sort_unstable();
+            assert_eq!(point_indices, expected);
+
+            let (min, max) = (Vec3::new(-0.5, 0.0, -0.2), Vec3::new(0.3, 0.6, 0.9));
+            let mut expected = octree.query_aabb(&min, &max, &vertex_points);
+            expected.sort_unstable();
+            let point_indices = Rc::new(RefCell::new(Vec::new()));
+            let point_indices_ref = point_indices.clone();
+            workers.query_aabb(&min, &max, move |found| {
+                *point_indices_ref.borrow_mut() = found.unwrap();
+            });
+            let mut point_indices: Vec<usize> =
+                point_indices.borrow().iter().map(|&i| i as usize).collect();
+            point_indices.sort_unstable();
+            assert_eq!(point_indices, expected);
+
+            let slab = ConvexPolytope::slab(&Vec3::new(1.0, 2.0, -1.0), -0.2, 0.1);
+            let mut expected = octree.query_polytope(&slab, &vertex_points);
+            expected.sort_unstable();
+            let point_indices = Rc::new(RefCell::new(Vec::new()));
+            let point_indices_ref = point_indices.clone();
+            workers.query_polytope(&slab, move |found| {
+                *point_indices_ref.borrow_mut() = found.unwrap();
+            });
+            let mut point_indices: Vec<usize> =
+                point_indices.borrow().iter().map(|&i| i as usize).collect();
+            point_indices.sort_unstable();
+            assert_eq!(point_indices, expected);
+
+            // Each shard's nearest points are merged into the nearest overall
+            let expected = octree.knn(&center, 10, &vertex_points);
+            let neighbours = Rc::new(RefCell::new(Vec::new()));
+            let neighbours_ref = neighbours.clone();
+            workers.knn(&center, 10, move |found| {
+                *neighbours_ref.borrow_mut() = found.unwrap();
+            });
+            assert_eq!(*neighbours.borrow(), expected);
+
+            let ray_origin = Vec3::new(0.0, 0.0, 3.0);
+            let ray_dir = Vec3::new(0.05, 0.02, -1.0);
+            let expected = octree.pick(&ray_origin, &ray_dir, 0.05, &vertex_points);
+            let hit = Rc::new(RefCell::new(None));
+            let hit_ref = hit.clone();
+            workers.pick(&ray_origin, &ray_dir, 0.05, move |found| {
+                *hit_ref.borrow_mut() = found.unwrap();
+            });
+            assert_eq!(hit.borrow().map(|(i, _)| i), expected.map(|(i, _)| i));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// worker.js
+//
+// Runs the spatial jobs posted by `WorkerPool` in src/worker_pool.rs. Every message has an
+// `id` and a `kind`, and every reply carries the same `id`, plus an `error` if the job
+// failed.
+import init, {
+    worker_build_scene,
+    worker_load_shard,
+    worker_query_shape,
+    worker_query_aabb,
+    worker_query_polytope,
+    worker_knn,
+    worker_query_screen_polygon,
+    worker_pick,
+    worker_find_outliers,
+    worker_estimate_normals,
+    worker_find_clusters,
+} from "./pkg/neara.js";
+
+const ready = init();
+
+self.onmessage = async (event) => {
+    await ready;
+    const { id, kind } = event.data;
+    try {
+        switch (kind) {
+            case "build": {
+                // Reordered in place into the octree's Morton order
+                const vertices = event.data.vertices;
+                const octree = worker_build_scene(vertices);
+                self.postMessage({ id, vertices, octree }, [vertices.buffer, octree.buffer]);
+                break;
+            }
+            case "load":
+                worker_load_shard(event.data.firstPoint, event.data.vertices, event.data.spatialIndex);
+                self.postMessage({ id });
+                break;
+            case "query-shape": {
+                const { shape, start, end, radius } = event.data;
+                const indices = worker_query_shape(shape, start, end, radius);
+                self.postMessage({ id, indices }, [indices.buffer]);
+                break;
+            }
+            case "query-aabb": {
+                const indices = worker_query_aabb(event.data.min, event.data.max);
+                self.postMessage({ id, indices }, [indices.buffer]);
+                break;
+            }
+            case "query-polytope": {
+                const indices = worker_query_polytope(event.data.planes);
+                self.postMessage({ id, indices }, [indices.buffer]);
+                break;
+            }
+            case "knn": {
+                const neighbours = worker_knn(event.data.query, event.data.k);
+                self.postMessage({ id, neighbours }, [neighbours.buffer]);
+                break;
+            }
+            case "query-screen": {
+                const indices = worker_query_screen_polygon(event.data.mvp, event.data.vertices);
+                self.postMessage({ id, indices }, [indices.buffer]);
+                break;
+            }
+            case "pick": {
+                const hit = worker_pick(event.data.rayOrigin, event.data.rayDir, event.data.pickRadius);
+                self.postMessage({ id, hit });
+                break;
+            }
+            case "find-outliers": {
+                const { vertices, filter, numNeighbours, value } = event.data;
+                const indices = worker_find_outliers(vertices, filter, numNeighbours, value);
+                self.postMessage({ id, indices }, [indices.buffer]);
+                break;
+            }
+            case "estimate-normals": {
+                const { vertices, k, viewpoint } = event.data;
+                const normals = worker_estimate_normals(vertices, k, viewpoint);
+                self.postMessage({ id, normals }, [normals.buffer]);
+                break;
+            }
+            case "find-clusters": {
+                const { vertices, method, distance, minPoints } = event.data;
+                const clusterIds = worker_find_clusters(vertices, method, distance, minPoints);
+                self.postMessage({ id, clusterIds }, [clusterIds.buffer]);
+                break;
+            }
+            default:
+                throw new Error(`Unknown job kind: ${kind}`);
+        }
+    } catch (error) {
+        self.postMessage({ id, error: String(error) });
+    }
+};